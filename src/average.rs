@@ -0,0 +1,60 @@
+use chrono::prelude::*;
+
+/// Average of a value weighted by how long it was in effect
+#[derive(Debug, Clone)]
+pub(crate) struct TimeWeighted {
+    // integral of the value over time since `from`, up to `last_dt`
+    integral: f64,
+    from: NaiveDateTime,
+    last_dt: NaiveDateTime,
+    pub(crate) last: f64,
+}
+
+impl TimeWeighted {
+    /// Nothing is in effect before the first value
+    pub(crate) fn new(dt: NaiveDateTime, value: f64) -> Self {
+        Self {
+            integral: 0.,
+            from: dt,
+            last_dt: dt,
+            last: value,
+        }
+    }
+
+    /// Starts over at `bar_start`, the last value stays in effect until `dt`
+    pub(crate) fn carry(&self, bar_start: NaiveDateTime, dt: NaiveDateTime, value: f64) -> Self {
+        Self {
+            integral: self.last * seconds(dt - bar_start),
+            from: bar_start,
+            last_dt: dt,
+            last: value,
+        }
+    }
+
+    pub(crate) fn push(&mut self, dt: NaiveDateTime, value: f64) {
+        self.integral += self.last * seconds(dt - self.last_dt);
+        self.last_dt = dt;
+        self.last = value;
+    }
+
+    pub(crate) fn last_dt(&self) -> NaiveDateTime {
+        self.last_dt
+    }
+
+    /// Average up to `end`, the last value if no time has passed
+    pub(crate) fn average(&self, end: NaiveDateTime) -> f64 {
+        let total = seconds(end - self.from);
+        if total > 0. {
+            (self.integral + self.last * seconds(end - self.last_dt)) / total
+        } else {
+            self.last
+        }
+    }
+}
+
+fn seconds(duration: chrono::Duration) -> f64 {
+    match duration.num_microseconds() {
+        Some(us) => us as f64 / 1e6,
+        None => duration.num_seconds() as f64,
+    }
+}
@@ -0,0 +1,813 @@
+//! Columnar binary format for bar sequences
+//!
+//! ```text
+//! header   "METABARS" u16 version
+//! block*   u32 count, u8 price encoding, then five length-prefixed columns:
+//!          times, open, high, low, close
+//! index    per block: i64 first bar_start, i64 last bar_start, u64 offset, u32 length, u32 count
+//! trailer  u64 index offset, "MBINDEX1"
+//! ```
+//!
+//! Integers are little-endian, times are nanoseconds since the epoch. The time column
+//! holds the first `bar_start` and length, then for each next bar the gap since the
+//! previous `next_bar_dt` and the change in length as zigzag varints, so contiguous
+//! bars of a fixed period take two bytes.
+//!
+//! When every price of a block is an exact decimal of at most 9 places, the encoding
+//! byte is the number of places and prices are stored as integer points: the open as
+//! the change from the previous close, the rest as the distance from the open, in
+//! zigzag varints. Otherwise it is 0xff and each price column is XOR-compressed against
+//! its previous value, as in Facebook's Gorilla.
+
+use crate::Bar;
+use chrono::prelude::*;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+const MAGIC: &[u8; 8] = b"METABARS";
+const INDEX_MAGIC: &[u8; 8] = b"MBINDEX1";
+const VERSION: u16 = 1;
+const HEADER_LEN: u64 = 10;
+const TRAILER_LEN: u64 = 16;
+const ENTRY_LEN: u64 = 32;
+// price encoding of a block that isn't decimal
+const XOR: u8 = 0xff;
+
+#[derive(Debug)]
+pub enum BarFileError {
+    Io(io::Error),
+    /// Not a bar file, an unsupported version or a damaged one
+    Format(&'static str),
+    /// `bar_start` isn't after the one of the previous bar
+    OutOfOrder(NaiveDateTime),
+    /// A time that doesn't fit in nanoseconds since the epoch
+    TimeRange(NaiveDateTime),
+}
+
+impl fmt::Display for BarFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BarFileError::Io(err) => write!(f, "{}", err),
+            BarFileError::Format(reason) => write!(f, "invalid bar file: {}", reason),
+            BarFileError::OutOfOrder(dt) => write!(f, "bar at {} is out of order", dt),
+            BarFileError::TimeRange(dt) => write!(f, "{} is out of range", dt),
+        }
+    }
+}
+
+impl std::error::Error for BarFileError {}
+
+impl From<io::Error> for BarFileError {
+    fn from(err: io::Error) -> Self {
+        BarFileError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BlockIndex {
+    first: i64,
+    last: i64,
+    offset: u64,
+    len: u32,
+    count: u32,
+}
+
+/// Writes bars ordered by `bar_start` in blocks, the index on `finish`
+pub struct BarFileWriter<W: Write> {
+    writer: W,
+    block: Vec<Bar>,
+    block_size: usize,
+    index: Vec<BlockIndex>,
+    offset: u64,
+    last: Option<NaiveDateTime>,
+}
+
+impl<W: Write> BarFileWriter<W> {
+    /// Writes the header, blocks hold 4096 bars
+    pub fn new(mut writer: W) -> Result<Self, BarFileError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            block: vec![],
+            block_size: 4096,
+            index: vec![],
+            offset: HEADER_LEN,
+            last: None,
+        })
+    }
+
+    /// Smaller blocks make seeking cheaper and compression worse
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        assert!(block_size > 0, "block size must be positive");
+        self.block_size = block_size;
+        self
+    }
+
+    pub fn write_bar(&mut self, bar: &Bar) -> Result<(), BarFileError> {
+        if self.last.is_some_and(|last| bar.bar_start <= last) {
+            return Err(BarFileError::OutOfOrder(bar.bar_start));
+        }
+        nanos(bar.bar_start)?;
+        nanos(bar.next_bar_dt)?;
+        self.last = Some(bar.bar_start);
+        self.block.push(bar.clone());
+        if self.block.len() == self.block_size {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining bars and the index, returns the inner writer
+    pub fn finish(mut self) -> Result<W, BarFileError> {
+        self.write_block()?;
+        let index_offset = self.offset;
+        for entry in &self.index {
+            self.writer.write_all(&entry.first.to_le_bytes())?;
+            self.writer.write_all(&entry.last.to_le_bytes())?;
+            self.writer.write_all(&entry.offset.to_le_bytes())?;
+            self.writer.write_all(&entry.len.to_le_bytes())?;
+            self.writer.write_all(&entry.count.to_le_bytes())?;
+        }
+        self.writer.write_all(&index_offset.to_le_bytes())?;
+        self.writer.write_all(INDEX_MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_block(&mut self) -> Result<(), BarFileError> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let block = encode_block(&self.block)?;
+        self.writer.write_all(&block)?;
+        self.index.push(BlockIndex {
+            first: nanos(self.block[0].bar_start)?,
+            last: nanos(self.block[self.block.len() - 1].bar_start)?,
+            offset: self.offset,
+            len: block.len() as u32,
+            count: self.block.len() as u32,
+        });
+        self.offset += block.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+}
+
+/// Reads a bar file, loading only the blocks a query touches
+#[derive(Debug)]
+pub struct BarFileReader<R> {
+    reader: R,
+    index: Vec<BlockIndex>,
+}
+
+impl<R: Read + Seek> BarFileReader<R> {
+    /// Reads the header and the index
+    pub fn new(mut reader: R) -> Result<Self, BarFileError> {
+        let mut header = [0; HEADER_LEN as usize];
+        reader.seek(SeekFrom::Start(0))?;
+        read_exact(&mut reader, &mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(BarFileError::Format("bad magic"));
+        }
+        if u16::from_le_bytes([header[8], header[9]]) != VERSION {
+            return Err(BarFileError::Format("unsupported version"));
+        }
+
+        let end = reader.seek(SeekFrom::End(0))?;
+        if end < HEADER_LEN + TRAILER_LEN {
+            return Err(BarFileError::Format("missing index"));
+        }
+        let mut trailer = [0; TRAILER_LEN as usize];
+        reader.seek(SeekFrom::Start(end - TRAILER_LEN))?;
+        read_exact(&mut reader, &mut trailer)?;
+        if &trailer[8..] != INDEX_MAGIC {
+            return Err(BarFileError::Format("missing index"));
+        }
+        let index_offset = u64_at(&trailer, 0);
+        let index_len = (end - TRAILER_LEN)
+            .checked_sub(index_offset)
+            .filter(|len| index_offset >= HEADER_LEN && len % ENTRY_LEN == 0)
+            .ok_or(BarFileError::Format("bad index offset"))?;
+
+        let mut entries = vec![0; index_len as usize];
+        reader.seek(SeekFrom::Start(index_offset))?;
+        read_exact(&mut reader, &mut entries)?;
+        let index: Vec<_> = entries
+            .chunks(ENTRY_LEN as usize)
+            .map(|entry| BlockIndex {
+                first: u64_at(entry, 0) as i64,
+                last: u64_at(entry, 8) as i64,
+                offset: u64_at(entry, 16),
+                len: u32_at(entry, 24),
+                count: u32_at(entry, 28),
+            })
+            .collect();
+        let blocks_end = index.iter().try_fold(HEADER_LEN, |offset, entry| {
+            (entry.offset == offset && entry.first <= entry.last).then(|| offset + entry.len as u64)
+        });
+        if blocks_end != Some(index_offset) {
+            return Err(BarFileError::Format("bad index"));
+        }
+        Ok(Self { reader, index })
+    }
+
+    /// Number of bars in the file
+    pub fn len(&self) -> u64 {
+        self.index.iter().map(|entry| entry.count as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// `bar_start` of the first and the last bar
+    pub fn span(&self) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let first = self.index.first()?;
+        let last = self.index.last()?;
+        Some((from_nanos(first.first)?, from_nanos(last.last)?))
+    }
+
+    /// Bars with `from <= bar_start < to`, in order
+    pub fn range(&mut self, from: NaiveDateTime, to: NaiveDateTime) -> Range<'_, R> {
+        let from_nanos = from
+            .and_utc()
+            .timestamp_nanos_opt()
+            .unwrap_or(if from.year() < 1970 {
+                i64::MIN
+            } else {
+                i64::MAX
+            });
+        let block = self.index.partition_point(|entry| entry.last < from_nanos);
+        Range {
+            file: self,
+            block,
+            bars: vec![].into_iter(),
+            from,
+            to,
+        }
+    }
+
+    /// All the bars, in order
+    pub fn iter(&mut self) -> Range<'_, R> {
+        self.range(NaiveDateTime::MIN, NaiveDateTime::MAX)
+    }
+
+    fn read_block(&mut self, block: usize) -> Result<Vec<Bar>, BarFileError> {
+        let entry = self.index[block];
+        let mut bytes = vec![0; entry.len as usize];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        read_exact(&mut self.reader, &mut bytes)?;
+        let bars = decode_block(&bytes)?;
+        if bars.len() != entry.count as usize {
+            return Err(BarFileError::Format("bad block"));
+        }
+        Ok(bars)
+    }
+}
+
+/// Iterator over the bars of a time range, see `BarFileReader::range`
+#[derive(Debug)]
+pub struct Range<'a, R> {
+    file: &'a mut BarFileReader<R>,
+    block: usize,
+    bars: std::vec::IntoIter<Bar>,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+}
+
+impl<'a, R: Read + Seek> Iterator for Range<'a, R> {
+    type Item = Result<Bar, BarFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for bar in &mut self.bars {
+                if bar.bar_start >= self.to {
+                    self.block = self.file.index.len();
+                    return None;
+                }
+                if bar.bar_start >= self.from {
+                    return Some(Ok(bar));
+                }
+            }
+            if self.block >= self.file.index.len() {
+                return None;
+            }
+            match self.file.read_block(self.block) {
+                Ok(bars) => {
+                    self.block += 1;
+                    self.bars = bars.into_iter();
+                }
+                Err(err) => {
+                    self.block = self.file.index.len();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+fn nanos(dt: NaiveDateTime) -> Result<i64, BarFileError> {
+    dt.and_utc()
+        .timestamp_nanos_opt()
+        .ok_or(BarFileError::TimeRange(dt))
+}
+
+fn from_nanos(nanos: i64) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(
+        nanos.div_euclid(1_000_000_000),
+        nanos.rem_euclid(1_000_000_000) as u32,
+    )
+    .map(|dt| dt.naive_utc())
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), BarFileError> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => BarFileError::Format("truncated file"),
+        _ => BarFileError::Io(err),
+    })
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(buf)
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn encode_block(bars: &[Bar]) -> Result<Vec<u8>, BarFileError> {
+    let mut times = vec![];
+    let mut previous: Option<(i64, i64)> = None;
+    for bar in bars {
+        let start = nanos(bar.bar_start)?;
+        let len = nanos(bar.next_bar_dt)?.wrapping_sub(start);
+        let (a, b) = match previous {
+            None => (start, len),
+            Some((end, previous_len)) => (start.wrapping_sub(end), len.wrapping_sub(previous_len)),
+        };
+        write_varint(&mut times, zigzag(a));
+        write_varint(&mut times, zigzag(b));
+        previous = Some((start.wrapping_add(len), len));
+    }
+
+    let mut block = (bars.len() as u32).to_le_bytes().to_vec();
+    let prices = match decimal_scale(bars) {
+        Some(scale) => {
+            block.push(scale as u8);
+            let points = |value: f64| (value * 10f64.powi(scale)).round() as i64;
+            let mut columns = vec![vec![]; 4];
+            let mut close = 0;
+            for bar in bars {
+                let open = points(bar.open);
+                write_varint(&mut columns[0], zigzag(open - close));
+                write_varint(&mut columns[1], zigzag(points(bar.high) - open));
+                write_varint(&mut columns[2], zigzag(points(bar.low) - open));
+                write_varint(&mut columns[3], zigzag(points(bar.close) - open));
+                close = points(bar.close);
+            }
+            columns
+        }
+        None => {
+            block.push(XOR);
+            let columns: [fn(&Bar) -> f64; 4] = [|b| b.open, |b| b.high, |b| b.low, |b| b.close];
+            columns
+                .iter()
+                .map(|column| {
+                    let mut xor = XorWriter::default();
+                    for bar in bars {
+                        xor.push(column(bar));
+                    }
+                    xor.bits.bytes
+                })
+                .collect()
+        }
+    };
+    for column in Some(times).into_iter().chain(prices) {
+        block.extend_from_slice(&(column.len() as u32).to_le_bytes());
+        block.extend_from_slice(&column);
+    }
+    Ok(block)
+}
+
+// the fewest decimals every price of the block has exactly, if any up to 9
+fn decimal_scale(bars: &[Bar]) -> Option<i32> {
+    (0..=9).find(|scale| {
+        let exact = |value: f64| {
+            let points = value * 10f64.powi(*scale);
+            points.abs() < (1u64 << 53) as f64
+                && ((points.round() as i64) as f64 / 10f64.powi(*scale)).to_bits()
+                    == value.to_bits()
+        };
+        bars.iter()
+            .all(|bar| exact(bar.open) && exact(bar.high) && exact(bar.low) && exact(bar.close))
+    })
+}
+
+fn decode_block(block: &[u8]) -> Result<Vec<Bar>, BarFileError> {
+    let bad = BarFileError::Format("bad block");
+    if block.len() < 5 {
+        return Err(bad);
+    }
+    let count = u32_at(block, 0) as usize;
+    let scale = block[4];
+    let mut columns = vec![];
+    let mut at = 5;
+    for _ in 0..5 {
+        if block.len() < at + 4 {
+            return Err(bad);
+        }
+        let len = u32_at(block, at) as usize;
+        at += 4;
+        let column = block
+            .get(at..at + len)
+            .ok_or(BarFileError::Format("bad block"))?;
+        columns.push(column);
+        at += len;
+    }
+
+    // every bar takes two varints, a byte each at least, so a damaged count
+    // can't ask for more than the block holds
+    let mut times = columns[0];
+    if count > times.len() / 2 {
+        return Err(bad);
+    }
+    let mut bars = Vec::with_capacity(count);
+    let mut previous: Option<(i64, i64)> = None;
+    for _ in 0..count {
+        let a = unzigzag(read_varint(&mut times).ok_or(BarFileError::Format("bad times"))?);
+        let b = unzigzag(read_varint(&mut times).ok_or(BarFileError::Format("bad times"))?);
+        let (start, len) = match previous {
+            None => (a, b),
+            Some((end, previous_len)) => (end.wrapping_add(a), previous_len.wrapping_add(b)),
+        };
+        let end = start.wrapping_add(len);
+        previous = Some((end, len));
+        bars.push(Bar {
+            open: 0.,
+            high: 0.,
+            low: 0.,
+            close: 0.,
+            bar_start: from_nanos(start).ok_or(BarFileError::Format("bad times"))?,
+            next_bar_dt: from_nanos(end).ok_or(BarFileError::Format("bad times"))?,
+        });
+    }
+
+    let columns_mut: [fn(&mut Bar) -> &mut f64; 4] = [
+        |b| &mut b.open,
+        |b| &mut b.high,
+        |b| &mut b.low,
+        |b| &mut b.close,
+    ];
+    if scale == XOR {
+        for (column, bytes) in columns_mut.iter().zip(&columns[1..]) {
+            let mut xor = XorReader::new(bytes);
+            for bar in &mut bars {
+                *column(bar) = xor.next().ok_or(BarFileError::Format("bad prices"))?;
+            }
+        }
+    } else if scale <= 9 {
+        let divisor = 10f64.powi(scale as i32);
+        let mut columns: Vec<_> = columns[1..].to_vec();
+        let mut close = 0i64;
+        for bar in &mut bars {
+            let mut deltas = [0; 4];
+            for (delta, column) in deltas.iter_mut().zip(&mut columns) {
+                *delta = unzigzag(read_varint(column).ok_or(BarFileError::Format("bad prices"))?);
+            }
+            let open = close.wrapping_add(deltas[0]);
+            close = open.wrapping_add(deltas[3]);
+            bar.open = open as f64 / divisor;
+            bar.high = open.wrapping_add(deltas[1]) as f64 / divisor;
+            bar.low = open.wrapping_add(deltas[2]) as f64 / divisor;
+            bar.close = close as f64 / divisor;
+        }
+    } else {
+        return Err(BarFileError::Format("bad prices"));
+    }
+    Ok(bars)
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    // bits used in the last byte, 0 means it is full
+    used: u32,
+}
+
+impl BitWriter {
+    // the `count` low bits of `value`, most significant first
+    fn write(&mut self, value: u64, count: u32) {
+        for bit in (0..count).rev() {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            let byte = self.bytes.last_mut().unwrap();
+            *byte |= (((value >> bit) & 1) as u8) << (7 - self.used);
+            self.used = (self.used + 1) % 8;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, count: u32) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self.bytes.get(self.position / 8)?;
+            value = value << 1 | ((byte >> (7 - self.position % 8)) & 1) as u64;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+#[derive(Default)]
+struct XorWriter {
+    bits: BitWriter,
+    previous: Option<u64>,
+    // leading and trailing zeros of the last stored XOR
+    window: Option<(u32, u32)>,
+}
+
+impl XorWriter {
+    fn push(&mut self, value: f64) {
+        let value = value.to_bits();
+        let previous = match self.previous.replace(value) {
+            Some(previous) => previous,
+            None => return self.bits.write(value, 64),
+        };
+        let xor = value ^ previous;
+        if xor == 0 {
+            return self.bits.write(0, 1);
+        }
+        self.bits.write(1, 1);
+
+        let leading = xor.leading_zeros().min(31);
+        let trailing = xor.trailing_zeros();
+        match self.window {
+            Some((window_leading, window_trailing))
+                if leading >= window_leading && trailing >= window_trailing =>
+            {
+                self.bits.write(0, 1);
+                let meaningful = 64 - window_leading - window_trailing;
+                self.bits.write(xor >> window_trailing, meaningful);
+            }
+            _ => {
+                let meaningful = 64 - leading - trailing;
+                self.bits.write(1, 1);
+                self.bits.write(leading as u64, 5);
+                self.bits.write(meaningful as u64 - 1, 6);
+                self.bits.write(xor >> trailing, meaningful);
+                self.window = Some((leading, trailing));
+            }
+        }
+    }
+}
+
+struct XorReader<'a> {
+    bits: BitReader<'a>,
+    previous: Option<u64>,
+    window: Option<(u32, u32)>,
+}
+
+impl<'a> XorReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bits: BitReader { bytes, position: 0 },
+            previous: None,
+            window: None,
+        }
+    }
+
+    fn next(&mut self) -> Option<f64> {
+        let value = match self.previous {
+            None => self.bits.read(64)?,
+            Some(previous) if self.bits.read(1)? == 0 => previous,
+            Some(previous) => {
+                let (leading, trailing) = if self.bits.read(1)? == 0 {
+                    self.window?
+                } else {
+                    let leading = self.bits.read(5)? as u32;
+                    let meaningful = self.bits.read(6)? as u32 + 1;
+                    let trailing = 64u32.checked_sub(leading + meaningful)?;
+                    self.window = Some((leading, trailing));
+                    (leading, trailing)
+                };
+                let meaningful = 64 - leading - trailing;
+                previous ^ (self.bits.read(meaningful)? << trailing)
+            }
+        };
+        self.previous = Some(value);
+        Some(f64::from_bits(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use chrono::Duration;
+    use std::io::Cursor;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    // bars of a random walk with gaps, as the sampler emits them, prices are
+    // 5 decimal quotes or the sums of float steps
+    fn sampled(short: &str, ticks: usize, decimal: bool) -> Vec<Bar> {
+        let mut sampler = <dyn Sampler>::from_short(short).unwrap();
+        let mut dt = date("2015-01-01 00:00:00");
+        let mut points = 110_000;
+        let mut sum = 1.1;
+        let mut bars = vec![];
+        for i in 0..ticks {
+            dt += Duration::milliseconds((i * 7_919 % 50_000) as i64 + 1);
+            let step = (i * 31 % 11) as i64 - 5;
+            points += step;
+            sum += step as f64 * 0.00001;
+            let price = if decimal { points as f64 / 1e5 } else { sum };
+            if let Some(closed) = sampler.next_bar(dt, price) {
+                let (bar, empty) = closed.into_parts();
+                bars.push(bar);
+                bars.extend(empty);
+            }
+        }
+        bars
+    }
+
+    fn write(bars: &[Bar], block_size: usize) -> Vec<u8> {
+        let mut writer = BarFileWriter::new(vec![]).unwrap().block_size(block_size);
+        for bar in bars {
+            writer.write_bar(bar).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        for short in &["M1", "M15", "H5", "D1", "Mn1"] {
+            for (decimal, block_size) in [(true, 1), (true, 7), (true, 4096), (false, 7)] {
+                let bars = sampled(short, 20_000, decimal);
+                let bytes = write(&bars, block_size);
+                let mut reader = BarFileReader::new(Cursor::new(bytes)).unwrap();
+                assert_eq!(reader.len(), bars.len() as u64);
+                let read: Vec<_> = reader.iter().collect::<Result<_, _>>().unwrap();
+                assert_eq!(read, bars, "{} in blocks of {}", short, block_size);
+            }
+        }
+    }
+
+    #[test]
+    fn special_values() {
+        let bars: Vec<_> = [1.5, f64::NAN, -0., f64::INFINITY, 1e-300, 1.5]
+            .iter()
+            .enumerate()
+            .map(|(i, price)| Bar {
+                open: *price,
+                high: price * 2.,
+                low: -price,
+                close: *price,
+                bar_start: date("1969-12-31 23:00:00") + Duration::hours(i as i64 * 3),
+                next_bar_dt: date("1969-12-31 23:00:00") + Duration::hours(i as i64 * 3 + 1),
+            })
+            .collect();
+        let bytes = write(&bars, 4);
+        let read: Vec<_> = BarFileReader::new(Cursor::new(bytes))
+            .unwrap()
+            .iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        for (read, bar) in read.iter().zip(&bars) {
+            assert_eq!(read.open.to_bits(), bar.open.to_bits());
+            assert_eq!(read.high.to_bits(), bar.high.to_bits());
+            assert_eq!(read.bar_start, bar.bar_start);
+            assert_eq!(read.next_bar_dt, bar.next_bar_dt);
+        }
+    }
+
+    #[test]
+    fn range_queries() {
+        let bars = sampled("M1", 5_000, true);
+        let mut reader = BarFileReader::new(Cursor::new(write(&bars, 16))).unwrap();
+        let (first, last) = reader.span().unwrap();
+        assert_eq!(first, bars[0].bar_start);
+        assert_eq!(last, bars[bars.len() - 1].bar_start);
+
+        let from = bars[100].bar_start;
+        let to = bars[133].bar_start + Duration::seconds(30);
+        let read: Vec<_> = reader.range(from, to).collect::<Result<_, _>>().unwrap();
+        assert_eq!(read, bars[100..=133].to_vec());
+
+        assert_eq!(
+            reader
+                .range(last + Duration::seconds(1), NaiveDateTime::MAX)
+                .count(),
+            0
+        );
+        assert_eq!(reader.range(NaiveDateTime::MIN, first).count(), 0);
+        assert_eq!(
+            reader.range(NaiveDateTime::MIN, NaiveDateTime::MAX).count(),
+            bars.len()
+        );
+    }
+
+    #[test]
+    fn compression() {
+        // contiguous M1 bars, 48 bytes each in memory
+        let per_bar = |decimal| {
+            let bars = sampled("M1", 100_000, decimal);
+            write(&bars, 4096).len() as f64 / bars.len() as f64
+        };
+        assert!(per_bar(true) < 8., "{} bytes per bar", per_bar(true));
+        assert!(per_bar(false) < 24., "{} bytes per bar", per_bar(false));
+    }
+
+    #[test]
+    fn invalid_files() {
+        let bars = sampled("M5", 1_000, true);
+        let mut writer = BarFileWriter::new(vec![]).unwrap();
+        writer.write_bar(&bars[1]).unwrap();
+        assert!(matches!(
+            writer.write_bar(&bars[0]),
+            Err(BarFileError::OutOfOrder(_))
+        ));
+
+        let bytes = write(&bars, 8);
+        let err = |bytes: Vec<u8>| {
+            BarFileReader::new(Cursor::new(bytes))
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(err(b"METABAR".to_vec()), "invalid bar file: truncated file");
+        assert_eq!(
+            err(bytes[..bytes.len() - 1].to_vec()),
+            "invalid bar file: missing index"
+        );
+        let mut other = bytes.clone();
+        other[0] = b'X';
+        assert_eq!(err(other), "invalid bar file: bad magic");
+
+        // a damaged block is found when read
+        let mut damaged = bytes;
+        damaged[HEADER_LEN as usize + 5] = 0xff;
+        let mut reader = BarFileReader::new(Cursor::new(damaged)).unwrap();
+        let mut read = reader.iter();
+        assert!(read.next().unwrap().is_err());
+        assert!(read.next().is_none());
+
+        // so is a block count no block could hold
+        let mut damaged = write(&bars, 8);
+        let at = HEADER_LEN as usize;
+        damaged[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = BarFileReader::new(Cursor::new(damaged)).unwrap();
+        assert_eq!(
+            reader.iter().next().unwrap().unwrap_err().to_string(),
+            "invalid bar file: bad block"
+        );
+
+        let empty = BarFileWriter::new(vec![]).unwrap().finish().unwrap();
+        let mut reader = BarFileReader::new(Cursor::new(empty)).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(reader.span(), None);
+        assert_eq!(reader.iter().count(), 0);
+    }
+}
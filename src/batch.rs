@@ -0,0 +1,273 @@
+use crate::timeframe::{parse_short, FromSampler};
+use crate::*;
+use chrono::prelude::*;
+use std::fmt;
+
+/// Bars stored column by column, the output of the batch path
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarColumns<P = f64> {
+    pub bar_start: Vec<NaiveDateTime>,
+    pub next_bar_dt: Vec<NaiveDateTime>,
+    pub open: Vec<P>,
+    pub high: Vec<P>,
+    pub low: Vec<P>,
+    pub close: Vec<P>,
+    pub volume: Vec<f64>,
+    pub vwap: Vec<f64>,
+    pub twap: Vec<f64>,
+    /// True for the flat bars of periods without ticks
+    pub empty: Vec<bool>,
+}
+
+impl<P> Default for BarColumns<P> {
+    fn default() -> Self {
+        Self {
+            bar_start: vec![],
+            next_bar_dt: vec![],
+            open: vec![],
+            high: vec![],
+            low: vec![],
+            close: vec![],
+            volume: vec![],
+            vwap: vec![],
+            twap: vec![],
+            empty: vec![],
+        }
+    }
+}
+
+impl<P> BarColumns<P> {
+    pub fn len(&self) -> usize {
+        self.bar_start.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bar_start.is_empty()
+    }
+
+    pub fn push(&mut self, bar: TradeBar<P>) {
+        self.push_bar(bar, false)
+    }
+
+    fn push_bar(&mut self, bar: TradeBar<P>, empty: bool) {
+        self.bar_start.push(bar.bar.bar_start);
+        self.next_bar_dt.push(bar.bar.next_bar_dt);
+        self.open.push(bar.bar.open);
+        self.high.push(bar.bar.high);
+        self.low.push(bar.bar.low);
+        self.close.push(bar.bar.close);
+        self.volume.push(bar.volume);
+        self.vwap.push(bar.vwap);
+        self.twap.push(bar.twap);
+        self.empty.push(empty);
+    }
+
+    fn push_bars(&mut self, bars: TradeBars<P>) {
+        match bars {
+            TradeBars::Single(bar) => self.push(bar),
+            TradeBars::WithEmpty(bar, empty) => {
+                self.push(bar);
+                for bar in empty {
+                    self.push_bar(bar, true);
+                }
+            }
+        }
+    }
+}
+
+/// A tick of a batch refused by the price policy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchError<P = f64> {
+    /// Index of the tick in the batch
+    pub row: usize,
+    pub error: PriceError<P>,
+}
+
+impl<P: Price> fmt::Display for BatchError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "row {}: {}", self.row, self.error)
+    }
+}
+
+impl<P: Price> std::error::Error for BatchError<P> {}
+
+/// Resamples ticks a batch of columns at a time
+///
+/// Trait objects dispatch once per batch, the per-tick loop runs on the concrete sampler.
+/// Bars carry volume, VWAP and TWAP as `TradeSampler` computes them.
+pub trait BatchSampler<P: Price = f64>: Send {
+    /// Appends the bars closed by the ticks to `bars`, including empty ones.
+    /// `volume` is zero for every tick if None.
+    ///
+    /// On error the ticks before `row` have been sampled and their bars appended.
+    ///
+    /// # Panics
+    ///
+    /// If the columns differ in length
+    fn resample(
+        &mut self,
+        dt: &[NaiveDateTime],
+        price: &[P],
+        volume: Option<&[f64]>,
+        bars: &mut BarColumns<P>,
+    ) -> Result<(), BatchError<P>>;
+
+    fn set_policy(&mut self, policy: PricePolicy);
+
+    fn set_closed(&mut self, closed: Closed);
+
+    fn set_origin(&mut self, origin: Origin);
+
+    fn current_incomplete(&self) -> Option<TradeBar<P>>;
+}
+
+/// `BatchSampler` over a concrete sampler type
+#[derive(Debug, Clone)]
+pub struct Batch<T, P = f64> {
+    trades: TradeSampler<T, P>,
+}
+
+impl<P: Price, T: Sampler<P>> Batch<T, P> {
+    pub fn new(sampler: T) -> Self {
+        Self {
+            trades: TradeSampler::new(sampler),
+        }
+    }
+}
+
+impl<P: Price, T: Sampler<P>> BatchSampler<P> for Batch<T, P> {
+    fn resample(
+        &mut self,
+        dt: &[NaiveDateTime],
+        price: &[P],
+        volume: Option<&[f64]>,
+        bars: &mut BarColumns<P>,
+    ) -> Result<(), BatchError<P>> {
+        assert_eq!(dt.len(), price.len(), "columns differ in length");
+        if let Some(volume) = volume {
+            assert_eq!(dt.len(), volume.len(), "columns differ in length");
+        }
+
+        for row in 0..dt.len() {
+            let volume = volume.map_or(0., |volume| volume[row]);
+            match self.trades.try_next_trade(dt[row], price[row], volume) {
+                Ok(Some(closed)) => bars.push_bars(closed),
+                Ok(None) => {}
+                Err(error) => return Err(BatchError { row, error }),
+            }
+        }
+        Ok(())
+    }
+
+    fn set_policy(&mut self, policy: PricePolicy) {
+        self.trades.set_policy(policy)
+    }
+
+    fn set_closed(&mut self, closed: Closed) {
+        self.trades.sampler_mut().set_closed(closed)
+    }
+
+    fn set_origin(&mut self, origin: Origin) {
+        self.trades.sampler_mut().set_origin(origin)
+    }
+
+    fn current_incomplete(&self) -> Option<TradeBar<P>> {
+        self.trades.current_incomplete()
+    }
+}
+
+impl<P: Price> dyn BatchSampler<P> {
+    /// Same timeframes as `Sampler::from_short`
+    pub fn from_short(short: &str) -> Option<Box<dyn BatchSampler<P>>> {
+        struct Batched;
+
+        impl<P: Price> FromSampler<P> for Batched {
+            type Output = Box<dyn BatchSampler<P>>;
+
+            fn build<S: Sampler<P> + 'static>(self, sampler: S) -> Self::Output {
+                Box::new(Batch::new(sampler))
+            }
+        }
+
+        parse_short(short, Batched)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn matches_trade_sampler() {
+        let start = date("2015-01-01 00:00:00");
+        let mut dt: Vec<_> = (0..2_000i64)
+            .map(|i| start + Duration::seconds(i * i % 7_919 + i * 97))
+            .collect();
+        let price: Vec<_> = (0..2_000).map(|i| (i % 37) as f64 + 0.5).collect();
+        let volume: Vec<_> = (0..2_000).map(|i| (i % 5) as f64).collect();
+        dt.sort();
+
+        for short in Bar::available_timeframes().iter().chain(&["M7", "H5"]) {
+            let mut batch = <dyn BatchSampler>::from_short(short).unwrap();
+            let mut columns = BarColumns::default();
+            // split in uneven batches
+            for (from, to) in [(0, 3), (3, 1_000), (1_000, 1_000), (1_000, 2_000)] {
+                batch
+                    .resample(
+                        &dt[from..to],
+                        &price[from..to],
+                        Some(&volume[from..to]),
+                        &mut columns,
+                    )
+                    .unwrap();
+            }
+
+            let mut sampler = TradeSampler::from_short(short).unwrap();
+            let mut expected = BarColumns::default();
+            for i in 0..dt.len() {
+                if let Some(bars) = sampler.next_trade(dt[i], price[i], volume[i]) {
+                    expected.push_bars(bars);
+                }
+            }
+            assert_eq!(columns, expected, "{}", short);
+            assert_eq!(batch.current_incomplete(), sampler.current_incomplete());
+        }
+        assert!(<dyn BatchSampler>::from_short("X1").is_none());
+        assert!(<dyn BatchSampler>::from_short("M0").is_none());
+        assert!(<dyn BatchSampler>::from_short("").is_none());
+        assert!(<dyn BatchSampler>::from_short("é5").is_none());
+    }
+
+    #[test]
+    fn rejected_row() {
+        let mut batch = <dyn BatchSampler>::from_short("M5").unwrap();
+        batch.set_closed(Closed::Right);
+        let dt = [
+            date("2015-01-01 10:00:00"),
+            date("2015-01-01 10:05:00"),
+            date("2015-01-01 10:06:00"),
+        ];
+        let mut bars = BarColumns::default();
+        let err = batch
+            .resample(&dt, &[1., 2., f64::NAN], None, &mut bars)
+            .unwrap_err();
+        assert_eq!(err.row, 2);
+        assert_eq!(err.to_string(), "row 2: non-finite price: NaN");
+        // right-closed: 10:00 still belongs to the bar before
+        assert_eq!(bars.bar_start, vec![date("2015-01-01 09:55:00")]);
+        assert_eq!(bars.close, vec![1.]);
+        assert_eq!(bars.volume, vec![0.]);
+        assert_eq!(bars.empty, vec![false]);
+
+        batch.set_policy(PricePolicy::Skip);
+        batch
+            .resample(&dt[2..], &[f64::NAN], None, &mut bars)
+            .unwrap();
+        assert_eq!(bars.len(), 1);
+    }
+}
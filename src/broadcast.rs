@@ -0,0 +1,466 @@
+use crate::{Bar, PriceError, Registry};
+use chrono::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tungstenite::Message;
+
+// how long client and accept loops wait before checking for work again
+const POLL: Duration = Duration::from_millis(10);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// how long a client may block a write before it is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// messages waiting for a client, a client that falls further behind is dropped
+const CLIENT_QUEUE: usize = 1_024;
+
+/// Runs samplers on ticks pushed through `next_tick` and broadcasts their bars to
+/// WebSocket clients
+///
+/// Clients send `subscribe SYMBOL TIMEFRAME` and `unsubscribe SYMBOL TIMEFRAME` text
+/// messages. A subscription gets the incomplete bar right away, then every closed
+/// bar and the incomplete bar after each tick, as JSON objects like
+/// `{"symbol":"EURUSD","timeframe":"M1","complete":true,"bar_start":"2015-01-01T10:00:00",
+/// "next_bar_dt":"2015-01-01T10:01:00","open":1.5,"high":2,"low":1.25,"close":1.75}`.
+/// Requests it can't serve get `{"error":"..."}`. A client that doesn't keep up
+/// with its messages is disconnected.
+pub struct BarServer {
+    addr: SocketAddr,
+    shared: Arc<Mutex<Shared>>,
+    stop: Arc<AtomicBool>,
+    accept: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    registries: Vec<(String, Registry)>,
+    clients: HashMap<u64, Client>,
+}
+
+struct Client {
+    subscriptions: HashSet<(String, String)>,
+    sender: SyncSender<String>,
+}
+
+impl BarServer {
+    /// Listens on `addr` and samples every symbol into `timeframes`
+    pub fn bind<A: ToSocketAddrs>(addr: A, timeframes: &[&str]) -> io::Result<Self> {
+        let registries = timeframes
+            .iter()
+            .map(|short| {
+                let registry = Registry::new(short).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown timeframe: {}", short),
+                    )
+                })?;
+                Ok((short.to_string(), registry))
+            })
+            .collect::<io::Result<_>>()?;
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Mutex::new(Shared {
+            registries,
+            clients: HashMap::new(),
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let accept = {
+            let shared = shared.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut next_id = 0;
+                let mut clients: Vec<JoinHandle<()>> = vec![];
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let (shared, stop) = (shared.clone(), stop.clone());
+                            next_id += 1;
+                            let id = next_id;
+                            clients.retain(|client| !client.is_finished());
+                            clients.push(thread::spawn(move || serve(stream, id, shared, stop)));
+                        }
+                        // nothing to accept, or a connection that failed before it was
+                        Err(_) => thread::sleep(POLL),
+                    }
+                }
+                for client in clients {
+                    let _ = client.join();
+                }
+            })
+        };
+
+        Ok(Self {
+            addr,
+            shared,
+            stop,
+            accept: Some(accept),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Samples the tick into every timeframe and sends the bars to subscribers
+    ///
+    /// Returns an error if the price is NaN or infinite, nothing is sampled then.
+    pub fn next_tick(&self, symbol: &str, dt: NaiveDateTime, price: f64) -> Result<(), PriceError> {
+        let mut shared = self.shared.lock().unwrap();
+        let Shared {
+            registries,
+            clients,
+        } = &mut *shared;
+        for (timeframe, registry) in registries {
+            let key = (symbol.to_string(), timeframe.clone());
+            let subscribed = clients
+                .values()
+                .any(|client| client.subscriptions.contains(&key));
+            let closed = registry.try_next_bar(symbol, dt, price)?;
+            if !subscribed {
+                continue;
+            }
+            let mut messages = vec![];
+            if let Some(closed) = closed {
+                let (bar, empty) = closed.bars.into_parts();
+                for bar in Some(bar).iter().chain(&empty) {
+                    messages.push(bar_json(symbol, timeframe, bar, true));
+                }
+            }
+            if let Some(bar) = registry
+                .sampler(symbol)
+                .and_then(|sampler| sampler.current_incomplete())
+            {
+                messages.push(bar_json(symbol, timeframe, &bar, false));
+            }
+            // a client whose thread is gone has disconnected, one with a full
+            // queue is dropped and its thread closes the connection
+            clients.retain(|_, client| {
+                !client.subscriptions.contains(&key)
+                    || messages
+                        .iter()
+                        .all(|message| client.sender.try_send(message.clone()).is_ok())
+            });
+        }
+        Ok(())
+    }
+
+    /// Connected clients
+    pub fn clients(&self) -> usize {
+        self.shared.lock().unwrap().clients.len()
+    }
+}
+
+// stops accepting and waits for the client threads, the accept thread joins them
+impl Drop for BarServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+    }
+}
+
+fn serve(stream: TcpStream, id: u64, shared: Arc<Mutex<Shared>>, stop: Arc<AtomicBool>) {
+    let handshake = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)));
+    if handshake.is_err() {
+        return;
+    }
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    let timeouts = socket
+        .get_ref()
+        .set_read_timeout(Some(POLL))
+        .and_then(|_| socket.get_ref().set_write_timeout(Some(WRITE_TIMEOUT)));
+    if timeouts.is_err() {
+        return;
+    }
+    let (sender, receiver) = mpsc::sync_channel(CLIENT_QUEUE);
+    shared.lock().unwrap().clients.insert(
+        id,
+        Client {
+            subscriptions: HashSet::new(),
+            sender,
+        },
+    );
+
+    'serve: while !stop.load(Ordering::Relaxed) {
+        match socket.read() {
+            Ok(Message::Text(text)) => request(&shared, id, &text),
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(err))
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(_) => break,
+        }
+        loop {
+            match receiver.try_recv() {
+                Ok(message) => {
+                    if socket.send(Message::Text(message)).is_err() {
+                        break 'serve;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                // dropped by `next_tick` for falling behind
+                Err(TryRecvError::Disconnected) => break 'serve,
+            }
+        }
+    }
+    shared.lock().unwrap().clients.remove(&id);
+    let _ = socket.close(None);
+    let _ = socket.flush();
+}
+
+fn request(shared: &Mutex<Shared>, id: u64, text: &str) {
+    let mut shared = shared.lock().unwrap();
+    let Shared {
+        registries,
+        clients,
+    } = &mut *shared;
+    let client = match clients.get_mut(&id) {
+        Some(client) => client,
+        None => return,
+    };
+    let words: Vec<_> = text.split_whitespace().collect();
+    let (action, symbol, timeframe) = match words[..] {
+        [action, symbol, timeframe] => (action, symbol, timeframe),
+        _ => {
+            let _ = client
+                .sender
+                .try_send(error_json(&format!("invalid request: {}", text)));
+            return;
+        }
+    };
+    let registry = match registries.iter().find(|(short, _)| short == timeframe) {
+        Some((_, registry)) => registry,
+        None => {
+            let error = format!("timeframe not served: {}", timeframe);
+            let _ = client.sender.try_send(error_json(&error));
+            return;
+        }
+    };
+    let key = (symbol.to_string(), timeframe.to_string());
+    match action {
+        "subscribe" => {
+            // sent under the lock, so no update slips in before the snapshot
+            if let Some(bar) = registry
+                .sampler(symbol)
+                .and_then(|sampler| sampler.current_incomplete())
+            {
+                let _ = client
+                    .sender
+                    .try_send(bar_json(symbol, timeframe, &bar, false));
+            }
+            client.subscriptions.insert(key);
+        }
+        "unsubscribe" => {
+            client.subscriptions.remove(&key);
+        }
+        _ => {
+            let _ = client
+                .sender
+                .try_send(error_json(&format!("invalid request: {}", text)));
+        }
+    }
+}
+
+fn bar_json(symbol: &str, timeframe: &str, bar: &Bar, complete: bool) -> String {
+    let mut json = String::from("{\"symbol\":");
+    push_str(&mut json, symbol);
+    json.push_str(",\"timeframe\":");
+    push_str(&mut json, timeframe);
+    let _ = write!(
+        json,
+        ",\"complete\":{},\"bar_start\":\"{}\",\"next_bar_dt\":\"{}\"",
+        complete,
+        bar.bar_start.format("%Y-%m-%dT%H:%M:%S%.f"),
+        bar.next_bar_dt.format("%Y-%m-%dT%H:%M:%S%.f"),
+    );
+    for (name, value) in [
+        ("open", bar.open),
+        ("high", bar.high),
+        ("low", bar.low),
+        ("close", bar.close),
+    ] {
+        // JSON has no NaN or infinities
+        if value.is_finite() {
+            let _ = write!(json, ",\"{}\":{}", name, value);
+        } else {
+            let _ = write!(json, ",\"{}\":null", name);
+        }
+    }
+    json.push('}');
+    json
+}
+
+fn error_json(error: &str) -> String {
+    let mut json = String::from("{\"error\":");
+    push_str(&mut json, error);
+    json.push('}');
+    json
+}
+
+fn push_str(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::WebSocket;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn connect(server: &BarServer) -> WebSocket<MaybeTlsStream<TcpStream>> {
+        let (socket, _) = tungstenite::connect(format!("ws://{}", server.local_addr())).unwrap();
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+        }
+        socket
+    }
+
+    fn receive(socket: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> String {
+        loop {
+            if let Message::Text(text) = socket.read().unwrap() {
+                return text;
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_and_updates() {
+        let server = BarServer::bind("127.0.0.1:0", &["M1", "M5"]).unwrap();
+        server
+            .next_tick("EURUSD", date("2015-01-01 10:00:10"), 1.5)
+            .unwrap();
+        server
+            .next_tick("USDJPY", date("2015-01-01 10:00:10"), 120.)
+            .unwrap();
+
+        let mut socket = connect(&server);
+        socket
+            .send(Message::Text("subscribe EURUSD M1".to_string()))
+            .unwrap();
+        assert_eq!(
+            receive(&mut socket),
+            "{\"symbol\":\"EURUSD\",\"timeframe\":\"M1\",\"complete\":false,\
+             \"bar_start\":\"2015-01-01T10:00:00\",\"next_bar_dt\":\"2015-01-01T10:01:00\",\
+             \"open\":1.5,\"high\":1.5,\"low\":1.5,\"close\":1.5}"
+        );
+
+        // neither the other symbol nor the other timeframe come through
+        server
+            .next_tick("USDJPY", date("2015-01-01 10:02:10"), 121.)
+            .unwrap();
+        server
+            .next_tick("EURUSD", date("2015-01-01 10:00:20"), 2.)
+            .unwrap();
+        server
+            .next_tick("EURUSD", date("2015-01-01 10:02:00"), 1.75)
+            .unwrap();
+        let messages: Vec<_> = (0..4).map(|_| receive(&mut socket)).collect();
+        assert!(messages[0].contains("\"complete\":false") && messages[0].contains("\"high\":2,"));
+        assert!(messages[1].contains("\"complete\":true,\"bar_start\":\"2015-01-01T10:00:00\""));
+        assert!(messages[1].contains("\"close\":2}"));
+        // the empty bar of 10:01
+        assert!(messages[2].contains("\"complete\":true,\"bar_start\":\"2015-01-01T10:01:00\""));
+        assert!(messages[3].contains("\"complete\":false,\"bar_start\":\"2015-01-01T10:02:00\""));
+        assert!(messages.iter().all(|message| message.contains("EURUSD")));
+
+        socket.close(None).unwrap();
+        while socket.read().is_ok() {}
+        for _ in 0..500 {
+            if server.clients() == 0 {
+                break;
+            }
+            thread::sleep(POLL);
+        }
+        assert_eq!(server.clients(), 0);
+    }
+
+    #[test]
+    fn bad_requests() {
+        assert!(BarServer::bind("127.0.0.1:0", &["X1"]).is_err());
+        let server = BarServer::bind("127.0.0.1:0", &["M1"]).unwrap();
+        let mut socket = connect(&server);
+        for (request, error) in [
+            ("subscribe EURUSD H1", "timeframe not served: H1"),
+            ("subscribe", "invalid request: subscribe"),
+            ("watch EURUSD M1", "invalid request: watch EURUSD M1"),
+        ] {
+            socket.send(Message::Text(request.to_string())).unwrap();
+            assert_eq!(receive(&mut socket), format!("{{\"error\":\"{}\"}}", error));
+        }
+
+        // nothing to snapshot yet, the first update comes with the first tick
+        socket
+            .send(Message::Text("subscribe EURUSD M1".to_string()))
+            .unwrap();
+        socket
+            .send(Message::Text("unsubscribe GBPUSD M1".to_string()))
+            .unwrap();
+        socket.send(Message::Text("subscribe".to_string())).unwrap();
+        assert!(receive(&mut socket).contains("error"));
+        assert!(matches!(
+            server.next_tick("EURUSD", date("2015-01-01 10:00:10"), f64::NAN),
+            Err(PriceError::NonFinite(_))
+        ));
+        server
+            .next_tick("EURUSD", date("2015-01-01 10:00:10"), 1.5)
+            .unwrap();
+        assert!(receive(&mut socket).contains("\"open\":1.5"));
+    }
+
+    #[test]
+    fn slow_client_is_dropped() {
+        let server = BarServer::bind("127.0.0.1:0", &["M1"]).unwrap();
+        let mut socket = connect(&server);
+        socket
+            .send(Message::Text("subscribe EURUSD M1".to_string()))
+            .unwrap();
+        server
+            .next_tick("EURUSD", date("2015-01-01 10:00:00"), 1.)
+            .unwrap();
+        receive(&mut socket);
+
+        // stops reading, the socket buffers then the queue fill up
+        let start = date("2015-01-01 10:00:00");
+        for i in 0..1_000_000 {
+            if server.clients() == 0 {
+                break;
+            }
+            let dt = start + chrono::Duration::minutes(i);
+            server.next_tick("EURUSD", dt, 1.).unwrap();
+        }
+        assert_eq!(server.clients(), 0);
+        drop(socket);
+    }
+}
@@ -0,0 +1,250 @@
+use chrono::prelude::*;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+const RECORD_LEN: usize = 20;
+
+/// A quote of a Dukascopy tick file
+#[derive(Debug, Clone, PartialEq)]
+pub struct DukascopyTick {
+    pub dt: NaiveDateTime,
+    pub ask: f64,
+    pub bid: f64,
+    /// In millions of units
+    pub ask_volume: f64,
+    pub bid_volume: f64,
+}
+
+impl DukascopyTick {
+    pub fn mid(&self) -> f64 {
+        (self.ask + self.bid) / 2.
+    }
+}
+
+#[derive(Debug)]
+pub enum Bi5Error {
+    Io(io::Error),
+    Lzma(lzma_rs::error::Error),
+    Format(&'static str),
+    /// A path not in the `SYMBOL/year/month/day/HHh_ticks.bi5` layout
+    Path(PathBuf),
+}
+
+impl fmt::Display for Bi5Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bi5Error::Io(err) => write!(f, "{}", err),
+            Bi5Error::Lzma(err) => write!(f, "{}", err),
+            Bi5Error::Format(reason) => write!(f, "invalid bi5 file: {}", reason),
+            Bi5Error::Path(path) => write!(f, "not a Dukascopy tick path: {}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Bi5Error {}
+
+impl From<io::Error> for Bi5Error {
+    fn from(err: io::Error) -> Self {
+        Bi5Error::Io(err)
+    }
+}
+
+impl From<lzma_rs::error::Error> for Bi5Error {
+    fn from(err: lzma_rs::error::Error) -> Self {
+        Bi5Error::Lzma(err)
+    }
+}
+
+/// Decimal places of Dukascopy's integer prices for `symbol`
+///
+/// Three for yen crosses and metals, five otherwise. Indices, commodities and
+/// crypto vary, callers should know their digits.
+pub fn dukascopy_digits(symbol: &str) -> u32 {
+    let symbol = symbol.to_ascii_uppercase();
+    if symbol.contains("JPY") || symbol.starts_with("XAU") || symbol.starts_with("XAG") {
+        3
+    } else {
+        5
+    }
+}
+
+/// Symbol and hour of a tick file from its path, `EURUSD/2015/00/02/09h_ticks.bi5`
+/// with the month counting from zero
+pub fn dukascopy_hour<Q: AsRef<Path>>(path: Q) -> Option<(String, NaiveDateTime)> {
+    let parts: Vec<_> = path
+        .as_ref()
+        .iter()
+        .rev()
+        .take(5)
+        .map(|part| part.to_str())
+        .collect::<Option<_>>()?;
+    if parts.len() != 5 {
+        return None;
+    }
+    let hour = parts[0].strip_suffix("h_ticks.bi5")?.parse().ok()?;
+    let day = parts[1].parse().ok()?;
+    let month = parts[2].parse::<u32>().ok()?.checked_add(1)?;
+    let year = parts[3].parse().ok()?;
+    let dt = NaiveDate::from_ymd_opt(year, month, day)?.and_hms_opt(hour, 0, 0)?;
+    Some((parts[4].to_string(), dt))
+}
+
+/// Reads the quotes of an hourly Dukascopy `.bi5` tick file
+///
+/// The file is LZMA-compressed big-endian records of the millisecond offset from
+/// the hour, ask and bid as integers of `digits` decimal places, then ask and bid
+/// volumes. Hours without ticks come as empty files.
+pub struct Bi5Reader {
+    records: Vec<u8>,
+    at: usize,
+    hour: NaiveDateTime,
+    scale: f64,
+}
+
+impl Bi5Reader {
+    /// Takes the symbol and hour from the path, see `dukascopy_hour`
+    pub fn from_path<Q: AsRef<Path>>(path: Q) -> Result<Self, Bi5Error> {
+        let path = path.as_ref();
+        let (symbol, hour) =
+            dukascopy_hour(path).ok_or_else(|| Bi5Error::Path(path.to_path_buf()))?;
+        Self::new(std::fs::File::open(path)?, hour, dukascopy_digits(&symbol))
+    }
+
+    /// Decompresses the whole file, an hour of ticks fits in memory
+    pub fn new<R: Read>(reader: R, hour: NaiveDateTime, digits: u32) -> Result<Self, Bi5Error> {
+        let mut compressed = vec![];
+        io::BufReader::new(reader).read_to_end(&mut compressed)?;
+        let mut records = vec![];
+        if !compressed.is_empty() {
+            lzma_rs::lzma_decompress(&mut &compressed[..], &mut records)?;
+        }
+        if records.len() % RECORD_LEN != 0 {
+            return Err(Bi5Error::Format("truncated record"));
+        }
+        Ok(Self {
+            records,
+            at: 0,
+            hour,
+            scale: 10f64.powi(digits as i32),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len() / RECORD_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl Iterator for Bi5Reader {
+    type Item = DukascopyTick;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.records.get(self.at..self.at + RECORD_LEN)?;
+        self.at += RECORD_LEN;
+        let u32_at = |at: usize| u32::from_be_bytes(record[at..at + 4].try_into().unwrap());
+        let f32_at = |at: usize| f32::from_be_bytes(record[at..at + 4].try_into().unwrap());
+        Some(DukascopyTick {
+            dt: self.hour + chrono::Duration::milliseconds(u32_at(0) as i64),
+            ask: u32_at(4) as f64 / self.scale,
+            bid: u32_at(8) as f64 / self.scale,
+            ask_volume: f32_at(12) as f64,
+            bid_volume: f32_at(16) as f64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data/dukascopy/USDJPY/2015/00/02/09h_ticks.bi5")
+    }
+
+    #[test]
+    fn paths_and_digits() {
+        assert_eq!(
+            dukascopy_hour(fixture()),
+            Some(("USDJPY".to_string(), date("2015-01-02 09:00:00")))
+        );
+        assert_eq!(
+            dukascopy_hour("EURUSD/2020/11/31/23h_ticks.bi5"),
+            Some(("EURUSD".to_string(), date("2020-12-31 23:00:00")))
+        );
+        assert_eq!(dukascopy_hour("EURUSD/2020/12/01/23h_ticks.bi5"), None);
+        assert_eq!(dukascopy_hour("2020/11/31/23h_ticks.bi5"), None);
+        assert_eq!(dukascopy_digits("USDJPY"), 3);
+        assert_eq!(dukascopy_digits("xauusd"), 3);
+        assert_eq!(dukascopy_digits("EURUSD"), 5);
+    }
+
+    #[test]
+    fn read_fixture() {
+        let reader = Bi5Reader::from_path(fixture()).unwrap();
+        assert_eq!(reader.len(), 5);
+        let ticks: Vec<_> = reader.collect();
+        assert_eq!(
+            ticks[0],
+            DukascopyTick {
+                dt: date("2015-01-02 09:00:00.125"),
+                ask: 120.412,
+                bid: 120.405,
+                ask_volume: 1.5,
+                bid_volume: 2.25,
+            }
+        );
+        assert_eq!(ticks[4].dt, date("2015-01-02 09:59:59.999"));
+
+        // quotes straight into the samplers
+        let mut quotes = QuoteSampler::from_short("M15").unwrap();
+        let mut mid = M15::default();
+        let mut closed = 0;
+        for tick in &ticks {
+            closed += quotes.next_quote(tick.dt, tick.bid, tick.ask).is_some() as usize;
+            mid.next_bar(tick.dt, tick.mid());
+        }
+        assert_eq!(closed, 2);
+        let last = quotes.current_incomplete().unwrap();
+        assert_eq!(last.bid.bar_start, date("2015-01-02 09:45:00"));
+        assert_eq!(last.ask.close, ticks[4].ask);
+        assert_eq!(mid.current_incomplete().unwrap().close, ticks[4].mid());
+    }
+
+    #[test]
+    fn empty_and_damaged() {
+        let hour = date("2015-01-02 09:00:00");
+        assert!(Bi5Reader::new(&[][..], hour, 5).unwrap().is_empty());
+
+        let mut records = [0; RECORD_LEN + 1];
+        records[..4].copy_from_slice(&1_000u32.to_be_bytes());
+        let mut compressed = vec![];
+        lzma_rs::lzma_compress(&mut &records[..], &mut compressed).unwrap();
+        assert!(matches!(
+            Bi5Reader::new(&compressed[..], hour, 5),
+            Err(Bi5Error::Format("truncated record"))
+        ));
+        assert!(matches!(
+            Bi5Reader::new(&compressed[..8], hour, 5),
+            Err(Bi5Error::Lzma(_))
+        ));
+
+        compressed.clear();
+        lzma_rs::lzma_compress(&mut &records[..RECORD_LEN], &mut compressed).unwrap();
+        let tick = Bi5Reader::new(&compressed[..], hour, 5)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(tick.dt, date("2015-01-02 09:00:01"));
+    }
+}
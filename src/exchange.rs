@@ -0,0 +1,378 @@
+use crate::{CsvError, TimeFormat};
+use chrono::prelude::*;
+use std::io::Read;
+
+/// The side that took liquidity in a trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// A trade of an exchange dump with its id and aggressor side
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeTrade {
+    /// Rises with time within a symbol, aggregate trade id for aggTrades
+    pub id: u64,
+    pub dt: NaiveDateTime,
+    pub price: f64,
+    pub quantity: f64,
+    pub side: Side,
+}
+
+impl ExchangeTrade {
+    /// Price times quantity, in the quote currency
+    pub fn notional(&self) -> f64 {
+        self.price * self.quantity
+    }
+
+    /// Quantity, negative when the seller was the aggressor
+    pub fn signed_quantity(&self) -> f64 {
+        match self.side {
+            Side::Buy => self.quantity,
+            Side::Sell => -self.quantity,
+        }
+    }
+}
+
+/// Where a trade file keeps the aggressor side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SideColumn {
+    /// `true` when the buyer was the maker, so the seller was the aggressor
+    BuyerMaker(usize),
+    /// `buy`/`sell` or `b`/`s` in any case
+    Side(usize),
+}
+
+/// Columns of a trade CSV file, by position
+///
+/// A first line with names in the id, price and quantity columns is taken for a
+/// header and skipped, Binance dumps come both with and without one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeFormat {
+    pub delimiter: u8,
+    pub id: usize,
+    pub time: usize,
+    pub price: usize,
+    pub quantity: usize,
+    pub side: SideColumn,
+    pub time_format: TimeFormat,
+}
+
+impl TradeFormat {
+    /// `agg_trade_id,price,quantity,first_trade_id,last_trade_id,transact_time,is_buyer_maker,is_best_match`
+    pub fn binance_agg_trades() -> Self {
+        Self {
+            delimiter: b',',
+            id: 0,
+            time: 5,
+            price: 1,
+            quantity: 2,
+            side: SideColumn::BuyerMaker(6),
+            time_format: TimeFormat::UnixMillis,
+        }
+    }
+
+    /// `id,price,qty,quote_qty,time,is_buyer_maker,is_best_match`
+    pub fn binance_trades() -> Self {
+        Self {
+            delimiter: b',',
+            id: 0,
+            time: 4,
+            price: 1,
+            quantity: 2,
+            side: SideColumn::BuyerMaker(5),
+            time_format: TimeFormat::UnixMillis,
+        }
+    }
+}
+
+/// Streams trades from an exchange CSV dump
+pub struct TradeReader<R> {
+    reader: csv::Reader<R>,
+    record: csv::StringRecord,
+    format: TradeFormat,
+}
+
+impl TradeReader<std::fs::File> {
+    pub fn from_path<Q: AsRef<std::path::Path>>(
+        path: Q,
+        format: &TradeFormat,
+    ) -> Result<Self, CsvError> {
+        Self::new(std::fs::File::open(path).map_err(csv::Error::from)?, format)
+    }
+}
+
+impl<R: Read> TradeReader<R> {
+    pub fn new(reader: R, format: &TradeFormat) -> Result<Self, CsvError> {
+        Ok(Self {
+            reader: csv::ReaderBuilder::new()
+                .delimiter(format.delimiter)
+                .has_headers(false)
+                .flexible(true)
+                .trim(csv::Trim::All)
+                .from_reader(reader),
+            record: csv::StringRecord::new(),
+            format: format.clone(),
+        })
+    }
+
+    fn field(&self, index: usize, column: &'static str) -> Result<&str, CsvError> {
+        self.record
+            .get(index)
+            .ok_or_else(|| self.parse_error(column, ""))
+    }
+
+    fn parse_error(&self, column: &'static str, value: &str) -> CsvError {
+        CsvError::Parse {
+            line: self.line(),
+            column,
+            value: value.to_string(),
+        }
+    }
+
+    fn line(&self) -> u64 {
+        self.record.position().map_or(0, |position| position.line())
+    }
+
+    fn is_header(&self) -> bool {
+        let is_name = |index| {
+            self.record.get(index).is_some_and(|field: &str| {
+                field.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && field
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ' ')
+            })
+        };
+        [self.format.id, self.format.price, self.format.quantity]
+            .iter()
+            .all(|&index| is_name(index))
+    }
+
+    fn parse(&self) -> Result<ExchangeTrade, CsvError> {
+        let id = self.field(self.format.id, "id")?;
+        let id = id.parse().map_err(|_| self.parse_error("id", id))?;
+
+        let time = self.field(self.format.time, "time")?;
+        let dt = self
+            .format
+            .time_format
+            .parse(time)
+            .ok_or_else(|| self.parse_error("time", time))?;
+
+        let price = self.field(self.format.price, "price")?;
+        let price = price
+            .parse()
+            .map_err(|_| self.parse_error("price", price))?;
+
+        let quantity = self.field(self.format.quantity, "quantity")?;
+        let quantity = quantity
+            .parse()
+            .map_err(|_| self.parse_error("quantity", quantity))?;
+
+        let side = match self.format.side {
+            SideColumn::BuyerMaker(index) => {
+                let buyer_maker = self.field(index, "side")?;
+                match buyer_maker.to_ascii_lowercase().as_str() {
+                    "true" | "1" => Side::Sell,
+                    "false" | "0" => Side::Buy,
+                    _ => return Err(self.parse_error("side", buyer_maker)),
+                }
+            }
+            SideColumn::Side(index) => {
+                let side = self.field(index, "side")?;
+                match side.to_ascii_lowercase().as_str() {
+                    "buy" | "b" => Side::Buy,
+                    "sell" | "s" => Side::Sell,
+                    _ => return Err(self.parse_error("side", side)),
+                }
+            }
+        };
+
+        Ok(ExchangeTrade {
+            id,
+            dt,
+            price,
+            quantity,
+            side,
+        })
+    }
+}
+
+impl<R: Read> Iterator for TradeReader<R> {
+    type Item = Result<ExchangeTrade, CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.reader.read_record(&mut self.record) {
+                Ok(true) if self.line() == 1 && self.is_header() => continue,
+                Ok(true) => return Some(self.parse()),
+                Ok(false) => return None,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+/// Drops trades already seen, by id, when dump files overlap
+///
+/// Ids must rise within the stream, as they do per symbol on an exchange.
+#[derive(Debug, Clone, Default)]
+pub struct TradeDedup {
+    last_id: Option<u64>,
+    duplicates: u64,
+}
+
+impl TradeDedup {
+    /// False for a trade with an id at or below the last accepted one
+    pub fn accept(&mut self, trade: &ExchangeTrade) -> bool {
+        if self.last_id.is_some_and(|last_id| trade.id <= last_id) {
+            self.duplicates += 1;
+            return false;
+        }
+        self.last_id = Some(trade.id);
+        true
+    }
+
+    pub fn last_id(&self) -> Option<u64> {
+        self.last_id
+    }
+
+    /// Trades dropped so far
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    const AGG_TRADES: &str = "\
+agg_trade_id,price,quantity,first_trade_id,last_trade_id,transact_time,is_buyer_maker,is_best_match
+26129,0.01633102,4.70443515,27781,27781,1498793709153,true,true
+26130,0.01633100,2.50000000,27782,27783,1498793712410,False,True
+26131,0.01633000,1.00000000,27784,27784,1498793771020,false,true
+";
+
+    const TRADES: &str = "\
+27782,0.01633100,1.50000000,0.02449650,1498793712410,False,True
+27783,0.01633100,1.00000000,0.01633100,1498793712410,False,True
+";
+
+    fn read(data: &str, format: &TradeFormat) -> Vec<ExchangeTrade> {
+        TradeReader::new(data.as_bytes(), format)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn binance_dumps() {
+        let trades = read(AGG_TRADES, &TradeFormat::binance_agg_trades());
+        assert_eq!(trades.len(), 3);
+        assert_eq!(
+            trades[0],
+            ExchangeTrade {
+                id: 26129,
+                dt: date("2017-06-30 03:35:09.153"),
+                price: 0.01633102,
+                quantity: 4.70443515,
+                side: Side::Sell,
+            }
+        );
+        assert_eq!(trades[1].side, Side::Buy);
+        assert_eq!(trades[1].signed_quantity(), 2.5);
+        assert_eq!(trades[0].signed_quantity(), -4.70443515);
+
+        // no header
+        let trades = read(TRADES, &TradeFormat::binance_trades());
+        assert_eq!(
+            trades.iter().map(|t| t.id).collect::<Vec<_>>(),
+            [27782, 27783]
+        );
+        assert_eq!(trades[0].notional(), 0.01633100 * 1.5);
+        assert_eq!(trades[1].dt, date("2017-06-30 03:35:12.410"));
+    }
+
+    #[test]
+    fn generic_format_and_errors() {
+        let format = TradeFormat {
+            delimiter: b';',
+            id: 3,
+            time: 0,
+            price: 1,
+            quantity: 2,
+            side: SideColumn::Side(4),
+            time_format: TimeFormat::Pattern("%Y-%m-%dT%H:%M:%S%.fZ".to_string()),
+        };
+        let data = "2021-03-01T10:00:00.5Z;49000.5;0.25;7;BUY\n\
+                    2021-03-01T10:00:01Z;49001;0.1;8;s\n\
+                    2021-03-01T10:00:02Z;49002;0.1;9;hold\n";
+        let trades: Vec<_> = TradeReader::new(data.as_bytes(), &format)
+            .unwrap()
+            .collect();
+        assert_eq!(trades[0].as_ref().unwrap().side, Side::Buy);
+        assert_eq!(trades[1].as_ref().unwrap().side, Side::Sell);
+        assert_eq!(
+            trades[2].as_ref().unwrap_err().to_string(),
+            "line 3: invalid side: \"hold\""
+        );
+
+        let data = "time;price;qty;id;side\n2021-03-01T10:00:00Z;1;1;1;buy\n";
+        let trades: Vec<_> = TradeReader::new(data.as_bytes(), &format)
+            .unwrap()
+            .collect();
+        assert_eq!(trades.len(), 1);
+        assert!(trades[0].is_ok());
+
+        // a broken first line isn't a header
+        let data =
+            "26129,0.1,1 x,1,1,1498793709153,true,true\n26130,0.1,x,1,1,1498793709153,true,true\n";
+        let trades: Vec<_> = TradeReader::new(data.as_bytes(), &TradeFormat::binance_agg_trades())
+            .unwrap()
+            .collect();
+        assert_eq!(
+            trades[0].as_ref().unwrap_err().to_string(),
+            "line 1: invalid quantity: \"1 x\""
+        );
+        assert_eq!(
+            trades[1].as_ref().unwrap_err().to_string(),
+            "line 2: invalid quantity: \"x\""
+        );
+    }
+
+    #[test]
+    fn overlapping_dumps() {
+        let format = TradeFormat::binance_agg_trades();
+        let first = read(AGG_TRADES, &format);
+        // the next dump starts one trade early
+        let second = "26130,0.01633100,2.50000000,27782,27783,1498793712410,false,true\n\
+                      26132,0.01633200,3.00000000,27785,27786,1498793830000,true,true\n";
+        let second = read(second, &format);
+
+        let mut dedup = TradeDedup::default();
+        let mut sampler = TradeSampler::new(M1::default());
+        let mut bars = vec![];
+        let mut order_flow = 0.;
+        for trade in first.iter().chain(&second) {
+            if dedup.accept(trade) {
+                order_flow += trade.signed_quantity();
+                bars.extend(sampler.next_trade(trade.dt, trade.price, trade.quantity));
+            }
+        }
+        assert_eq!(dedup.duplicates(), 1);
+        assert_eq!(dedup.last_id(), Some(26132));
+        assert_eq!(order_flow, -4.70443515 + 2.5 + 1. - 3.);
+        assert_eq!(bars.len(), 2);
+        match &bars[0] {
+            TradeBars::Single(bar) => assert_eq!(bar.volume, 4.70443515 + 2.5),
+            bars => panic!("unexpected {:?}", bars),
+        }
+    }
+}
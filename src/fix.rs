@@ -0,0 +1,644 @@
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+
+const SOH: u8 = 0x01;
+
+/// A tick out of FIX market data
+#[derive(Debug, Clone, PartialEq)]
+pub enum FixTick {
+    Trade {
+        symbol: String,
+        dt: NaiveDateTime,
+        price: f64,
+        size: f64,
+    },
+    /// The top of book after it changed
+    Quote {
+        symbol: String,
+        dt: NaiveDateTime,
+        bid: f64,
+        ask: f64,
+    },
+}
+
+/// A price level of the book
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    pub price: f64,
+    pub size: f64,
+}
+
+/// Best bid and offer of a symbol, either side may be empty
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TopOfBook {
+    pub bid: Option<Level>,
+    pub ask: Option<Level>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixError {
+    /// A message that isn't `tag=value` fields ended by SOH
+    Format(&'static str),
+    BodyLength {
+        declared: usize,
+        actual: usize,
+    },
+    Checksum {
+        declared: u8,
+        actual: u8,
+    },
+    MissingTag(u32),
+    InvalidValue {
+        tag: u32,
+        value: String,
+    },
+}
+
+impl fmt::Display for FixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FixError::Format(reason) => write!(f, "invalid FIX message: {}", reason),
+            FixError::BodyLength { declared, actual } => {
+                write!(f, "body length is {}, not {}", actual, declared)
+            }
+            FixError::Checksum { declared, actual } => {
+                write!(f, "checksum is {:03}, not {:03}", actual, declared)
+            }
+            FixError::MissingTag(tag) => write!(f, "missing tag {}", tag),
+            FixError::InvalidValue { tag, value } => {
+                write!(f, "invalid value of tag {}: {:?}", tag, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FixError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Bid,
+    Offer,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Book {
+    bids: Vec<Level>,
+    offers: Vec<Level>,
+    // price of each MDEntryID, for updates that leave the price out
+    ids: HashMap<String, (Side, f64)>,
+    // last quote emitted
+    quoted: Option<(f64, f64)>,
+}
+
+impl Book {
+    fn levels(&mut self, side: Side) -> &mut Vec<Level> {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Offer => &mut self.offers,
+        }
+    }
+
+    fn set(&mut self, side: Side, price: f64, size: f64) {
+        let levels = self.levels(side);
+        match levels.iter_mut().find(|level| level.price == price) {
+            Some(level) => level.size = size,
+            None => levels.push(Level { price, size }),
+        }
+    }
+
+    fn delete(&mut self, side: Side, price: f64) {
+        self.levels(side).retain(|level| level.price != price);
+    }
+
+    fn top(&self) -> TopOfBook {
+        let best = |levels: &[Level], better: fn(f64, f64) -> bool| {
+            levels
+                .iter()
+                .copied()
+                .fold(None, |best: Option<Level>, level| match best {
+                    Some(best) if !better(level.price, best.price) => Some(best),
+                    _ => Some(level),
+                })
+        };
+        TopOfBook {
+            bid: best(&self.bids, |a, b| a > b),
+            ask: best(&self.offers, |a, b| a < b),
+        }
+    }
+}
+
+/// Keeps the books of FIX 4.4 market data and turns its messages into ticks
+///
+/// Takes MarketDataSnapshotFullRefresh (`35=W`) and MarketDataIncrementalRefresh
+/// (`35=X`) messages and skips any other type. Books are the bid (`269=0`) and
+/// offer (`269=1`) levels by price. Trades (`269=2`) come from incremental
+/// refreshes only, the last trade of a snapshot is one already seen. Ticks are
+/// stamped with MDEntryDate/MDEntryTime when present, SendingTime otherwise. An
+/// MDEntryTime without MDEntryDate is taken within 12 hours of SendingTime.
+/// Prices and sizes must be finite.
+#[derive(Debug, Default)]
+pub struct FixMarketData {
+    books: HashMap<String, Book>,
+}
+
+impl FixMarketData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the framing of one message and applies it to the books
+    ///
+    /// A message with any invalid entry leaves the books as they were.
+    pub fn parse(&mut self, message: &[u8]) -> Result<Vec<FixTick>, FixError> {
+        let fields = fields(message)?;
+        let value = |tag: u32| {
+            fields
+                .iter()
+                .find(|(t, _)| *t == tag)
+                .map(|(_, value)| *value)
+        };
+        match value(35) {
+            Some("W") | Some("X") => {}
+            Some(_) => return Ok(vec![]),
+            None => return Err(FixError::MissingTag(35)),
+        }
+        let sending_time = value(52).ok_or(FixError::MissingTag(52))?;
+        let sending_time = NaiveDateTime::parse_from_str(sending_time, "%Y%m%d-%H:%M:%S%.f")
+            .map_err(|_| invalid(52, sending_time))?;
+
+        let snapshot = value(35) == Some("W");
+        // the first tag of an entry of the NoMDEntries group
+        let first = if snapshot { 269 } else { 279 };
+        let group = fields
+            .iter()
+            .position(|(tag, _)| *tag == 268)
+            .ok_or(FixError::MissingTag(268))?;
+        let mut entries: Vec<Vec<(u32, &str)>> = vec![];
+        for &(tag, value) in &fields[group + 1..] {
+            match (tag, entries.last_mut()) {
+                (10, _) => break,
+                (tag, _) if tag == first => entries.push(vec![(tag, value)]),
+                (_, Some(entry)) => entry.push((tag, value)),
+                (tag, None) => return Err(invalid(tag, value)),
+            }
+        }
+        let count = fields[group].1;
+        if count.parse::<usize>().ok() != Some(entries.len()) {
+            return Err(invalid(268, count));
+        }
+
+        // entries apply to copies of the books they touch, which replace the books
+        // once every entry is valid
+        let mut staged: HashMap<String, Book> = HashMap::new();
+        let symbol = value(55);
+        if snapshot {
+            let symbol = symbol.ok_or(FixError::MissingTag(55))?;
+            let book = stage(&mut staged, &self.books, symbol);
+            book.bids.clear();
+            book.offers.clear();
+            book.ids.clear();
+        }
+        let mut ticks = vec![];
+        let mut touched: Vec<(String, NaiveDateTime)> = vec![];
+        for entry in &entries {
+            let field = |tag: u32| entry.iter().find(|(t, _)| *t == tag).map(|(_, v)| *v);
+            let number = |tag: u32| {
+                field(tag)
+                    .map(|value| {
+                        value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|number| number.is_finite())
+                            .ok_or_else(|| invalid(tag, value))
+                    })
+                    .transpose()
+            };
+            let symbol = field(55)
+                .or(symbol)
+                .ok_or(FixError::MissingTag(55))?
+                .to_string();
+            let dt = entry_time(field(272), field(273), sending_time)?;
+            let action = if snapshot { "0" } else { field(279).unwrap() };
+            let entry_type = field(269).ok_or(FixError::MissingTag(269))?;
+            let side = match entry_type {
+                "0" => Side::Bid,
+                "1" => Side::Offer,
+                "2" => {
+                    if !snapshot && action == "0" {
+                        ticks.push(FixTick::Trade {
+                            symbol,
+                            dt,
+                            price: number(270)?.ok_or(FixError::MissingTag(270))?,
+                            size: number(271)?.unwrap_or(0.),
+                        });
+                    }
+                    continue;
+                }
+                // imbalances, statistics and the like
+                _ => continue,
+            };
+
+            let book = stage(&mut staged, &self.books, &symbol);
+            let id = field(278);
+            let known = id.and_then(|id| book.ids.get(id)).map(|(_, price)| *price);
+            let price = match (number(270)?, known) {
+                (Some(price), _) | (None, Some(price)) => price,
+                (None, None) => return Err(FixError::MissingTag(270)),
+            };
+            match action {
+                "0" | "1" => {
+                    // a changed price moves the entry to another level
+                    if let Some(known) = known.filter(|known| *known != price) {
+                        book.delete(side, known);
+                    }
+                    let size = number(271)?.ok_or(FixError::MissingTag(271))?;
+                    book.set(side, price, size);
+                    if let Some(id) = id {
+                        book.ids.insert(id.to_string(), (side, price));
+                    }
+                }
+                "2" => {
+                    book.delete(side, price);
+                    if let Some(id) = id {
+                        book.ids.remove(id);
+                    }
+                }
+                _ => return Err(invalid(279, action)),
+            }
+            match touched.iter_mut().find(|(touched, _)| *touched == symbol) {
+                Some(touched) => touched.1 = touched.1.max(dt),
+                None => touched.push((symbol, dt)),
+            }
+        }
+
+        for (symbol, dt) in touched {
+            let book = staged.get_mut(&symbol).unwrap();
+            if let TopOfBook {
+                bid: Some(bid),
+                ask: Some(ask),
+            } = book.top()
+            {
+                if book.quoted != Some((bid.price, ask.price)) {
+                    book.quoted = Some((bid.price, ask.price));
+                    ticks.push(FixTick::Quote {
+                        symbol,
+                        dt,
+                        bid: bid.price,
+                        ask: ask.price,
+                    });
+                }
+            }
+        }
+        self.books.extend(staged);
+        Ok(ticks)
+    }
+
+    /// None for a symbol without market data yet
+    pub fn top(&self, symbol: &str) -> Option<TopOfBook> {
+        self.books.get(symbol).map(Book::top)
+    }
+}
+
+fn stage<'a>(
+    staged: &'a mut HashMap<String, Book>,
+    books: &HashMap<String, Book>,
+    symbol: &str,
+) -> &'a mut Book {
+    staged
+        .entry(symbol.to_string())
+        .or_insert_with(|| books.get(symbol).cloned().unwrap_or_default())
+}
+
+fn invalid(tag: u32, value: &str) -> FixError {
+    FixError::InvalidValue {
+        tag,
+        value: value.to_string(),
+    }
+}
+
+// the fields of a message, after checking BodyLength and CheckSum
+fn fields(message: &[u8]) -> Result<Vec<(u32, &str)>, FixError> {
+    if message.last() != Some(&SOH) {
+        return Err(FixError::Format("no SOH at the end"));
+    }
+    let mut fields = vec![];
+    let mut at = 0;
+    for field in message[..message.len() - 1].split(|b| *b == SOH) {
+        let field = std::str::from_utf8(field).map_err(|_| FixError::Format("not UTF-8"))?;
+        let (tag, value) = field
+            .split_once('=')
+            .ok_or(FixError::Format("field without '='"))?;
+        let tag = tag.parse().map_err(|_| FixError::Format("bad tag"))?;
+        fields.push((tag, value, at));
+        at += field.len() + 1;
+    }
+
+    match fields[..] {
+        [(8, _, _), (9, length, _), .., (10, checksum, checksum_at)] => {
+            let declared = length.parse().map_err(|_| invalid(9, length))?;
+            // from after the BodyLength field up to the CheckSum field
+            let actual = checksum_at - fields[2].2;
+            if declared != actual {
+                return Err(FixError::BodyLength { declared, actual });
+            }
+            let declared = checksum.parse().map_err(|_| invalid(10, checksum))?;
+            let actual = message[..checksum_at]
+                .iter()
+                .fold(0u8, |sum, b| sum.wrapping_add(*b));
+            if declared != actual {
+                return Err(FixError::Checksum { declared, actual });
+            }
+        }
+        _ => return Err(FixError::Format("no BeginString, BodyLength or CheckSum")),
+    }
+    Ok(fields
+        .into_iter()
+        .map(|(tag, value, _)| (tag, value))
+        .collect())
+}
+
+// MDEntryDate and MDEntryTime, falling back on SendingTime. A time without a
+// date takes the day that puts it within 12 hours of SendingTime, so an entry at
+// 23:59:59 sent at 00:00:01 stays on the previous day.
+fn entry_time(
+    date: Option<&str>,
+    time: Option<&str>,
+    sending_time: NaiveDateTime,
+) -> Result<NaiveDateTime, FixError> {
+    let time = match time {
+        Some(time) => {
+            NaiveTime::parse_from_str(time, "%H:%M:%S%.f").map_err(|_| invalid(273, time))?
+        }
+        None => return Ok(sending_time),
+    };
+    match date {
+        Some(date) => Ok(NaiveDate::parse_from_str(date, "%Y%m%d")
+            .map_err(|_| invalid(272, date))?
+            .and_time(time)),
+        None => {
+            let dt = sending_time.date().and_time(time);
+            let half_day = chrono::Duration::hours(12);
+            Ok(if dt - sending_time > half_day {
+                dt - chrono::Duration::days(1)
+            } else if sending_time - dt > half_day {
+                dt + chrono::Duration::days(1)
+            } else {
+                dt
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    // frames `|`-separated fields after BodyLength with SOH, BodyLength and CheckSum
+    fn message(body: &str) -> Vec<u8> {
+        let body = format!("{}|", body).replace('|', "\x01");
+        let mut message = format!("8=FIX.4.4\x019={}\x01{}", body.len(), body).into_bytes();
+        let checksum = message.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        message.extend(format!("10={:03}\x01", checksum).into_bytes());
+        message
+    }
+
+    #[test]
+    fn raw_snapshot() {
+        let raw = "8=FIX.4.4\x019=161\x0135=W\x0149=VENUE\x0156=CLIENT\x0134=2\x01\
+                   52=20150101-10:00:00.250\x0155=EUR/USD\x01268=3\x01\
+                   269=0\x01270=1.20010\x01271=1000000\x01\
+                   269=1\x01270=1.20030\x01271=2000000\x01\
+                   269=0\x01270=1.20000\x01271=5000000\x0110=058\x01";
+        let mut market_data = FixMarketData::new();
+        assert_eq!(
+            market_data.parse(raw.as_bytes()).unwrap(),
+            vec![FixTick::Quote {
+                symbol: "EUR/USD".to_string(),
+                dt: date("2015-01-01 10:00:00.250"),
+                bid: 1.2001,
+                ask: 1.2003,
+            }]
+        );
+        assert_eq!(
+            market_data.top("EUR/USD").unwrap().bid,
+            Some(Level {
+                price: 1.2001,
+                size: 1e6,
+            })
+        );
+        assert_eq!(market_data.top("GBP/USD"), None);
+    }
+
+    #[test]
+    fn incremental_refresh() {
+        let mut market_data = FixMarketData::new();
+        market_data
+            .parse(&message(
+                "35=W|34=2|52=20150101-10:00:00|55=EURUSD|268=2|\
+                 269=0|270=1.2001|271=1000000|278=b1|269=1|270=1.2003|271=1000000|278=a1",
+            ))
+            .unwrap();
+
+        // a better bid, a trade and a delete of the best offer that leaves the deeper one
+        let ticks = market_data
+            .parse(&message(
+                "35=X|34=3|52=20150101-10:00:01.500|268=4|\
+                 279=0|269=0|55=EURUSD|270=1.2002|271=500000|278=b2|\
+                 279=0|269=1|55=EURUSD|270=1.2005|271=3000000|278=a2|\
+                 279=0|269=2|55=EURUSD|270=1.2003|271=250000|273=10:00:01.250|\
+                 279=2|269=1|55=EURUSD|278=a1",
+            ))
+            .unwrap();
+        assert_eq!(
+            ticks,
+            vec![
+                FixTick::Trade {
+                    symbol: "EURUSD".to_string(),
+                    dt: date("2015-01-01 10:00:01.250"),
+                    price: 1.2003,
+                    size: 250000.,
+                },
+                FixTick::Quote {
+                    symbol: "EURUSD".to_string(),
+                    dt: date("2015-01-01 10:00:01.500"),
+                    bid: 1.2002,
+                    ask: 1.2005,
+                },
+            ]
+        );
+
+        // a size change doesn't move the top, a price change of b2 does
+        let ticks = market_data
+            .parse(&message(
+                "35=X|34=4|52=20150101-10:00:02|268=1|279=1|269=0|55=EURUSD|270=1.2002|271=9|278=b2",
+            ))
+            .unwrap();
+        assert!(ticks.is_empty());
+        let ticks = market_data
+            .parse(&message(
+                "35=X|34=5|52=20150101-10:00:03|268=1|279=1|269=0|55=EURUSD|270=1.1999|271=9|278=b2",
+            ))
+            .unwrap();
+        assert_eq!(
+            ticks,
+            vec![FixTick::Quote {
+                symbol: "EURUSD".to_string(),
+                dt: date("2015-01-01 10:00:03"),
+                bid: 1.2001,
+                ask: 1.2005,
+            }]
+        );
+
+        // heartbeats are skipped
+        assert!(market_data
+            .parse(&message("35=0|34=6|52=20150101-10:00:04"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn ticks_feed_samplers() {
+        let mut market_data = FixMarketData::new();
+        let mut quotes = QuoteSampler::from_short("M1").unwrap();
+        let mut trades = TradeSampler::from_short("M1").unwrap();
+        let mut closed = 0;
+        let updates = [
+            "35=W|52=20150101-10:00:00|55=X|268=2|269=0|270=10|271=1|269=1|270=11|271=1",
+            "35=X|52=20150101-10:00:30|268=1|279=0|269=2|55=X|270=10.5|271=3",
+            "35=X|52=20150101-10:01:00|268=1|279=0|269=0|55=X|270=10.25|271=1",
+            "35=X|52=20150101-10:01:10|268=1|279=0|269=2|55=X|270=10.75|271=2",
+        ];
+        for update in &updates {
+            for tick in market_data.parse(&message(update)).unwrap() {
+                match tick {
+                    FixTick::Quote { dt, bid, ask, .. } => {
+                        closed += quotes.next_quote(dt, bid, ask).is_some() as usize;
+                    }
+                    FixTick::Trade {
+                        dt, price, size, ..
+                    } => {
+                        closed += trades.next_trade(dt, price, size).is_some() as usize;
+                    }
+                }
+            }
+        }
+        assert_eq!(closed, 2);
+        assert_eq!(quotes.current_incomplete().unwrap().bid.open, 10.25);
+        assert_eq!(trades.current_incomplete().unwrap().volume, 2.);
+    }
+
+    #[test]
+    fn entry_time_around_midnight() {
+        let sent = date("2015-01-02 00:00:01");
+        assert_eq!(
+            entry_time(None, Some("23:59:59.5"), sent),
+            Ok(date("2015-01-01 23:59:59.5"))
+        );
+        assert_eq!(
+            entry_time(None, Some("00:00:02"), date("2015-01-01 23:59:58")),
+            Ok(date("2015-01-02 00:00:02"))
+        );
+        assert_eq!(
+            entry_time(Some("20150102"), Some("23:59:59"), sent),
+            Ok(date("2015-01-02 23:59:59"))
+        );
+        assert_eq!(
+            entry_time(None, Some("09:00:00"), sent),
+            Ok(date("2015-01-02 09:00:00"))
+        );
+        assert_eq!(entry_time(None, None, sent), Ok(sent));
+    }
+
+    #[test]
+    fn invalid_entry_leaves_books() {
+        let mut market_data = FixMarketData::new();
+        market_data
+            .parse(&message(
+                "35=W|52=20150101-10:00:00|55=X|268=2|269=0|270=10|271=1|269=1|270=11|271=1",
+            ))
+            .unwrap();
+        let top = market_data.top("X");
+
+        for body in [
+            // a snapshot that would clear the book
+            "35=W|52=20150101-10:00:01|55=X|268=2|269=0|270=9|271=1|269=1|270=12",
+            "35=X|52=20150101-10:00:01|268=2|\
+             279=0|269=0|55=X|270=10.5|271=1|279=0|269=1|55=X|270=NaN|271=1",
+            "35=X|52=20150101-10:00:01|268=3|279=2|269=1|55=X|270=11|\
+             279=0|269=2|55=X|270=10.5|271=1|279=9|269=0|55=X|270=10.5|271=1",
+            "35=X|52=20150101-10:00:01|268=2|\
+             279=0|269=0|55=Y|270=1|271=1|279=0|269=0|55=X|270=10.5|271=1|273=25:00:00",
+        ] {
+            assert!(market_data.parse(&message(body)).is_err(), "{}", body);
+            assert_eq!(market_data.top("X"), top);
+            assert_eq!(market_data.top("Y"), None);
+        }
+
+        let ticks = market_data
+            .parse(&message(
+                "35=X|52=20150101-10:00:02|268=1|279=0|269=0|55=X|270=10.5|271=1",
+            ))
+            .unwrap();
+        assert_eq!(ticks.len(), 1);
+    }
+
+    #[test]
+    fn errors() {
+        let mut market_data = FixMarketData::new();
+        let good = message("35=W|52=20150101-10:00:00|55=X|268=1|269=0|270=10|271=1");
+        assert!(market_data.parse(&good).is_ok());
+        assert_eq!(
+            market_data.parse(&good[..good.len() - 1]),
+            Err(FixError::Format("no SOH at the end"))
+        );
+
+        let mut bad_checksum = good.clone();
+        let at = bad_checksum.len() - 2;
+        bad_checksum[at] = if bad_checksum[at] == b'0' { b'1' } else { b'0' };
+        assert!(matches!(
+            market_data.parse(&bad_checksum),
+            Err(FixError::Checksum { .. })
+        ));
+
+        let long = String::from_utf8(good).unwrap().replace("9=", "9=1");
+        assert!(matches!(
+            market_data.parse(long.as_bytes()),
+            Err(FixError::BodyLength { .. })
+        ));
+
+        for (body, error) in [
+            (
+                "35=W|52=20150101-10:00:00|55=X|268=2|269=0|270=10|271=1",
+                "invalid value of tag 268: \"2\"",
+            ),
+            ("35=W|55=X|268=0", "missing tag 52"),
+            (
+                "35=X|52=20150101-10:00:00|268=1|279=2|269=0|55=X",
+                "missing tag 270",
+            ),
+            (
+                "35=X|52=20150101-10:00:00|268=1|279=0|269=1|55=X|270=x|271=1",
+                "invalid value of tag 270: \"x\"",
+            ),
+            (
+                "35=X|52=20150101-10:00:00|268=1|279=0|269=1|55=X|270=NaN|271=1",
+                "invalid value of tag 270: \"NaN\"",
+            ),
+            (
+                "35=X|52=20150101-10:00:00|268=1|279=0|269=2|55=X|270=10|271=inf",
+                "invalid value of tag 271: \"inf\"",
+            ),
+        ] {
+            assert_eq!(
+                market_data.parse(&message(body)).unwrap_err().to_string(),
+                error
+            );
+        }
+    }
+}
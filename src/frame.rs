@@ -0,0 +1,383 @@
+use crate::{BarColumns, BatchSampler, Closed, Label, Origin, PricePolicy, Session};
+use chrono::prelude::*;
+use polars::prelude::*;
+
+/// Resamples a polars `DataFrame` of ticks into OHLCV bars
+///
+/// Bars are those the timeframe's sampler emits from `next_bar`, followed by the
+/// incomplete one. Ticks have a datetime column read as UTC, a price column cast to
+/// `Float64` and an optional volume column. A null price counts as NaN, a null volume
+/// as zero.
+#[derive(Debug, Clone)]
+pub struct FrameResampler {
+    short: String,
+    time: String,
+    price: String,
+    volume: String,
+    policy: PricePolicy,
+    closed: Closed,
+    origin: Origin,
+    label: Label,
+    session: Option<Session>,
+    skip_empty: bool,
+    incomplete: bool,
+}
+
+impl FrameResampler {
+    /// Returns None if the timeframe is unknown to `Sampler::from_short`
+    pub fn new(short: &str) -> Option<Self> {
+        <dyn BatchSampler>::from_short(short)?;
+        Some(Self {
+            short: short.to_string(),
+            time: "time".to_string(),
+            price: "price".to_string(),
+            volume: "volume".to_string(),
+            policy: PricePolicy::default(),
+            closed: Closed::default(),
+            origin: Origin::default(),
+            label: Label::default(),
+            session: None,
+            skip_empty: false,
+            incomplete: true,
+        })
+    }
+
+    /// Names of the tick columns, the volume column may be missing from the frame
+    pub fn columns(mut self, time: &str, price: &str, volume: &str) -> Self {
+        self.time = time.to_string();
+        self.price = price.to_string();
+        self.volume = volume.to_string();
+        self
+    }
+
+    pub fn policy(mut self, policy: PricePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn closed(mut self, closed: Closed) -> Self {
+        self.closed = closed;
+        self
+    }
+
+    pub fn origin(mut self, origin: Origin) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Which end of the bar the `time` column holds
+    pub fn label(mut self, label: Label) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Drops the ticks outside of `session`, and the empty bars of periods it
+    /// doesn't overlap
+    pub fn session(mut self, session: Session) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Leaves out the flat bars of periods without ticks
+    pub fn skip_empty(mut self, skip_empty: bool) -> Self {
+        self.skip_empty = skip_empty;
+        self
+    }
+
+    /// Whether the bar of the last tick is included, true by default
+    pub fn incomplete(mut self, incomplete: bool) -> Self {
+        self.incomplete = incomplete;
+        self
+    }
+
+    /// Returns `time`, `open`, `high`, `low`, `close`, `volume` and `vwap` columns,
+    /// times in nanoseconds without a timezone
+    pub fn resample(&self, ticks: &DataFrame) -> PolarsResult<DataFrame> {
+        let time = ticks
+            .column(&self.time)?
+            .cast(&DataType::Datetime(TimeUnit::Nanoseconds, None))?;
+        let price = ticks.column(&self.price)?.cast(&DataType::Float64)?;
+        let volume = match ticks.column(&self.volume) {
+            Ok(volume) => Some(volume.cast(&DataType::Float64)?),
+            Err(_) => None,
+        };
+
+        let mut dt = Vec::with_capacity(ticks.height());
+        let mut prices = Vec::with_capacity(ticks.height());
+        let mut volumes = Vec::with_capacity(ticks.height());
+        let volume_values = volume.as_ref().map(|volume| volume.f64()).transpose()?;
+        let mut volume_values = volume_values.map(|volume| volume.into_iter());
+        for (row, (nanos, price)) in time.datetime()?.into_iter().zip(price.f64()?).enumerate() {
+            let volume = volume_values
+                .as_mut()
+                .and_then(|volume| volume.next())
+                .flatten()
+                .unwrap_or(0.);
+            let tick_dt = nanos
+                .and_then(from_nanos)
+                .ok_or_else(|| polars_err!(ComputeError: "row {}: invalid time", row))?;
+            if let Some(session) = &self.session {
+                if !session.contains(tick_dt) {
+                    continue;
+                }
+            }
+            dt.push(tick_dt);
+            prices.push(price.unwrap_or(f64::NAN));
+            volumes.push(volume);
+        }
+
+        let mut sampler = <dyn BatchSampler>::from_short(&self.short).unwrap();
+        sampler.set_policy(self.policy);
+        sampler.set_closed(self.closed);
+        sampler.set_origin(self.origin);
+        let mut bars = BarColumns::default();
+        sampler
+            .resample(&dt, &prices, Some(&volumes), &mut bars)
+            .map_err(|err| polars_err!(ComputeError: "{}", err))?;
+        if self.incomplete {
+            if let Some(bar) = sampler.current_incomplete() {
+                bars.push(bar);
+            }
+        }
+        self.to_frame(bars)
+    }
+
+    fn to_frame(&self, bars: BarColumns) -> PolarsResult<DataFrame> {
+        let outside_session = |i: usize| {
+            self.session
+                .as_ref()
+                .is_some_and(|session| !session.overlaps(bars.bar_start[i], bars.next_bar_dt[i]))
+        };
+        let keep: Vec<_> = (0..bars.empty.len())
+            .map(|i| !bars.empty[i] || !(self.skip_empty || outside_session(i)))
+            .collect();
+        let label = match self.label {
+            Label::Open => &bars.bar_start,
+            Label::Close => &bars.next_bar_dt,
+        };
+        let time = kept(label, &keep)
+            .into_iter()
+            .map(|dt| {
+                dt.and_utc()
+                    .timestamp_nanos_opt()
+                    .ok_or_else(|| polars_err!(ComputeError: "{} is out of range", dt))
+            })
+            .collect::<PolarsResult<Vec<_>>>()?;
+        let float = |name: &str, values: &[f64]| Series::new(name, kept(values, &keep));
+
+        DataFrame::new(vec![
+            Int64Chunked::from_vec("time", time)
+                .into_datetime(TimeUnit::Nanoseconds, None)
+                .into_series(),
+            float("open", &bars.open),
+            float("high", &bars.high),
+            float("low", &bars.low),
+            float("close", &bars.close),
+            float("volume", &bars.volume),
+            float("vwap", &bars.vwap),
+        ])
+    }
+}
+
+/// Shortcut for `FrameResampler` with the default settings
+pub fn resample_frame(ticks: &DataFrame, short: &str) -> PolarsResult<DataFrame> {
+    FrameResampler::new(short)
+        .ok_or_else(|| polars_err!(InvalidOperation: "unknown timeframe: {}", short))?
+        .resample(ticks)
+}
+
+fn kept<T: Copy>(values: &[T], keep: &[bool]) -> Vec<T> {
+    values
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| **keep)
+        .map(|(value, _)| *value)
+        .collect()
+}
+
+fn from_nanos(nanos: i64) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(
+        nanos.div_euclid(1_000_000_000),
+        nanos.rem_euclid(1_000_000_000) as u32,
+    )
+    .map(|dt| dt.naive_utc())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Sampler;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn ticks(time: &[&str], price: &[f64]) -> DataFrame {
+        let millis: Vec<_> = time
+            .iter()
+            .map(|dt| date(dt).and_utc().timestamp_millis())
+            .collect();
+        DataFrame::new(vec![
+            Int64Chunked::from_vec("ts", millis)
+                .into_datetime(TimeUnit::Milliseconds, None)
+                .into_series(),
+            Series::new("px", price),
+        ])
+        .unwrap()
+    }
+
+    fn times(frame: &DataFrame) -> Vec<NaiveDateTime> {
+        frame
+            .column("time")
+            .unwrap()
+            .datetime()
+            .unwrap()
+            .into_iter()
+            .map(|nanos| from_nanos(nanos.unwrap()).unwrap())
+            .collect()
+    }
+
+    fn floats(frame: &DataFrame, name: &str) -> Vec<f64> {
+        frame
+            .column(name)
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect()
+    }
+
+    #[test]
+    fn matches_next_bar() {
+        let time = [
+            "2015-01-01 10:00:00",
+            "2015-01-01 10:03:00",
+            "2015-01-01 10:05:00",
+            "2015-01-01 10:21:00",
+            "2015-01-01 10:22:00",
+        ];
+        let price = [1., 3., 2., 5., 4.];
+        let frame = FrameResampler::new("M5")
+            .unwrap()
+            .columns("ts", "px", "qty")
+            .closed(Closed::Right)
+            .resample(&ticks(&time, &price))
+            .unwrap();
+
+        let mut sampler = <dyn Sampler>::from_short("M5").unwrap();
+        sampler.set_closed(Closed::Right);
+        let mut expected = vec![];
+        for (dt, price) in time.iter().zip(&price) {
+            if let Some(bars) = sampler.next_bar(date(dt), *price) {
+                let (bar, empty) = bars.into_parts();
+                expected.push(bar);
+                expected.extend(empty);
+            }
+        }
+        expected.extend(sampler.current_incomplete());
+
+        assert_eq!(
+            times(&frame),
+            expected.iter().map(|bar| bar.bar_start).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            floats(&frame, "close"),
+            expected.iter().map(|bar| bar.close).collect::<Vec<_>>()
+        );
+        assert_eq!(floats(&frame, "volume"), vec![0.; expected.len()]);
+        assert_eq!(frame.height(), 6);
+    }
+
+    #[test]
+    fn session_label_and_gaps() {
+        let ticks = ticks(
+            &[
+                "2021-01-08 09:00:00",
+                "2021-01-08 09:40:00",
+                "2021-01-08 11:10:00",
+                "2021-01-08 16:30:00",
+            ],
+            &[1., 2., 3., 4.],
+        );
+        let resampler = FrameResampler::new("H1")
+            .unwrap()
+            .columns("ts", "px", "qty")
+            .session("09:30-16:00".parse().unwrap())
+            .label(Label::Close);
+
+        let frame = resampler.clone().resample(&ticks).unwrap();
+        assert_eq!(
+            times(&frame),
+            vec![
+                date("2021-01-08 10:00:00"),
+                date("2021-01-08 11:00:00"),
+                date("2021-01-08 12:00:00"),
+            ]
+        );
+        assert_eq!(floats(&frame, "open"), vec![2., 2., 3.]);
+
+        let frame = resampler
+            .skip_empty(true)
+            .incomplete(false)
+            .resample(&ticks)
+            .unwrap();
+        assert_eq!(times(&frame), vec![date("2021-01-08 10:00:00")]);
+    }
+
+    #[test]
+    fn no_empty_bars_outside_session() {
+        let ticks = ticks(
+            &[
+                "2021-01-07 14:10:00",
+                "2021-01-07 15:10:00",
+                "2021-01-08 09:40:00",
+                "2021-01-08 11:10:00",
+            ],
+            &[1., 2., 3., 4.],
+        );
+        let frame = FrameResampler::new("H1")
+            .unwrap()
+            .columns("ts", "px", "qty")
+            .session("09:30-16:00".parse().unwrap())
+            .resample(&ticks)
+            .unwrap();
+        // nothing from 16:00 to 09:00 overnight, 10:00 is empty within the session
+        assert_eq!(
+            times(&frame),
+            vec![
+                date("2021-01-07 14:00:00"),
+                date("2021-01-07 15:00:00"),
+                date("2021-01-08 09:00:00"),
+                date("2021-01-08 10:00:00"),
+                date("2021-01-08 11:00:00"),
+            ]
+        );
+        assert_eq!(floats(&frame, "close"), vec![1., 2., 3., 3., 4.]);
+    }
+
+    #[test]
+    fn errors() {
+        let ticks = ticks(&["2015-01-01 10:00:00"], &[f64::NAN]);
+        assert!(resample_frame(&ticks, "X1")
+            .unwrap_err()
+            .to_string()
+            .contains("unknown timeframe: X1"));
+        // no "time" column
+        assert!(resample_frame(&ticks, "M1").is_err());
+
+        let resampler = FrameResampler::new("M1")
+            .unwrap()
+            .columns("ts", "px", "qty");
+        assert!(resampler
+            .clone()
+            .resample(&ticks)
+            .unwrap_err()
+            .to_string()
+            .contains("row 0: non-finite price: NaN"));
+        let frame = resampler
+            .policy(PricePolicy::Skip)
+            .resample(&ticks)
+            .unwrap();
+        assert_eq!(frame.height(), 0);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{Bar, Bars, Closed, Origin, Price, PriceError, PricePolicy, Sampler};
+use chrono::prelude::*;
+use std::collections::VecDeque;
+use std::ops::Index;
+
+/// Keeps the last `capacity` closed bars of a sampler, empty ones included
+///
+/// Bars are indexed from the most recent, `history[0]` is the last closed bar.
+/// Column views are ordered the same way. Being a sampler itself, it takes the
+/// place of the one it wraps.
+#[derive(Debug, Clone)]
+pub struct History<S, P = f64> {
+    sampler: S,
+    bars: VecDeque<Bar<P>>,
+    columns: Option<Columns<P>>,
+    capacity: usize,
+}
+
+// every value is written twice, `capacity` apart, so the last `len` values
+// are always contiguous from `head`, newest first
+#[derive(Debug, Clone)]
+struct Columns<P> {
+    open: Vec<P>,
+    high: Vec<P>,
+    low: Vec<P>,
+    close: Vec<P>,
+    head: usize,
+}
+
+impl<P: Price> Columns<P> {
+    fn new(bar: &Bar<P>, capacity: usize) -> Self {
+        Self {
+            open: vec![bar.open; 2 * capacity],
+            high: vec![bar.high; 2 * capacity],
+            low: vec![bar.low; 2 * capacity],
+            close: vec![bar.close; 2 * capacity],
+            head: 0,
+        }
+    }
+
+    fn push(&mut self, bar: &Bar<P>, capacity: usize) {
+        self.head = (self.head + capacity - 1) % capacity;
+        let (first, second) = (self.head, self.head + capacity);
+        for (column, value) in [
+            (&mut self.open, bar.open),
+            (&mut self.high, bar.high),
+            (&mut self.low, bar.low),
+            (&mut self.close, bar.close),
+        ] {
+            column[first] = value;
+            column[second] = value;
+        }
+    }
+}
+
+impl<P: Price, S: Sampler<P>> History<S, P> {
+    /// Panics when `capacity` is 0
+    pub fn new(sampler: S, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity of 0");
+        Self {
+            sampler,
+            bars: VecDeque::with_capacity(capacity),
+            columns: None,
+            capacity,
+        }
+    }
+
+    /// Closed bars kept, at most `capacity`
+    pub fn len(&self) -> usize {
+        self.bars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bars.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The `i`th bar back, 0 being the most recent
+    pub fn get(&self, i: usize) -> Option<&Bar<P>> {
+        self.bars.get(i)
+    }
+
+    /// The kept bar starting at `bar_start`
+    pub fn at(&self, bar_start: NaiveDateTime) -> Option<&Bar<P>> {
+        self.bars
+            .binary_search_by(|bar| bar_start.cmp(&bar.bar_start))
+            .ok()
+            .map(|i| &self.bars[i])
+    }
+
+    /// Most recent first
+    pub fn iter(&self) -> impl Iterator<Item = &Bar<P>> {
+        self.bars.iter()
+    }
+
+    /// Opens, most recent first
+    pub fn opens(&self) -> &[P] {
+        self.column(|columns| &columns.open)
+    }
+
+    /// Highs, most recent first
+    pub fn highs(&self) -> &[P] {
+        self.column(|columns| &columns.high)
+    }
+
+    /// Lows, most recent first
+    pub fn lows(&self) -> &[P] {
+        self.column(|columns| &columns.low)
+    }
+
+    /// Closes, most recent first
+    pub fn closes(&self) -> &[P] {
+        self.column(|columns| &columns.close)
+    }
+
+    pub fn sampler(&self) -> &S {
+        &self.sampler
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sampler
+    }
+
+    fn column(&self, column: fn(&Columns<P>) -> &Vec<P>) -> &[P] {
+        match &self.columns {
+            Some(columns) => &column(columns)[columns.head..columns.head + self.bars.len()],
+            None => &[],
+        }
+    }
+
+    fn push(&mut self, bar: &Bar<P>) {
+        if self.bars.len() == self.capacity {
+            self.bars.pop_back();
+        }
+        self.bars.push_front(bar.clone());
+        let capacity = self.capacity;
+        self.columns
+            .get_or_insert_with(|| Columns::new(bar, capacity))
+            .push(bar, capacity);
+    }
+}
+
+impl<P: Price, S: Sampler<P>> Index<usize> for History<S, P> {
+    type Output = Bar<P>;
+
+    fn index(&self, i: usize) -> &Bar<P> {
+        &self.bars[i]
+    }
+}
+
+impl<P: Price, S: Sampler<P>> Sampler<P> for History<S, P> {
+    fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime {
+        self.sampler.bar_start(dt)
+    }
+
+    fn next_bar(&mut self, dt: NaiveDateTime, value: P) -> Option<Bars<P>> {
+        self.try_next_bar(dt, value).unwrap_or(None)
+    }
+
+    fn try_next_bar(
+        &mut self,
+        dt: NaiveDateTime,
+        value: P,
+    ) -> Result<Option<Bars<P>>, PriceError<P>> {
+        let bars = self.sampler.try_next_bar(dt, value)?;
+        match &bars {
+            Some(Bars::Single(bar)) => self.push(bar),
+            Some(Bars::WithEmpty(bar, empty)) => {
+                self.push(bar);
+                for bar in empty {
+                    self.push(bar);
+                }
+            }
+            None => {}
+        }
+        Ok(bars)
+    }
+
+    fn set_policy(&mut self, policy: PricePolicy) {
+        self.sampler.set_policy(policy)
+    }
+
+    fn set_closed(&mut self, closed: Closed) {
+        self.sampler.set_closed(closed)
+    }
+
+    fn set_origin(&mut self, origin: Origin) {
+        self.sampler.set_origin(origin)
+    }
+
+    fn next_bar_dt(&self, dt: NaiveDateTime) -> NaiveDateTime {
+        self.sampler.next_bar_dt(dt)
+    }
+
+    fn current_incomplete(&self) -> Option<Bar<P>> {
+        self.sampler.current_incomplete()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn last_bars() {
+        let mut history = History::new(M1::default(), 3);
+        assert!(history.is_empty());
+        assert!(history.closes().is_empty());
+
+        let ticks = [
+            ("2015-01-01 10:00:10", 1.),
+            ("2015-01-01 10:00:20", 2.),
+            ("2015-01-01 10:01:10", 3.),
+            ("2015-01-01 10:02:10", 4.),
+        ];
+        for (dt, value) in &ticks {
+            history.next_bar(date(dt), *value);
+        }
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].bar_start, date("2015-01-01 10:01:00"));
+        assert_eq!(history.opens(), [3., 1.]);
+        assert_eq!(history.highs(), [3., 2.]);
+        assert_eq!(history.closes(), [3., 2.]);
+        assert_eq!(history.current_incomplete().unwrap().close, 4.);
+
+        // 10:02, with 10:03 and 10:04 empty, pushes 10:00 and 10:01 out
+        history.next_bar(date("2015-01-01 10:05:10"), 5.);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.capacity(), 3);
+        assert_eq!(history.closes(), [4., 4., 4.]);
+        assert_eq!(history.lows(), [4., 4., 4.]);
+        assert_eq!(
+            history
+                .iter()
+                .map(|bar| bar.bar_start.minute())
+                .collect::<Vec<_>>(),
+            [4, 3, 2]
+        );
+
+        history.next_bar(date("2015-01-01 10:06:10"), 6.);
+        history.next_bar(date("2015-01-01 10:07:10"), 7.);
+        assert_eq!(history.closes(), [6., 5., 4.]);
+        assert_eq!(history.opens(), [6., 5., 4.]);
+        assert!(history.get(3).is_none());
+    }
+
+    #[test]
+    fn lookup_by_bar_start() {
+        let mut history = History::new(Box::new(M5::default()) as Box<dyn Sampler>, 10);
+        for minute in [0, 6, 12, 30] {
+            history.next_bar(
+                date("2015-01-01 10:00:00") + chrono::Duration::minutes(minute),
+                minute as f64,
+            );
+        }
+        // 10:00, 10:05, 10:10, 10:15 to 10:25 empty
+        assert_eq!(history.len(), 6);
+        assert_eq!(history.at(date("2015-01-01 10:05:00")).unwrap().close, 6.);
+        assert_eq!(history.at(date("2015-01-01 10:20:00")).unwrap().close, 12.);
+        assert_eq!(history.at(date("2015-01-01 10:00:00")).unwrap().open, 0.);
+        assert!(history.at(date("2015-01-01 10:30:00")).is_none());
+        assert!(history.at(date("2015-01-01 10:02:00")).is_none());
+        assert_eq!(history.into_inner().current_incomplete().unwrap().open, 30.);
+    }
+}
@@ -0,0 +1,559 @@
+use crate::{Bar, Bars, Price};
+use std::collections::VecDeque;
+
+/// An indicator updated one closed bar at a time
+///
+/// Values are `None` until enough bars have closed. Empty bars count like any
+/// other, `update_bars` takes them after the closing bar.
+pub trait Indicator: Clone {
+    type Output;
+
+    /// Takes a closed bar and gives the new value
+    fn update<P: Price>(&mut self, bar: &Bar<P>) -> Option<Self::Output>;
+
+    /// The value after the last closed bar
+    fn value(&self) -> Option<Self::Output>;
+
+    /// The value if `bar` closed now, without taking it
+    ///
+    /// Meant for the bar of `current_incomplete()`.
+    fn preview<P: Price>(&self, bar: &Bar<P>) -> Option<Self::Output> {
+        self.clone().update(bar)
+    }
+
+    /// Takes the closing bar and the empty ones, gives the value after the last
+    fn update_bars<P: Price>(&mut self, bars: &Bars<P>) -> Option<Self::Output> {
+        match bars {
+            Bars::Single(bar) => self.update(bar),
+            Bars::WithEmpty(bar, empty) => {
+                let mut value = self.update(bar);
+                for bar in empty {
+                    value = self.update(bar);
+                }
+                value
+            }
+        }
+    }
+}
+
+/// Simple moving average of closes
+#[derive(Debug, Clone)]
+pub struct Sma {
+    window: Window,
+}
+
+impl Sma {
+    /// Panics when `period` is 0
+    pub fn new(period: usize) -> Self {
+        Self {
+            window: Window::new(period),
+        }
+    }
+}
+
+impl Indicator for Sma {
+    type Output = f64;
+
+    fn update<P: Price>(&mut self, bar: &Bar<P>) -> Option<f64> {
+        self.window.push(bar.close.to_f64());
+        self.value()
+    }
+
+    fn value(&self) -> Option<f64> {
+        self.window.mean()
+    }
+}
+
+/// Exponential moving average of closes, seeded with the SMA of the first `period` closes
+#[derive(Debug, Clone)]
+pub struct Ema {
+    average: Average,
+}
+
+impl Ema {
+    /// Panics when `period` is 0
+    pub fn new(period: usize) -> Self {
+        Self {
+            average: Average::new(period, 2. / (period as f64 + 1.)),
+        }
+    }
+}
+
+impl Indicator for Ema {
+    type Output = f64;
+
+    fn update<P: Price>(&mut self, bar: &Bar<P>) -> Option<f64> {
+        self.average.push(bar.close.to_f64())
+    }
+
+    fn value(&self) -> Option<f64> {
+        self.average.value
+    }
+}
+
+/// Wilder's relative strength index, from 0 to 100
+///
+/// The first value comes with the `period + 1`th close, as in TA-Lib. Closes that
+/// neither rose nor fell over the averages give the neutral 50, where TA-Lib gives 0.
+#[derive(Debug, Clone)]
+pub struct Rsi {
+    prev_close: Option<f64>,
+    gain: Average,
+    loss: Average,
+}
+
+impl Rsi {
+    /// Panics when `period` is 0
+    pub fn new(period: usize) -> Self {
+        let alpha = 1. / period as f64;
+        Self {
+            prev_close: None,
+            gain: Average::new(period, alpha),
+            loss: Average::new(period, alpha),
+        }
+    }
+}
+
+impl Indicator for Rsi {
+    type Output = f64;
+
+    fn update<P: Price>(&mut self, bar: &Bar<P>) -> Option<f64> {
+        let close = bar.close.to_f64();
+        if let Some(prev_close) = self.prev_close.replace(close) {
+            let change = close - prev_close;
+            self.gain.push(change.max(0.));
+            self.loss.push((-change).max(0.));
+        }
+        self.value()
+    }
+
+    fn value(&self) -> Option<f64> {
+        match (self.gain.value, self.loss.value) {
+            (Some(0.), Some(0.)) => Some(50.),
+            (Some(_), Some(0.)) => Some(100.),
+            (Some(gain), Some(loss)) => Some(100. - 100. / (1. + gain / loss)),
+            _ => None,
+        }
+    }
+}
+
+/// Wilder's average true range
+///
+/// The first bar has no previous close, so the first value comes with the
+/// `period + 1`th bar, as in TA-Lib.
+#[derive(Debug, Clone)]
+pub struct Atr {
+    prev_close: Option<f64>,
+    range: Average,
+}
+
+impl Atr {
+    /// Panics when `period` is 0
+    pub fn new(period: usize) -> Self {
+        Self {
+            prev_close: None,
+            range: Average::new(period, 1. / period as f64),
+        }
+    }
+}
+
+impl Indicator for Atr {
+    type Output = f64;
+
+    fn update<P: Price>(&mut self, bar: &Bar<P>) -> Option<f64> {
+        let (high, low, close) = (bar.high.to_f64(), bar.low.to_f64(), bar.close.to_f64());
+        if let Some(prev_close) = self.prev_close.replace(close) {
+            let true_range = (high - low)
+                .max((high - prev_close).abs())
+                .max((low - prev_close).abs());
+            self.range.push(true_range);
+        }
+        self.value()
+    }
+
+    fn value(&self) -> Option<f64> {
+        self.range.value
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bands {
+    pub lower: f64,
+    pub middle: f64,
+    pub upper: f64,
+}
+
+/// SMA of closes with bands `k` population standard deviations away
+#[derive(Debug, Clone)]
+pub struct Bollinger {
+    window: Window,
+    k: f64,
+}
+
+impl Bollinger {
+    /// Panics when `period` is 0
+    pub fn new(period: usize, k: f64) -> Self {
+        Self {
+            window: Window::new(period),
+            k,
+        }
+    }
+}
+
+impl Indicator for Bollinger {
+    type Output = Bands;
+
+    fn update<P: Price>(&mut self, bar: &Bar<P>) -> Option<Bands> {
+        self.window.push(bar.close.to_f64());
+        self.value()
+    }
+
+    fn value(&self) -> Option<Bands> {
+        let middle = self.window.mean()?;
+        let values = &self.window.values;
+        let variance =
+            values.iter().map(|v| (v - middle).powi(2)).sum::<f64>() / values.len() as f64;
+        let width = self.k * variance.sqrt();
+        Some(Bands {
+            lower: middle - width,
+            middle,
+            upper: middle + width,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MacdValue {
+    pub macd: f64,
+    pub signal: f64,
+    /// MACD minus signal
+    pub histogram: f64,
+}
+
+/// Difference of a fast and a slow EMA of closes, with an EMA of it as signal
+///
+/// Every EMA is seeded with its own SMA, so the first value comes with the
+/// `slow + signal - 1`th close.
+#[derive(Debug, Clone)]
+pub struct Macd {
+    fast: Average,
+    slow: Average,
+    signal: Average,
+    value: Option<MacdValue>,
+}
+
+impl Macd {
+    /// Panics when a period is 0
+    pub fn new(fast: usize, slow: usize, signal: usize) -> Self {
+        let ema = |period: usize| Average::new(period, 2. / (period as f64 + 1.));
+        Self {
+            fast: ema(fast),
+            slow: ema(slow),
+            signal: ema(signal),
+            value: None,
+        }
+    }
+}
+
+impl Default for Macd {
+    /// The usual 12, 26, 9
+    fn default() -> Self {
+        Self::new(12, 26, 9)
+    }
+}
+
+impl Indicator for Macd {
+    type Output = MacdValue;
+
+    fn update<P: Price>(&mut self, bar: &Bar<P>) -> Option<MacdValue> {
+        let close = bar.close.to_f64();
+        if let (Some(fast), Some(slow)) = (self.fast.push(close), self.slow.push(close)) {
+            let macd = fast - slow;
+            if let Some(signal) = self.signal.push(macd) {
+                self.value = Some(MacdValue {
+                    macd,
+                    signal,
+                    histogram: macd - signal,
+                });
+            }
+        }
+        self.value
+    }
+
+    fn value(&self) -> Option<MacdValue> {
+        self.value
+    }
+}
+
+// the last `period` values
+#[derive(Debug, Clone)]
+struct Window {
+    period: usize,
+    values: VecDeque<f64>,
+}
+
+impl Window {
+    fn new(period: usize) -> Self {
+        assert!(period > 0, "period of 0");
+        Self {
+            period,
+            values: VecDeque::with_capacity(period),
+        }
+    }
+
+    fn push(&mut self, value: f64) {
+        if self.values.len() == self.period {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    // summed over the window rather than kept as a running sum, which drifts
+    fn mean(&self) -> Option<f64> {
+        (self.values.len() == self.period)
+            .then(|| self.values.iter().sum::<f64>() / self.period as f64)
+    }
+}
+
+// exponential average seeded with the mean of its first `period` values
+#[derive(Debug, Clone)]
+struct Average {
+    period: usize,
+    alpha: f64,
+    seed: f64,
+    count: usize,
+    value: Option<f64>,
+}
+
+impl Average {
+    fn new(period: usize, alpha: f64) -> Self {
+        assert!(period > 0, "period of 0");
+        Self {
+            period,
+            alpha,
+            seed: 0.,
+            count: 0,
+            value: None,
+        }
+    }
+
+    fn push(&mut self, value: f64) -> Option<f64> {
+        self.value = match self.value {
+            Some(prev) => Some(prev + (value - prev) * self.alpha),
+            None => {
+                self.seed += value;
+                self.count += 1;
+                (self.count == self.period).then(|| self.seed / self.period as f64)
+            }
+        };
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use chrono::prelude::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn close(close: f64) -> Bar {
+        Bar {
+            open: close,
+            high: close,
+            low: close,
+            close,
+            bar_start: date("2015-01-01 00:00:00"),
+            next_bar_dt: date("2015-01-02 00:00:00"),
+        }
+    }
+
+    fn assert_close(actual: Option<f64>, expected: &str, column: &str, line: usize) {
+        match (actual, expected) {
+            (None, "") => {}
+            (Some(actual), expected) if !expected.is_empty() => {
+                let expected: f64 = expected.parse().unwrap();
+                assert!(
+                    (actual - expected).abs() <= 1e-9 * expected.abs().max(1.),
+                    "{} on line {}: {} != {}",
+                    column,
+                    line,
+                    actual,
+                    expected
+                );
+            }
+            _ => panic!(
+                "{} on line {}: {:?} != {:?}",
+                column, line, actual, expected
+            ),
+        }
+    }
+
+    // a regression fixture, indicators.py writes it with the same definitions, so
+    // `closed_form_values` and `published_rsi` are the checks against outside values
+    #[test]
+    fn reference_values() {
+        let data = include_str!("../tests/data/indicators.csv");
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let mut sma = Sma::new(5);
+        let mut ema = Ema::new(10);
+        let mut rsi = Rsi::new(14);
+        let mut atr = Atr::new(14);
+        let mut bollinger = Bollinger::new(20, 2.);
+        let mut macd = Macd::default();
+        for (line, record) in reader.records().enumerate() {
+            let record = record.unwrap();
+            let line = line + 2;
+            let field = |i: usize| record[i].parse::<f64>().unwrap();
+            let bar_start = NaiveDate::parse_from_str(&record[0], "%Y-%m-%d")
+                .unwrap()
+                .and_time(NaiveTime::MIN);
+            let bar = Bar {
+                open: field(1),
+                high: field(2),
+                low: field(3),
+                close: field(4),
+                bar_start,
+                next_bar_dt: bar_start + chrono::Duration::days(1),
+            };
+
+            assert_close(sma.update(&bar), &record[5], "sma5", line);
+            assert_close(ema.update(&bar), &record[6], "ema10", line);
+            assert_close(rsi.update(&bar), &record[7], "rsi14", line);
+            assert_close(atr.update(&bar), &record[8], "atr14", line);
+            let bands = bollinger.update(&bar);
+            assert_close(bands.map(|b| b.lower), &record[9], "bb_lower", line);
+            assert_close(bands.map(|b| b.middle), &record[10], "bb_middle", line);
+            assert_close(bands.map(|b| b.upper), &record[11], "bb_upper", line);
+            let value = macd.update(&bar);
+            assert_close(value.map(|m| m.macd), &record[12], "macd", line);
+            assert_close(value.map(|m| m.signal), &record[13], "macd_signal", line);
+            assert_close(value.map(|m| m.histogram), &record[14], "macd_hist", line);
+        }
+        assert!(macd.value().is_some());
+    }
+
+    // series whose averages are known exactly
+    #[test]
+    fn closed_form_values() {
+        let near = |actual: Option<f64>, expected: f64| {
+            let actual = actual.unwrap();
+            assert!(
+                (actual - expected).abs() < 1e-9,
+                "{} != {}",
+                actual,
+                expected
+            );
+        };
+
+        // a ramp rising 0.5 a bar, each bar 2 high: an SMA-seeded EMA of n bars
+        // lags it by 0.5 * (n - 1) / 2 from its first value on, the true range is 2
+        let mut sma = Sma::new(5);
+        let mut ema = Ema::new(10);
+        let mut atr = Atr::new(14);
+        let mut macd = Macd::default();
+        for i in 0..60 {
+            let x = 100. + 0.5 * i as f64;
+            let bar = Bar {
+                high: x + 1.,
+                low: x - 1.,
+                ..close(x)
+            };
+            if let Some(value) = sma.update(&bar) {
+                near(Some(value), x - 1.);
+            }
+            if let Some(value) = ema.update(&bar) {
+                near(Some(value), x - 2.25);
+            }
+            if let Some(value) = atr.update(&bar) {
+                near(Some(value), 2.);
+            }
+            // 12.5 - 5.5 bars of lag apart
+            if let Some(value) = macd.update(&bar) {
+                near(Some(value.macd), 3.5);
+                near(Some(value.signal), 3.5);
+                near(Some(value.histogram), 0.);
+            }
+        }
+        assert!(macd.value().is_some());
+
+        // alternating 9 and 11 have a mean of 10 and a deviation of 1
+        let mut bollinger = Bollinger::new(20, 2.);
+        for i in 0..40 {
+            bollinger.update(&close(if i % 2 == 0 { 9. } else { 11. }));
+        }
+        let bands = bollinger.value().unwrap();
+        near(Some(bands.lower), 8.);
+        near(Some(bands.middle), 10.);
+        near(Some(bands.upper), 12.);
+    }
+
+    // the 14 day RSI example of StockCharts, rounded to 2 decimals
+    #[test]
+    fn published_rsi() {
+        let closes = [
+            44.3389, 44.0902, 44.1497, 43.6124, 44.3278, 44.8264, 45.0955, 45.4245, 45.8433,
+            46.0826, 45.8931, 46.0328, 45.6140, 46.2820, 46.2820, 46.0028, 46.0328, 46.4116,
+            46.2222, 45.6439, 46.2122, 46.2521, 45.7137, 46.4515, 45.7835, 45.3548, 44.0288,
+            44.1783, 44.2181, 44.5672, 43.4205, 42.6628, 43.1314,
+        ];
+        let expected = [
+            70.53, 66.32, 66.55, 69.41, 66.36, 57.97, 62.93, 63.26, 56.06, 62.38, 54.71, 50.42,
+            39.99, 41.46, 41.87, 45.46, 37.30, 33.08, 37.77,
+        ];
+        let mut rsi = Rsi::new(14);
+        let values: Vec<_> = closes
+            .iter()
+            .filter_map(|c| rsi.update(&close(*c)))
+            .map(|v| (v * 100.).round() / 100.)
+            .collect();
+        assert_eq!(values, expected);
+
+        let mut rsi = Rsi::new(2);
+        for c in [1., 2., 3.] {
+            rsi.update(&close(c));
+        }
+        assert_eq!(rsi.value(), Some(100.));
+    }
+
+    #[test]
+    fn empty_bars_and_preview() {
+        let mut sampler = M1::default();
+        let mut sma = Sma::new(3);
+        let ticks = [
+            ("2015-01-01 10:00:10", 1.),
+            ("2015-01-01 10:01:10", 4.),
+            // 10:02 and 10:03 are empty at 4
+            ("2015-01-01 10:04:10", 7.),
+            ("2015-01-01 10:05:10", 10.),
+        ];
+        for (dt, value) in &ticks {
+            if let Some(bars) = sampler.next_bar(date(dt), *value) {
+                sma.update_bars(&bars);
+            }
+        }
+        // closes 1, 4, 4, 4, 7
+        assert_eq!(sma.value(), Some(5.));
+
+        let mut rsi = Rsi::new(3);
+        for _ in 0..4 {
+            rsi.update(&close(4.));
+        }
+        assert_eq!(rsi.value(), Some(50.));
+        assert_eq!(rsi.update(&close(5.)), Some(100.));
+
+        let incomplete = sampler.current_incomplete().unwrap();
+        assert_eq!(sma.preview(&incomplete), Some(7.));
+        assert_eq!(sma.value(), Some(5.));
+
+        let mut macd = Macd::new(1, 2, 1);
+        assert_eq!(macd.preview(&close(1.)), None);
+        assert_eq!(macd.update(&close(1.)), None);
+        let value = macd.preview(&close(4.)).unwrap();
+        assert_eq!((value.macd, value.histogram), (1.5, 0.));
+        assert_eq!(macd.value(), None);
+    }
+}
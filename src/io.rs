@@ -0,0 +1,554 @@
+use crate::{Bar, Bars, Price, Sampler};
+use chrono::prelude::*;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// A trade or price update
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tick<P = f64> {
+    pub dt: NaiveDateTime,
+    pub price: P,
+    /// Zero when the source has no volume
+    pub volume: f64,
+}
+
+/// A CSV column, by position or by header name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+/// How timestamps are written in a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// `chrono::format::strftime` pattern
+    Pattern(String),
+    UnixSeconds,
+    UnixMillis,
+    UnixMicros,
+    UnixNanos,
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        TimeFormat::Pattern("%Y-%m-%d %H:%M:%S%.f".to_string())
+    }
+}
+
+impl TimeFormat {
+    pub fn parse(&self, s: &str) -> Option<NaiveDateTime> {
+        let from_unix = |value: i64, per_second: i64| {
+            DateTime::from_timestamp(
+                value.div_euclid(per_second),
+                (value.rem_euclid(per_second) * (1_000_000_000 / per_second)) as u32,
+            )
+            .map(|dt| dt.naive_utc())
+        };
+        match self {
+            TimeFormat::Pattern(pattern) => NaiveDateTime::parse_from_str(s, pattern).ok(),
+            TimeFormat::UnixSeconds => match s.split_once('.') {
+                // fractional seconds, up to nanoseconds
+                Some((whole, fraction)) if fraction.len() <= 9 => {
+                    let (negative, whole) = match whole.strip_prefix('-') {
+                        Some(whole) => (true, whole),
+                        None => (false, whole),
+                    };
+                    if !whole
+                        .bytes()
+                        .chain(fraction.bytes())
+                        .all(|b| b.is_ascii_digit())
+                    {
+                        return None;
+                    }
+                    let seconds = whole.parse::<i64>().ok()?;
+                    let nanos = format!("{:0<9}", fraction).parse::<u32>().ok()?;
+                    let (seconds, nanos) = match (negative, nanos) {
+                        (false, _) => (seconds, nanos),
+                        (true, 0) => (-seconds, 0),
+                        // -1.25 is 0.75 seconds after -2
+                        (true, _) => (-seconds - 1, 1_000_000_000 - nanos),
+                    };
+                    DateTime::from_timestamp(seconds, nanos).map(|dt| dt.naive_utc())
+                }
+                Some(_) => None,
+                None => from_unix(s.parse().ok()?, 1),
+            },
+            TimeFormat::UnixMillis => from_unix(s.parse().ok()?, 1_000),
+            TimeFormat::UnixMicros => from_unix(s.parse().ok()?, 1_000_000),
+            TimeFormat::UnixNanos => from_unix(s.parse().ok()?, 1_000_000_000),
+        }
+    }
+
+    pub fn format(&self, dt: NaiveDateTime) -> String {
+        let dt = dt.and_utc();
+        let nanos = dt.timestamp() as i128 * 1_000_000_000 + dt.timestamp_subsec_nanos() as i128;
+        match self {
+            TimeFormat::Pattern(pattern) => dt.format(pattern).to_string(),
+            TimeFormat::UnixSeconds => {
+                let (sign, nanos) = if nanos < 0 {
+                    ("-", -nanos)
+                } else {
+                    ("", nanos)
+                };
+                let fraction = format!("{:09}", nanos % 1_000_000_000);
+                match fraction.trim_end_matches('0') {
+                    "" => format!("{}{}", sign, nanos / 1_000_000_000),
+                    fraction => format!("{}{}.{}", sign, nanos / 1_000_000_000, fraction),
+                }
+            }
+            TimeFormat::UnixMillis => (nanos.div_euclid(1_000_000)).to_string(),
+            TimeFormat::UnixMicros => (nanos.div_euclid(1_000)).to_string(),
+            TimeFormat::UnixNanos => nanos.to_string(),
+        }
+    }
+}
+
+/// Layout of a tick CSV file
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvFormat {
+    pub delimiter: u8,
+    pub has_headers: bool,
+    pub time: Column,
+    pub price: Column,
+    pub volume: Option<Column>,
+    pub time_format: TimeFormat,
+}
+
+impl Default for CsvFormat {
+    /// `time,price,volume` with a header row
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_headers: true,
+            time: Column::Index(0),
+            price: Column::Index(1),
+            volume: Some(Column::Index(2)),
+            time_format: TimeFormat::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CsvError {
+    Csv(csv::Error),
+    /// A column name that isn't in the header
+    UnknownColumn(String),
+    /// A line that is too short or holds an unparsable value
+    Parse {
+        line: u64,
+        column: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsvError::Csv(err) => write!(f, "{}", err),
+            CsvError::UnknownColumn(name) => write!(f, "unknown column: {}", name),
+            CsvError::Parse {
+                line,
+                column,
+                value,
+            } => write!(f, "line {}: invalid {}: {:?}", line, column, value),
+        }
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+impl From<csv::Error> for CsvError {
+    fn from(err: csv::Error) -> Self {
+        CsvError::Csv(err)
+    }
+}
+
+/// Streams ticks from CSV, one record in memory at a time
+pub struct TickReader<R, P = f64> {
+    reader: csv::Reader<R>,
+    record: csv::StringRecord,
+    time_format: TimeFormat,
+    time: usize,
+    price: usize,
+    volume: Option<usize>,
+    price_type: std::marker::PhantomData<P>,
+}
+
+impl<P> TickReader<std::fs::File, P> {
+    pub fn from_path<Q: AsRef<std::path::Path>>(
+        path: Q,
+        format: &CsvFormat,
+    ) -> Result<Self, CsvError> {
+        Self::new(std::fs::File::open(path).map_err(csv::Error::from)?, format)
+    }
+}
+
+impl<R: Read, P> TickReader<R, P> {
+    pub fn new(reader: R, format: &CsvFormat) -> Result<Self, CsvError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(format.delimiter)
+            .has_headers(format.has_headers)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+
+        let headers = if format.has_headers {
+            Some(reader.headers()?.clone())
+        } else {
+            None
+        };
+        let index = |column: &Column| match column {
+            Column::Index(index) => Ok(*index),
+            Column::Name(name) => headers
+                .as_ref()
+                .and_then(|headers| headers.iter().position(|header| header == name))
+                .ok_or_else(|| CsvError::UnknownColumn(name.clone())),
+        };
+
+        Ok(Self {
+            time: index(&format.time)?,
+            price: index(&format.price)?,
+            volume: format.volume.as_ref().map(index).transpose()?,
+            reader,
+            record: csv::StringRecord::new(),
+            time_format: format.time_format.clone(),
+            price_type: std::marker::PhantomData,
+        })
+    }
+
+    fn field(&self, index: usize, column: &'static str) -> Result<&str, CsvError> {
+        self.record
+            .get(index)
+            .ok_or_else(|| self.parse_error(column, ""))
+    }
+
+    fn parse_error(&self, column: &'static str, value: &str) -> CsvError {
+        CsvError::Parse {
+            line: self.record.position().map_or(0, |position| position.line()),
+            column,
+            value: value.to_string(),
+        }
+    }
+}
+
+impl<R: Read, P: Price + FromStr> TickReader<R, P> {
+    fn parse(&self) -> Result<Tick<P>, CsvError> {
+        let time = self.field(self.time, "time")?;
+        let dt = self
+            .time_format
+            .parse(time)
+            .ok_or_else(|| self.parse_error("time", time))?;
+
+        let price = self.field(self.price, "price")?;
+        let price = price
+            .parse()
+            .map_err(|_| self.parse_error("price", price))?;
+
+        let volume = match self.volume {
+            Some(index) => {
+                let volume = self.field(index, "volume")?;
+                volume
+                    .parse()
+                    .map_err(|_| self.parse_error("volume", volume))?
+            }
+            None => 0.,
+        };
+
+        Ok(Tick { dt, price, volume })
+    }
+}
+
+impl<R: Read, P: Price + FromStr> Iterator for TickReader<R, P> {
+    type Item = Result<Tick<P>, CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_record(&mut self.record) {
+            Ok(true) => Some(self.parse()),
+            Ok(false) => None,
+            Err(err) => Some(Err(err.into())),
+        }
+    }
+}
+
+/// Writes bars as `bar_start,next_bar_dt,open,high,low,close` CSV
+pub struct BarWriter<W: Write> {
+    writer: csv::Writer<W>,
+    time_format: TimeFormat,
+    skip_empty: bool,
+}
+
+impl BarWriter<std::fs::File> {
+    pub fn from_path<Q: AsRef<std::path::Path>>(path: Q) -> Result<Self, CsvError> {
+        Self::new(std::fs::File::create(path).map_err(csv::Error::from)?)
+    }
+}
+
+impl<W: Write> BarWriter<W> {
+    /// Writes the header row
+    pub fn new(writer: W) -> Result<Self, CsvError> {
+        Self::with_format(
+            writer,
+            b',',
+            TimeFormat::Pattern("%Y-%m-%d %H:%M:%S".to_string()),
+        )
+    }
+
+    pub fn with_format(
+        writer: W,
+        delimiter: u8,
+        time_format: TimeFormat,
+    ) -> Result<Self, CsvError> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(writer);
+        writer.write_record(["bar_start", "next_bar_dt", "open", "high", "low", "close"])?;
+        Ok(Self {
+            writer,
+            time_format,
+            skip_empty: false,
+        })
+    }
+
+    /// Leaves out the empty bars of `Bars::WithEmpty`
+    pub fn skip_empty(mut self, skip_empty: bool) -> Self {
+        self.skip_empty = skip_empty;
+        self
+    }
+
+    pub fn write_bar<P: Price + fmt::Display>(&mut self, bar: &Bar<P>) -> Result<(), CsvError> {
+        self.writer.write_record(&[
+            self.time_format.format(bar.bar_start),
+            self.time_format.format(bar.next_bar_dt),
+            bar.open.to_string(),
+            bar.high.to_string(),
+            bar.low.to_string(),
+            bar.close.to_string(),
+        ])?;
+        Ok(())
+    }
+
+    pub fn write_bars<P: Price + fmt::Display>(&mut self, bars: &Bars<P>) -> Result<(), CsvError> {
+        match bars {
+            Bars::Single(bar) => self.write_bar(bar),
+            Bars::WithEmpty(bar, empty) => {
+                self.write_bar(bar)?;
+                if !self.skip_empty {
+                    for bar in empty {
+                        self.write_bar(bar)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), CsvError> {
+        self.writer.flush().map_err(csv::Error::from)?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> Result<W, CsvError> {
+        self.writer
+            .into_inner()
+            .map_err(|err| CsvError::Csv(csv::Error::from(err.into_error())))
+    }
+}
+
+/// Feeds every tick to `sampler` and writes the closed bars as they come,
+/// the incomplete bar is written at the end if `with_incomplete` is set.
+/// Memory use doesn't depend on the size of the input.
+///
+/// Returns the number of ticks read.
+pub fn resample_csv<R, W, P, S>(
+    ticks: TickReader<R, P>,
+    sampler: &mut S,
+    bars: &mut BarWriter<W>,
+    with_incomplete: bool,
+) -> Result<u64, CsvError>
+where
+    R: Read,
+    W: Write,
+    P: Price + FromStr + fmt::Display,
+    S: Sampler<P> + ?Sized,
+{
+    let mut count = 0;
+    for tick in ticks {
+        let tick = tick?;
+        count += 1;
+        if let Some(closed) = sampler.next_bar(tick.dt, tick.price) {
+            bars.write_bars(&closed)?;
+        }
+    }
+    if with_incomplete {
+        if let Some(bar) = sampler.current_incomplete() {
+            bars.write_bar(&bar)?;
+        }
+    }
+    bars.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn read_default_format() {
+        let data = "time,price,volume\n\
+                    2015-01-01 10:00:00,1.5,10\n\
+                    2015-01-01 10:00:01.250,1.25,0.5\n";
+        let ticks: Vec<Tick> = TickReader::new(data.as_bytes(), &CsvFormat::default())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            ticks,
+            vec![
+                Tick {
+                    dt: date("2015-01-01 10:00:00"),
+                    price: 1.5,
+                    volume: 10.
+                },
+                Tick {
+                    dt: date("2015-01-01 10:00:01") + chrono::Duration::milliseconds(250),
+                    price: 1.25,
+                    volume: 0.5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn read_mapped_columns() {
+        let data = "symbol;qty;ts;px\n\
+                    EURUSD;3;1420106400000;1.2\n\
+                    EURUSD;1;1420106401500;1.3\n";
+        let format = CsvFormat {
+            delimiter: b';',
+            time: Column::Name("ts".to_string()),
+            price: Column::Name("px".to_string()),
+            volume: Some(Column::Name("qty".to_string())),
+            time_format: TimeFormat::UnixMillis,
+            ..CsvFormat::default()
+        };
+        // prices aren't integers
+        let mut ticks = TickReader::<_, i64>::new(data.as_bytes(), &format).unwrap();
+        assert!(ticks.next().unwrap().is_err());
+
+        let ticks: Vec<Tick> = TickReader::new(data.as_bytes(), &format)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(ticks[0].dt, date("2015-01-01 10:00:00"));
+        assert_eq!(
+            ticks[1].dt,
+            date("2015-01-01 10:00:01") + chrono::Duration::milliseconds(500)
+        );
+        assert_eq!(ticks[1].price, 1.3);
+        assert_eq!(ticks[1].volume, 1.);
+    }
+
+    #[test]
+    fn read_errors() {
+        let format = CsvFormat {
+            has_headers: false,
+            volume: None,
+            ..CsvFormat::default()
+        };
+        let data = "2015-01-01 10:00:00,1\n2015-01-01 10:00:00,x\n2015-01-01\n";
+        let results: Vec<Result<Tick, _>> =
+            TickReader::new(data.as_bytes(), &format).unwrap().collect();
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1].as_ref().unwrap_err().to_string(),
+            "line 2: invalid price: \"x\""
+        );
+        assert_eq!(
+            results[2].as_ref().unwrap_err().to_string(),
+            "line 3: invalid time: \"2015-01-01\""
+        );
+
+        let format = CsvFormat {
+            price: Column::Name("bid".to_string()),
+            ..CsvFormat::default()
+        };
+        assert_eq!(
+            TickReader::<_, f64>::new("time,ask\n".as_bytes(), &format)
+                .err()
+                .unwrap()
+                .to_string(),
+            "unknown column: bid"
+        );
+    }
+
+    #[test]
+    fn time_formats() {
+        let dt = date("2015-01-01 10:00:00") + chrono::Duration::microseconds(1_500);
+        for format in &[
+            TimeFormat::UnixSeconds,
+            TimeFormat::UnixMicros,
+            TimeFormat::UnixNanos,
+        ] {
+            assert_eq!(format.parse(&format.format(dt)), Some(dt));
+        }
+        assert_eq!(TimeFormat::UnixSeconds.format(dt), "1420106400.0015");
+        let before_epoch = date("1969-12-31 23:59:58") + chrono::Duration::milliseconds(750);
+        assert_eq!(TimeFormat::UnixSeconds.format(before_epoch), "-1.25");
+        assert_eq!(TimeFormat::UnixSeconds.parse("-1.25"), Some(before_epoch));
+        assert_eq!(
+            TimeFormat::UnixSeconds.parse("-2.0"),
+            Some(date("1969-12-31 23:59:58"))
+        );
+        for invalid in ["1.-5", "--1.5", "+1.5", "1.0000000001"] {
+            assert_eq!(TimeFormat::UnixSeconds.parse(invalid), None);
+        }
+        assert_eq!(
+            TimeFormat::UnixSeconds.parse("1420106400.25"),
+            Some(date("2015-01-01 10:00:00") + chrono::Duration::milliseconds(250))
+        );
+        assert_eq!(TimeFormat::UnixMillis.format(dt), "1420106400001");
+    }
+
+    #[test]
+    fn resample_to_csv() {
+        let data = "time,price,volume\n\
+                    2015-01-01 10:00:00,1,1\n\
+                    2015-01-01 10:03:00,3,1\n\
+                    2015-01-01 10:04:00,2,1\n\
+                    2015-01-01 10:12:00,4,1\n";
+        let ticks = TickReader::<_, f64>::new(data.as_bytes(), &CsvFormat::default()).unwrap();
+        let mut writer = BarWriter::new(vec![]).unwrap();
+        let count = resample_csv(ticks, &mut M5::default(), &mut writer, true).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "bar_start,next_bar_dt,open,high,low,close\n\
+             2015-01-01 10:00:00,2015-01-01 10:05:00,1,3,1,2\n\
+             2015-01-01 10:05:00,2015-01-01 10:10:00,2,2,2,2\n\
+             2015-01-01 10:10:00,2015-01-01 10:15:00,4,4,4,4\n"
+        );
+    }
+
+    #[test]
+    fn skip_empty_bars() {
+        let mut sampler = M1::default();
+        let mut writer = BarWriter::with_format(vec![], b'\t', TimeFormat::UnixSeconds)
+            .unwrap()
+            .skip_empty(true);
+        sampler.next_bar(date("2015-01-01 10:00:00"), 1.);
+        let bars = sampler.next_bar(date("2015-01-01 10:05:00"), 2.).unwrap();
+        writer.write_bars(&bars).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "bar_start\tnext_bar_dt\topen\thigh\tlow\tclose\n\
+             1420106400\t1420106460\t1\t1\t1\t1\n"
+        );
+    }
+}
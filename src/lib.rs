@@ -1,3 +1,64 @@
+mod average;
+mod barfile;
+mod batch;
+#[cfg(feature = "websocket")]
+mod broadcast;
+#[cfg(feature = "dukascopy")]
+mod dukascopy;
+mod exchange;
+mod fix;
+#[cfg(feature = "polars")]
+mod frame;
+mod history;
+mod indicators;
+mod io;
+mod line_protocol;
+mod metatrader;
+mod origin;
+mod price;
+mod quote;
+#[cfg(feature = "arrow")]
+mod record_batch;
+mod registry;
+mod session;
+#[cfg(feature = "store")]
+mod store;
+mod sync;
 mod timeframe;
+mod trade;
+#[cfg(feature = "udf")]
+mod udf;
+mod validate;
+mod warmup;
 
+pub use barfile::*;
+pub use batch::*;
+#[cfg(feature = "websocket")]
+pub use broadcast::*;
+#[cfg(feature = "dukascopy")]
+pub use dukascopy::*;
+pub use exchange::*;
+pub use fix::*;
+#[cfg(feature = "polars")]
+pub use frame::*;
+pub use history::*;
+pub use indicators::*;
+pub use io::*;
+pub use line_protocol::*;
+pub use metatrader::*;
+pub use origin::*;
+pub use price::*;
+pub use quote::*;
+#[cfg(feature = "arrow")]
+pub use record_batch::*;
+pub use registry::*;
+pub use session::*;
+#[cfg(feature = "store")]
+pub use store::*;
+pub use sync::*;
 pub use timeframe::*;
+pub use trade::*;
+#[cfg(feature = "udf")]
+pub use udf::*;
+pub use validate::*;
+pub use warmup::*;
@@ -0,0 +1,337 @@
+use crate::{Bar, Bars, Label, Price};
+use chrono::prelude::*;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// Unit of line protocol timestamps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    Seconds,
+    Millis,
+    Micros,
+    #[default]
+    Nanos,
+}
+
+#[derive(Debug)]
+pub enum LineProtocolError {
+    Io(io::Error),
+    /// Line protocol has no NaN or infinities
+    NonFinite(String),
+    TimeRange(NaiveDateTime),
+}
+
+impl fmt::Display for LineProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineProtocolError::Io(err) => write!(f, "{}", err),
+            LineProtocolError::NonFinite(value) => write!(f, "non-finite field: {}", value),
+            LineProtocolError::TimeRange(dt) => write!(f, "{} is out of range", dt),
+        }
+    }
+}
+
+impl std::error::Error for LineProtocolError {}
+
+impl From<io::Error> for LineProtocolError {
+    fn from(err: io::Error) -> Self {
+        LineProtocolError::Io(err)
+    }
+}
+
+/// Encodes bars as InfluxDB line protocol, which QuestDB takes as well
+///
+/// A bar becomes `measurement,symbol=..,timeframe=..[,tag=..] open=..,high=..,low=..,close=.. time`.
+#[derive(Debug, Clone)]
+pub struct LineEncoder {
+    measurement: String,
+    tags: Vec<(String, String)>,
+    precision: Precision,
+    label: Label,
+}
+
+impl LineEncoder {
+    pub fn new(measurement: &str) -> Self {
+        Self {
+            measurement: measurement.to_string(),
+            tags: vec![],
+            precision: Precision::default(),
+            label: Label::default(),
+        }
+    }
+
+    /// A tag added to every line after `symbol` and `timeframe`
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Nanoseconds by default
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Which end of the bar is its timestamp
+    pub fn label(mut self, label: Label) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Appends the line of `bar`, with its newline, to `out`
+    pub fn encode<P: Price + fmt::Display>(
+        &self,
+        symbol: &str,
+        timeframe: &str,
+        bar: &Bar<P>,
+        out: &mut String,
+    ) -> Result<(), LineProtocolError> {
+        for value in [bar.open, bar.high, bar.low, bar.close] {
+            if !value.is_finite() {
+                return Err(LineProtocolError::NonFinite(value.to_string()));
+            }
+        }
+        let dt = bar.label(self.label);
+        let timestamp = timestamp(dt, self.precision).ok_or(LineProtocolError::TimeRange(dt))?;
+
+        escape(&self.measurement, &[',', ' '], out);
+        let tags = [("symbol", symbol), ("timeframe", timeframe)];
+        let tags = tags
+            .iter()
+            .copied()
+            .chain(self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        for (key, value) in tags {
+            out.push(',');
+            escape(key, &[',', '=', ' '], out);
+            out.push('=');
+            escape(value, &[',', '=', ' '], out);
+        }
+        // writing to a String can't fail
+        let _ = writeln!(
+            out,
+            " open={},high={},low={},close={} {}",
+            bar.open, bar.high, bar.low, bar.close, timestamp
+        );
+        Ok(())
+    }
+}
+
+/// Writes bars as line protocol in batches of `batch_size` lines
+///
+/// Lines are held until a batch fills up or `flush` is called, a dropped writer
+/// loses the lines of an unfinished batch.
+pub struct LineWriter<W: Write> {
+    writer: W,
+    encoder: LineEncoder,
+    buffer: String,
+    lines: usize,
+    batch_size: usize,
+}
+
+impl LineWriter<TcpStream> {
+    /// Connects to a line protocol TCP endpoint, like QuestDB's port 9009
+    pub fn connect<A: ToSocketAddrs>(addr: A, encoder: LineEncoder) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream, encoder))
+    }
+}
+
+impl<W: Write> LineWriter<W> {
+    pub fn new(writer: W, encoder: LineEncoder) -> Self {
+        Self {
+            writer,
+            encoder,
+            buffer: String::new(),
+            lines: 0,
+            batch_size: 5_000,
+        }
+    }
+
+    /// Lines per write, 5000 by default
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn write_bar<P: Price + fmt::Display>(
+        &mut self,
+        symbol: &str,
+        timeframe: &str,
+        bar: &Bar<P>,
+    ) -> Result<(), LineProtocolError> {
+        self.encoder
+            .encode(symbol, timeframe, bar, &mut self.buffer)?;
+        self.lines += 1;
+        if self.lines >= self.batch_size {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the closing bar and the empty ones
+    pub fn write_bars<P: Price + fmt::Display>(
+        &mut self,
+        symbol: &str,
+        timeframe: &str,
+        bars: &Bars<P>,
+    ) -> Result<(), LineProtocolError> {
+        match bars {
+            Bars::Single(bar) => self.write_bar(symbol, timeframe, bar),
+            Bars::WithEmpty(bar, empty) => {
+                self.write_bar(symbol, timeframe, bar)?;
+                for bar in empty {
+                    self.write_bar(symbol, timeframe, bar)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Lines waiting for the batch to fill up
+    pub fn pending(&self) -> usize {
+        self.lines
+    }
+
+    pub fn flush(&mut self) -> Result<(), LineProtocolError> {
+        self.write_batch()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Flushes the pending lines first
+    pub fn into_inner(mut self) -> Result<W, LineProtocolError> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.writer.write_all(self.buffer.as_bytes())?;
+            self.buffer.clear();
+            self.lines = 0;
+        }
+        Ok(())
+    }
+}
+
+fn timestamp(dt: NaiveDateTime, precision: Precision) -> Option<i64> {
+    let dt = dt.and_utc();
+    match precision {
+        Precision::Seconds => Some(dt.timestamp()),
+        Precision::Millis => Some(dt.timestamp_millis()),
+        Precision::Micros => Some(dt.timestamp_micros()),
+        Precision::Nanos => dt.timestamp_nanos_opt(),
+    }
+}
+
+fn escape(value: &str, special: &[char], out: &mut String) {
+    for c in value.chars() {
+        if special.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn bar(start: &str, close: f64) -> Bar {
+        Bar {
+            open: 1.5,
+            high: 2.,
+            low: 1.25,
+            close,
+            bar_start: date(start),
+            next_bar_dt: date(start) + chrono::Duration::minutes(1),
+        }
+    }
+
+    #[test]
+    fn encode() {
+        let mut out = String::new();
+        LineEncoder::new("bars")
+            .encode("EURUSD", "M1", &bar("2015-01-01 10:00:00", 1.75), &mut out)
+            .unwrap();
+        assert_eq!(
+            out,
+            "bars,symbol=EURUSD,timeframe=M1 open=1.5,high=2,low=1.25,close=1.75 1420106400000000000\n"
+        );
+
+        out.clear();
+        LineEncoder::new("fx bars,v2")
+            .tag("source", "feed=a b")
+            .precision(Precision::Seconds)
+            .label(Label::Close)
+            .encode("EUR/USD", "M1", &bar("2015-01-01 10:00:00", 2.), &mut out)
+            .unwrap();
+        assert_eq!(
+            out,
+            "fx\\ bars\\,v2,symbol=EUR/USD,timeframe=M1,source=feed\\=a\\ b \
+             open=1.5,high=2,low=1.25,close=2 1420106460\n"
+        );
+
+        assert_eq!(
+            LineEncoder::new("bars")
+                .encode("X", "M1", &bar("2015-01-01 10:00:00", f64::NAN), &mut out)
+                .unwrap_err()
+                .to_string(),
+            "non-finite field: NaN"
+        );
+        assert!(LineEncoder::new("bars")
+            .encode("X", "M1", &bar("2300-01-01 00:00:00", 1.), &mut out)
+            .is_err());
+    }
+
+    #[test]
+    fn batches_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let encoder = LineEncoder::new("bars").precision(Precision::Seconds);
+        let mut writer = LineWriter::connect(addr, encoder).unwrap().batch_size(2);
+        let mut sampler = M1::default();
+        let ticks = [
+            ("2015-01-01 10:00:10", 1.),
+            ("2015-01-01 10:01:10", 2.),
+            ("2015-01-01 10:04:10", 3.),
+            ("2015-01-01 10:05:00", 4.),
+        ];
+        for (dt, value) in &ticks {
+            if let Some(bars) = sampler.next_bar(date(dt), *value) {
+                writer.write_bars("EURUSD", "M1", &bars).unwrap();
+            }
+        }
+        // 10:00, 10:01 with 10:02 and 10:03 empty, 10:04
+        assert_eq!(writer.pending(), 1);
+        writer
+            .write_bar("EURUSD", "M1", &sampler.current_incomplete().unwrap())
+            .unwrap();
+        drop(writer.into_inner().unwrap());
+
+        let received = server.join().unwrap();
+        let lines: Vec<_> = received.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[2],
+            "bars,symbol=EURUSD,timeframe=M1 open=2,high=2,low=2,close=2 1420106520"
+        );
+        assert!(lines[5].ends_with("close=4 1420106700"));
+    }
+}
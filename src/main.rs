@@ -0,0 +1,499 @@
+use chrono::Duration;
+use metabars::*;
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "\
+Usage: metabars resample --tf M5,H1 --input ticks.csv --output bars/ [options]
+
+Reads `time,price[,volume]` ticks and writes one `<input>_<tf>` file per timeframe.
+
+Options:
+    --tf LIST                 comma separated timeframes, e.g. M1,M5,H1,D1
+    --input PATH              tick CSV, times in UTC
+    --output DIR              directory for the bar files, created if missing
+    --timezone OFFSET         fixed UTC offset bars are aligned and written in, e.g. +03:00,
+                              zone names and daylight saving time are not supported [UTC]
+    --session HOURS[/DAYS]    keep ticks inside the session only, in --timezone, and no empty
+                              bars outside it, e.g. 09:30-16:00/mon-fri
+    --gaps fill|skip          write flat bars for periods without ticks or leave them out [fill]
+    --format csv|tsv          output format [csv]
+    --policy reject|skip|propagate
+                              what to do with non-finite prices [reject]
+    --origin start-of-day|epoch|first-tick
+                              bucket alignment [start-of-day]
+    --closed left|right       side of a bar's interval that includes its boundary [left]
+    --incomplete              also write the last, incomplete bar
+    --delimiter CHAR          input delimiter [,]
+    --no-header               the input has no header row
+    --time-format FORMAT      input times: strftime pattern or unix-s, unix-ms, unix-us,
+                              unix-ns [%Y-%m-%d %H:%M:%S%.f]
+    --output-time-format FORMAT
+                              bar times, same values as --time-format [%Y-%m-%d %H:%M:%S]
+    -h, --help                print this help
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Gaps {
+    Fill,
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Csv,
+    Tsv,
+}
+
+impl OutputFormat {
+    fn delimiter(self) -> u8 {
+        match self {
+            OutputFormat::Csv => b',',
+            OutputFormat::Tsv => b'\t',
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Resample {
+    timeframes: Vec<String>,
+    input: PathBuf,
+    output: PathBuf,
+    offset: Duration,
+    session: Option<Session>,
+    gaps: Gaps,
+    format: OutputFormat,
+    policy: PricePolicy,
+    origin: Origin,
+    closed: Closed,
+    incomplete: bool,
+    csv: CsvFormat,
+    time_format: TimeFormat,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    Resample(Box<Resample>),
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    match args.next().as_deref() {
+        Some("resample") => {}
+        Some("-h") | Some("--help") | Some("help") | None => return Ok(Command::Help),
+        Some(command) => return Err(format!("unknown command: {}", command)),
+    }
+
+    let mut timeframes = None;
+    let mut input = None;
+    let mut output = None;
+    let mut resample = Resample {
+        timeframes: vec![],
+        input: PathBuf::new(),
+        output: PathBuf::new(),
+        offset: Duration::zero(),
+        session: None,
+        gaps: Gaps::Fill,
+        format: OutputFormat::Csv,
+        policy: PricePolicy::Reject,
+        origin: Origin::StartOfDay,
+        closed: Closed::Left,
+        incomplete: false,
+        csv: CsvFormat::default(),
+        time_format: TimeFormat::Pattern("%Y-%m-%d %H:%M:%S".to_string()),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--tf" => {
+                let list = value()?;
+                let list: Vec<String> = list.split(',').map(|tf| tf.trim().to_string()).collect();
+                for tf in &list {
+                    if <dyn Sampler>::from_short(tf).is_none() {
+                        return Err(format!("unknown timeframe: {}", tf));
+                    }
+                }
+                timeframes = Some(list);
+            }
+            "--input" => input = Some(PathBuf::from(value()?)),
+            "--output" => output = Some(PathBuf::from(value()?)),
+            "--timezone" => resample.offset = parse_offset(&value()?)?,
+            "--session" => {
+                resample.session = Some(value()?.parse().map_err(|err| format!("{}", err))?)
+            }
+            "--gaps" => {
+                resample.gaps = match value()?.as_str() {
+                    "fill" => Gaps::Fill,
+                    "skip" => Gaps::Skip,
+                    other => return Err(format!("invalid gap policy: {}", other)),
+                }
+            }
+            "--format" => {
+                resample.format = match value()?.as_str() {
+                    "csv" => OutputFormat::Csv,
+                    "tsv" => OutputFormat::Tsv,
+                    other => return Err(format!("invalid output format: {}", other)),
+                }
+            }
+            "--policy" => {
+                resample.policy = match value()?.as_str() {
+                    "reject" => PricePolicy::Reject,
+                    "skip" => PricePolicy::Skip,
+                    "propagate" => PricePolicy::Propagate,
+                    other => return Err(format!("invalid price policy: {}", other)),
+                }
+            }
+            "--origin" => {
+                resample.origin = match value()?.as_str() {
+                    "start-of-day" => Origin::StartOfDay,
+                    "epoch" => Origin::Epoch,
+                    "first-tick" => Origin::FirstTick,
+                    other => return Err(format!("invalid origin: {}", other)),
+                }
+            }
+            "--closed" => {
+                resample.closed = match value()?.as_str() {
+                    "left" => Closed::Left,
+                    "right" => Closed::Right,
+                    other => return Err(format!("invalid closed side: {}", other)),
+                }
+            }
+            "--incomplete" => resample.incomplete = true,
+            "--delimiter" => {
+                let delimiter = value()?;
+                resample.csv.delimiter = match delimiter.as_bytes() {
+                    [byte] => *byte,
+                    _ if delimiter == "\\t" => b'\t',
+                    _ => return Err(format!("invalid delimiter: {}", delimiter)),
+                }
+            }
+            "--no-header" => resample.csv.has_headers = false,
+            "--time-format" => resample.csv.time_format = parse_time_format(value()?),
+            "--output-time-format" => resample.time_format = parse_time_format(value()?),
+            other => return Err(format!("unknown option: {}", other)),
+        }
+    }
+
+    resample.timeframes = timeframes.ok_or("missing --tf")?;
+    resample.input = input.ok_or("missing --input")?;
+    resample.output = output.ok_or("missing --output")?;
+    Ok(Command::Resample(Box::new(resample)))
+}
+
+/// `UTC`, `Z`, `+03`, `+03:00` or `-0530`
+fn parse_offset(s: &str) -> Result<Duration, String> {
+    let err = || format!("invalid timezone: {}", s);
+    if s.eq_ignore_ascii_case("utc") || s == "Z" {
+        return Ok(Duration::zero());
+    }
+    let sign = match s.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(err()),
+    };
+    let digits: String = s[1..].chars().filter(|c| *c != ':').collect();
+    let (hours, minutes) = match digits.len() {
+        2 => (&digits[..], "0"),
+        4 => (&digits[..2], &digits[2..]),
+        _ => return Err(err()),
+    };
+    let hours: i64 = hours.parse().map_err(|_| err())?;
+    let minutes: i64 = minutes.parse().map_err(|_| err())?;
+    if hours > 23 || minutes > 59 {
+        return Err(err());
+    }
+    Ok(Duration::minutes(sign * (hours * 60 + minutes)))
+}
+
+fn parse_time_format(s: String) -> TimeFormat {
+    match s.as_str() {
+        "unix-s" => TimeFormat::UnixSeconds,
+        "unix-ms" => TimeFormat::UnixMillis,
+        "unix-us" => TimeFormat::UnixMicros,
+        "unix-ns" => TimeFormat::UnixNanos,
+        _ => TimeFormat::Pattern(s),
+    }
+}
+
+fn output_path(resample: &Resample, tf: &str) -> PathBuf {
+    let stem = resample
+        .input
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("ticks");
+    resample
+        .output
+        .join(format!("{}_{}.{}", stem, tf, resample.format.extension()))
+}
+
+/// Streams the input once, feeding every timeframe. Returns the number of ticks sampled.
+fn resample(resample: &Resample) -> Result<u64, Box<dyn Error>> {
+    std::fs::create_dir_all(&resample.output)?;
+
+    let mut outputs = vec![];
+    for tf in &resample.timeframes {
+        let mut sampler = <dyn Sampler>::from_short(tf).unwrap();
+        sampler.set_policy(resample.policy);
+        sampler.set_origin(resample.origin);
+        sampler.set_closed(resample.closed);
+        let writer = BarWriter::with_format(
+            File::create(output_path(resample, tf))?,
+            resample.format.delimiter(),
+            resample.time_format.clone(),
+        )?
+        .skip_empty(resample.gaps == Gaps::Skip);
+        outputs.push((sampler, writer));
+    }
+
+    let mut count = 0;
+    for tick in TickReader::<_, f64>::from_path(&resample.input, &resample.csv)? {
+        let tick = tick?;
+        let dt = tick.dt + resample.offset;
+        if let Some(session) = &resample.session {
+            if !session.contains(dt) {
+                continue;
+            }
+        }
+        count += 1;
+        for (sampler, writer) in &mut outputs {
+            let closed = sampler
+                .try_next_bar(dt, tick.price)
+                .map_err(|err| format!("tick {} at {}: {}", count, tick.dt, err))?;
+            match (closed, &resample.session) {
+                (Some(bars), Some(session)) => {
+                    let (bar, empty) = bars.into_parts();
+                    writer.write_bar(&bar)?;
+                    if resample.gaps == Gaps::Fill {
+                        for bar in empty {
+                            if session.overlaps(bar.bar_start, bar.next_bar_dt) {
+                                writer.write_bar(&bar)?;
+                            }
+                        }
+                    }
+                }
+                (Some(bars), None) => writer.write_bars(&bars)?,
+                (None, _) => {}
+            }
+        }
+    }
+
+    for (sampler, writer) in &mut outputs {
+        if resample.incomplete {
+            if let Some(bar) = sampler.current_incomplete() {
+                writer.write_bar(&bar)?;
+            }
+        }
+        writer.flush()?;
+    }
+    Ok(count)
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn main() {
+    let command = match parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("metabars: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    match command {
+        Command::Help => print!("{}", USAGE),
+        Command::Resample(args) => match resample(&args) {
+            Ok(count) => eprintln!(
+                "{} ticks resampled to {}",
+                count,
+                args.timeframes
+                    .iter()
+                    .map(|tf| display(&output_path(&args, tf)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Err(err) => {
+                eprintln!("metabars: {}: {}", display(&args.input), err);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn parse(line: &str) -> Resample {
+        match parse_args(args(line)).unwrap() {
+            Command::Resample(resample) => *resample,
+            Command::Help => panic!("help"),
+        }
+    }
+
+    #[test]
+    fn parse_resample() {
+        let resample = parse(
+            "resample --tf M5,H1 --input ticks.csv --output bars/ --timezone +03:00 \
+             --session 09:30-16:00/mon-fri --gaps skip --format tsv --policy skip \
+             --time-format unix-ms --no-header --delimiter ;",
+        );
+        assert_eq!(resample.timeframes, vec!["M5", "H1"]);
+        assert_eq!(resample.offset, Duration::hours(3));
+        assert_eq!(resample.session, "09:30-16:00/mon-fri".parse().ok());
+        assert_eq!(resample.gaps, Gaps::Skip);
+        assert_eq!(resample.format, OutputFormat::Tsv);
+        assert_eq!(resample.policy, PricePolicy::Skip);
+        assert_eq!(resample.csv.time_format, TimeFormat::UnixMillis);
+        assert!(!resample.csv.has_headers);
+        assert_eq!(resample.csv.delimiter, b';');
+        assert_eq!(output_path(&resample, "M5"), Path::new("bars/ticks_M5.tsv"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_args(args("")), Ok(Command::Help));
+        assert_eq!(parse_args(args("resample --help")), Ok(Command::Help));
+        assert_eq!(
+            parse_args(args("convert")),
+            Err("unknown command: convert".to_string())
+        );
+        assert_eq!(
+            parse_args(args("resample --tf M5,X3 --input a --output b")),
+            Err("unknown timeframe: X3".to_string())
+        );
+        assert_eq!(
+            parse_args(args("resample --tf M5 --input a")),
+            Err("missing --output".to_string())
+        );
+        assert_eq!(
+            parse_args(args("resample --tf M5 --input a --output b --gaps")),
+            Err("missing value for --gaps".to_string())
+        );
+        assert_eq!(
+            parse_args(args("resample --tf M5 --input a --output b --timezone +3")),
+            Err("invalid timezone: +3".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_timezones() {
+        assert_eq!(parse_offset("UTC"), Ok(Duration::zero()));
+        assert_eq!(parse_offset("+03"), Ok(Duration::hours(3)));
+        assert_eq!(parse_offset("-0530"), Ok(Duration::minutes(-330)));
+        assert_eq!(parse_offset("+05:45"), Ok(Duration::minutes(345)));
+        assert!(parse_offset("+24:00").is_err());
+        assert!(parse_offset("Europe/Moscow").is_err());
+    }
+
+    #[test]
+    fn resample_files() {
+        let dir = std::env::temp_dir().join(format!("metabars-cli-{}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("eurusd.csv");
+        std::fs::write(
+            &input,
+            "time,price,volume\n\
+             2021-01-08 06:29:00,1.0,1\n\
+             2021-01-08 06:31:00,1.5,1\n\
+             2021-01-08 06:33:00,1.2,1\n\
+             2021-01-08 06:47:00,1.3,1\n\
+             2021-01-08 08:05:00,1.4,1\n",
+        )
+        .unwrap();
+        let output = dir.join("bars");
+
+        let mut resample = parse(&format!(
+            "resample --tf M15,H1 --input {} --output {} --timezone +03:00 \
+             --session 09:30-16:00 --incomplete",
+            input.display(),
+            output.display()
+        ));
+        assert_eq!(super::resample(&resample).unwrap(), 4);
+        assert_eq!(
+            std::fs::read_to_string(output.join("eurusd_M15.csv")).unwrap(),
+            "bar_start,next_bar_dt,open,high,low,close\n\
+             2021-01-08 09:30:00,2021-01-08 09:45:00,1.5,1.5,1.2,1.2\n\
+             2021-01-08 09:45:00,2021-01-08 10:00:00,1.3,1.3,1.3,1.3\n\
+             2021-01-08 10:00:00,2021-01-08 10:15:00,1.3,1.3,1.3,1.3\n\
+             2021-01-08 10:15:00,2021-01-08 10:30:00,1.3,1.3,1.3,1.3\n\
+             2021-01-08 10:30:00,2021-01-08 10:45:00,1.3,1.3,1.3,1.3\n\
+             2021-01-08 10:45:00,2021-01-08 11:00:00,1.3,1.3,1.3,1.3\n\
+             2021-01-08 11:00:00,2021-01-08 11:15:00,1.4,1.4,1.4,1.4\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(output.join("eurusd_H1.csv")).unwrap(),
+            "bar_start,next_bar_dt,open,high,low,close\n\
+             2021-01-08 09:00:00,2021-01-08 10:00:00,1.5,1.5,1.2,1.3\n\
+             2021-01-08 10:00:00,2021-01-08 11:00:00,1.3,1.3,1.3,1.3\n\
+             2021-01-08 11:00:00,2021-01-08 12:00:00,1.4,1.4,1.4,1.4\n"
+        );
+
+        resample.gaps = Gaps::Skip;
+        resample.timeframes = vec!["M15".to_string()];
+        super::resample(&resample).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output.join("eurusd_M15.csv"))
+                .unwrap()
+                .lines()
+                .count(),
+            4
+        );
+
+        std::fs::write(&input, "time,price,volume\n2021-01-08 10:00:00,NaN,1\n").unwrap();
+        assert_eq!(
+            super::resample(&resample).unwrap_err().to_string(),
+            "tick 1 at 2021-01-08 10:00:00: non-finite price: NaN"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_empty_bars_outside_session() {
+        let dir = std::env::temp_dir().join(format!("metabars-session-{}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("ticks.csv");
+        // friday afternoon, then monday morning
+        std::fs::write(
+            &input,
+            "time,price,volume\n\
+             2021-01-08 14:10:00,1.0,1\n\
+             2021-01-08 15:50:00,1.1,1\n\
+             2021-01-11 09:40:00,1.2,1\n\
+             2021-01-11 10:10:00,1.3,1\n",
+        )
+        .unwrap();
+        let output = dir.join("bars");
+
+        let resample = parse(&format!(
+            "resample --tf H1 --input {} --output {} --session 09:30-16:00/mon-fri --gaps fill",
+            input.display(),
+            output.display()
+        ));
+        assert_eq!(super::resample(&resample).unwrap(), 4);
+        assert_eq!(
+            std::fs::read_to_string(output.join("ticks_H1.csv")).unwrap(),
+            "bar_start,next_bar_dt,open,high,low,close\n\
+             2021-01-08 14:00:00,2021-01-08 15:00:00,1,1,1,1\n\
+             2021-01-08 15:00:00,2021-01-08 16:00:00,1.1,1.1,1.1,1.1\n\
+             2021-01-11 09:00:00,2021-01-11 10:00:00,1.2,1.2,1.2,1.2\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
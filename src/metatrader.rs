@@ -0,0 +1,719 @@
+use crate::{Bar, Sampler};
+use chrono::prelude::*;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const HST_VERSION: i32 = 401;
+const HST_HEADER_LEN: usize = 148;
+const HST_RECORD_LEN: usize = 60;
+const HST_COPYRIGHT: &str = "(C)opyright 2003, MetaQuotes Software Corp.";
+const MT5_HEADER: [&str; 9] = [
+    "<DATE>",
+    "<TIME>",
+    "<OPEN>",
+    "<HIGH>",
+    "<LOW>",
+    "<CLOSE>",
+    "<TICKVOL>",
+    "<VOL>",
+    "<SPREAD>",
+];
+
+/// A bar of MetaTrader history
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtBar {
+    pub bar: Bar,
+    pub tick_volume: u64,
+    /// In points, zero when the source has none
+    pub spread: i32,
+    pub real_volume: u64,
+}
+
+impl From<Bar> for MtBar {
+    fn from(bar: Bar) -> Self {
+        Self {
+            bar,
+            tick_volume: 0,
+            spread: 0,
+            real_volume: 0,
+        }
+    }
+}
+
+/// MetaTrader's period of a timeframe, in minutes
+///
+/// Returns None for timeframes MetaTrader has no period for, like `Q1`, and for
+/// uniform periods of a day or longer.
+pub fn mt_period(short: &str) -> Option<u32> {
+    match short {
+        "D1" => Some(1440),
+        "W1" => Some(10080),
+        "Mn1" => Some(43200),
+        _ => {
+            <dyn Sampler>::from_short(short)?;
+            let minutes = match (short.get(..1)?, short[1..].parse::<u32>().ok()?) {
+                ("M", minutes) => minutes,
+                ("H", hours) => hours.checked_mul(60)?,
+                _ => return None,
+            };
+            Some(minutes).filter(|minutes| *minutes < 1440)
+        }
+    }
+}
+
+/// The timeframe of a MetaTrader period in minutes, as `Sampler::from_short` takes it
+///
+/// Periods over a day other than a week or a month have no matching sampler,
+/// MetaTrader aligns them differently.
+pub fn mt_timeframe(period: u32) -> Option<String> {
+    match period {
+        0 => None,
+        1440 => Some("D1".to_string()),
+        10080 => Some("W1".to_string()),
+        43200 => Some("Mn1".to_string()),
+        period if period > 1440 => None,
+        period if period % 60 == 0 => Some(format!("H{}", period / 60)),
+        period => Some(format!("M{}", period)),
+    }
+}
+
+#[derive(Debug)]
+pub enum MtError {
+    Io(io::Error),
+    Csv(csv::Error),
+    Format(&'static str),
+    /// A CSV line that is too short or holds an unparsable value
+    Parse {
+        line: u64,
+        column: &'static str,
+        value: String,
+    },
+    /// A period in a file with no matching timeframe
+    Period(u32),
+    /// A timeframe with no MetaTrader period
+    Timeframe(String),
+}
+
+impl fmt::Display for MtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MtError::Io(err) => write!(f, "{}", err),
+            MtError::Csv(err) => write!(f, "{}", err),
+            MtError::Format(reason) => write!(f, "invalid history file: {}", reason),
+            MtError::Parse {
+                line,
+                column,
+                value,
+            } => write!(f, "line {}: invalid {}: {:?}", line, column, value),
+            MtError::Period(period) => write!(f, "unsupported period: {}", period),
+            MtError::Timeframe(short) => write!(f, "no MetaTrader period for {}", short),
+        }
+    }
+}
+
+impl std::error::Error for MtError {}
+
+impl From<io::Error> for MtError {
+    fn from(err: io::Error) -> Self {
+        MtError::Io(err)
+    }
+}
+
+impl From<csv::Error> for MtError {
+    fn from(err: csv::Error) -> Self {
+        MtError::Csv(err)
+    }
+}
+
+/// Header of an MT4 `.hst` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HstHeader {
+    /// Up to 11 bytes
+    pub symbol: String,
+    /// As `Sampler::from_short` takes it
+    pub timeframe: String,
+    pub digits: u32,
+}
+
+/// Reads the bars of an MT4 `.hst` history file, format version 401
+pub struct HstReader<R> {
+    reader: R,
+    header: HstHeader,
+    sampler: Box<dyn Sampler>,
+}
+
+impl HstReader<io::BufReader<std::fs::File>> {
+    pub fn from_path<Q: AsRef<std::path::Path>>(path: Q) -> Result<Self, MtError> {
+        Self::new(io::BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+impl<R: Read> HstReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, MtError> {
+        let mut header = [0; HST_HEADER_LEN];
+        read_exact(&mut reader, &mut header)?;
+        if i32_at(&header, 0) != HST_VERSION {
+            return Err(MtError::Format("unsupported version"));
+        }
+        let symbol = &header[68..80];
+        let symbol = &symbol[..symbol.iter().position(|b| *b == 0).unwrap_or(12)];
+        let period = i32_at(&header, 80) as u32;
+        let timeframe = mt_timeframe(period).ok_or(MtError::Period(period))?;
+        let sampler = <dyn Sampler>::from_short(&timeframe).ok_or(MtError::Period(period))?;
+        Ok(Self {
+            reader,
+            header: HstHeader {
+                symbol: String::from_utf8_lossy(symbol).into_owned(),
+                timeframe,
+                digits: i32_at(&header, 84) as u32,
+            },
+            sampler,
+        })
+    }
+
+    pub fn header(&self) -> &HstHeader {
+        &self.header
+    }
+
+    fn read_bar(&mut self) -> Result<Option<MtBar>, MtError> {
+        let mut record = [0; HST_RECORD_LEN];
+        let mut read = 0;
+        while read < HST_RECORD_LEN {
+            match self.reader.read(&mut record[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(MtError::Format("truncated record")),
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let bar_start = DateTime::from_timestamp(i64_at(&record, 0), 0)
+            .map(|dt| dt.naive_utc())
+            .ok_or(MtError::Format("bad time"))?;
+        Ok(Some(MtBar {
+            bar: Bar {
+                open: f64_at(&record, 8),
+                high: f64_at(&record, 16),
+                low: f64_at(&record, 24),
+                close: f64_at(&record, 32),
+                bar_start,
+                next_bar_dt: bar_end(&*self.sampler, bar_start),
+            },
+            tick_volume: i64_at(&record, 40) as u64,
+            spread: i32_at(&record, 48),
+            real_volume: i64_at(&record, 52) as u64,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for HstReader<R> {
+    type Item = Result<MtBar, MtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_bar().transpose()
+    }
+}
+
+/// Writes an MT4 `.hst` history file, format version 401
+pub struct HstWriter<W: Write> {
+    writer: W,
+}
+
+impl HstWriter<io::BufWriter<std::fs::File>> {
+    pub fn from_path<Q: AsRef<std::path::Path>>(
+        path: Q,
+        header: &HstHeader,
+    ) -> Result<Self, MtError> {
+        Self::new(io::BufWriter::new(std::fs::File::create(path)?), header)
+    }
+}
+
+impl<W: Write> HstWriter<W> {
+    /// Writes the header, the symbol is cut to 11 bytes
+    pub fn new(mut writer: W, header: &HstHeader) -> Result<Self, MtError> {
+        let period = mt_period(&header.timeframe)
+            .ok_or_else(|| MtError::Timeframe(header.timeframe.clone()))?;
+        let mut bytes = [0; HST_HEADER_LEN];
+        bytes[..4].copy_from_slice(&HST_VERSION.to_le_bytes());
+        bytes[4..4 + HST_COPYRIGHT.len()].copy_from_slice(HST_COPYRIGHT.as_bytes());
+        let symbol = &header.symbol.as_bytes()[..header.symbol.len().min(11)];
+        bytes[68..68 + symbol.len()].copy_from_slice(symbol);
+        bytes[80..84].copy_from_slice(&period.to_le_bytes());
+        bytes[84..88].copy_from_slice(&header.digits.to_le_bytes());
+        writer.write_all(&bytes)?;
+        Ok(Self { writer })
+    }
+
+    pub fn write_bar(&mut self, bar: &MtBar) -> Result<(), MtError> {
+        let mut record = [0; HST_RECORD_LEN];
+        record[..8].copy_from_slice(&bar.bar.bar_start.and_utc().timestamp().to_le_bytes());
+        let prices = [bar.bar.open, bar.bar.high, bar.bar.low, bar.bar.close];
+        for (i, price) in prices.iter().enumerate() {
+            record[8 + i * 8..16 + i * 8].copy_from_slice(&price.to_le_bytes());
+        }
+        record[40..48].copy_from_slice(&(bar.tick_volume as i64).to_le_bytes());
+        record[48..52].copy_from_slice(&bar.spread.to_le_bytes());
+        record[52..60].copy_from_slice(&(bar.real_volume as i64).to_le_bytes());
+        self.writer.write_all(&record)?;
+        Ok(())
+    }
+
+    pub fn into_inner(mut self) -> Result<W, MtError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Layouts of MetaTrader's CSV history export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtCsvFormat {
+    /// `2015.01.02,10:00,open,high,low,close,volume` without a header
+    Mt4,
+    /// Tab separated `<DATE> <TIME> <OPEN> <HIGH> <LOW> <CLOSE> <TICKVOL> <VOL> <SPREAD>`
+    /// with a header, `<TIME>` is left out of daily and longer bars
+    Mt5,
+}
+
+/// Reads bars from MetaTrader's CSV history export
+pub struct MtCsvReader<R> {
+    reader: csv::Reader<R>,
+    record: csv::StringRecord,
+    // positions of the MT5 header columns, `MT5_HEADER` order
+    columns: [Option<usize>; 9],
+    sampler: Box<dyn Sampler>,
+}
+
+impl MtCsvReader<std::fs::File> {
+    pub fn from_path<Q: AsRef<std::path::Path>>(
+        path: Q,
+        format: MtCsvFormat,
+        timeframe: &str,
+    ) -> Result<Self, MtError> {
+        Self::new(std::fs::File::open(path)?, format, timeframe)
+    }
+}
+
+impl<R: Read> MtCsvReader<R> {
+    /// `timeframe` gives the bars their `next_bar_dt`
+    pub fn new(reader: R, format: MtCsvFormat, timeframe: &str) -> Result<Self, MtError> {
+        let sampler = <dyn Sampler>::from_short(timeframe)
+            .ok_or_else(|| MtError::Timeframe(timeframe.to_string()))?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(match format {
+                MtCsvFormat::Mt4 => b',',
+                MtCsvFormat::Mt5 => b'\t',
+            })
+            .has_headers(format == MtCsvFormat::Mt5)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let columns = match format {
+            // no spread and real volume
+            MtCsvFormat::Mt4 => [
+                Some(0),
+                Some(1),
+                Some(2),
+                Some(3),
+                Some(4),
+                Some(5),
+                Some(6),
+                None,
+                None,
+            ],
+            MtCsvFormat::Mt5 => {
+                let headers = reader.headers()?;
+                let mut columns = [None; 9];
+                for (column, name) in columns.iter_mut().zip(MT5_HEADER) {
+                    *column = headers.iter().position(|header| header == name);
+                }
+                if columns[0].is_none() || columns[2..6].contains(&None) {
+                    return Err(MtError::Format("missing column"));
+                }
+                columns
+            }
+        };
+        Ok(Self {
+            reader,
+            record: csv::StringRecord::new(),
+            columns,
+            sampler,
+        })
+    }
+
+    fn field(&self, column: usize) -> Option<&str> {
+        self.columns[column].and_then(|index| self.record.get(index))
+    }
+
+    fn parse<T: std::str::FromStr>(&self, column: usize, default: Option<T>) -> Result<T, MtError> {
+        match (self.field(column), default) {
+            (None, Some(default)) => Ok(default),
+            (value, _) => value
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| self.parse_error(column)),
+        }
+    }
+
+    fn parse_error(&self, column: usize) -> MtError {
+        MtError::Parse {
+            line: self.record.position().map_or(0, |position| position.line()),
+            column: MT5_HEADER[column],
+            value: self.field(column).unwrap_or("").to_string(),
+        }
+    }
+
+    fn parse_bar(&self) -> Result<MtBar, MtError> {
+        let date = self
+            .field(0)
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y.%m.%d").ok())
+            .ok_or_else(|| self.parse_error(0))?;
+        let time = match self.field(1) {
+            Some(time) => NaiveTime::parse_from_str(time, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+                .map_err(|_| self.parse_error(1))?,
+            None => NaiveTime::MIN,
+        };
+        let bar_start = date.and_time(time);
+        Ok(MtBar {
+            bar: Bar {
+                open: self.parse(2, None)?,
+                high: self.parse(3, None)?,
+                low: self.parse(4, None)?,
+                close: self.parse(5, None)?,
+                bar_start,
+                next_bar_dt: bar_end(&*self.sampler, bar_start),
+            },
+            tick_volume: self.parse(6, Some(0))?,
+            real_volume: self.parse(7, Some(0))?,
+            spread: self.parse(8, Some(0))?,
+        })
+    }
+}
+
+impl<R: Read> Iterator for MtCsvReader<R> {
+    type Item = Result<MtBar, MtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_record(&mut self.record) {
+            Ok(true) => Some(self.parse_bar()),
+            Ok(false) => None,
+            Err(err) => Some(Err(err.into())),
+        }
+    }
+}
+
+/// Writes bars in MetaTrader's CSV history export layout, with CRLF line ends
+pub struct MtCsvWriter<W: Write> {
+    writer: csv::Writer<W>,
+    format: MtCsvFormat,
+    digits: Option<usize>,
+}
+
+impl MtCsvWriter<std::fs::File> {
+    pub fn from_path<Q: AsRef<std::path::Path>>(
+        path: Q,
+        format: MtCsvFormat,
+    ) -> Result<Self, MtError> {
+        Self::new(std::fs::File::create(path)?, format)
+    }
+}
+
+impl<W: Write> MtCsvWriter<W> {
+    /// Writes the header row of `MtCsvFormat::Mt5`
+    pub fn new(writer: W, format: MtCsvFormat) -> Result<Self, MtError> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(match format {
+                MtCsvFormat::Mt4 => b',',
+                MtCsvFormat::Mt5 => b'\t',
+            })
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(writer);
+        if format == MtCsvFormat::Mt5 {
+            writer.write_record(MT5_HEADER)?;
+        }
+        Ok(Self {
+            writer,
+            format,
+            digits: None,
+        })
+    }
+
+    /// Decimal places of prices, the shortest exact form by default
+    pub fn digits(mut self, digits: usize) -> Self {
+        self.digits = Some(digits);
+        self
+    }
+
+    pub fn write_bar(&mut self, bar: &MtBar) -> Result<(), MtError> {
+        let price = |price: f64| match self.digits {
+            Some(digits) => format!("{:.*}", digits, price),
+            None => price.to_string(),
+        };
+        let dt = bar.bar.bar_start;
+        let mut record = vec![
+            dt.format("%Y.%m.%d").to_string(),
+            match self.format {
+                MtCsvFormat::Mt4 => dt.format("%H:%M").to_string(),
+                MtCsvFormat::Mt5 => dt.format("%H:%M:%S").to_string(),
+            },
+            price(bar.bar.open),
+            price(bar.bar.high),
+            price(bar.bar.low),
+            price(bar.bar.close),
+            bar.tick_volume.to_string(),
+        ];
+        if self.format == MtCsvFormat::Mt5 {
+            record.push(bar.real_volume.to_string());
+            record.push(bar.spread.to_string());
+        }
+        self.writer.write_record(&record)?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> Result<W, MtError> {
+        self.writer
+            .into_inner()
+            .map_err(|err| MtError::Io(err.into_error()))
+    }
+}
+
+// MetaTrader bars may start off the sampler's grid, like weekly bars on Sundays
+fn bar_end(sampler: &dyn Sampler, bar_start: NaiveDateTime) -> NaiveDateTime {
+    bar_start + (sampler.next_bar_dt(bar_start) - sampler.bar_start(bar_start))
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), MtError> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => MtError::Format("truncated file"),
+        _ => MtError::Io(err),
+    })
+}
+
+fn i32_at(bytes: &[u8], at: usize) -> i32 {
+    i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn i64_at(bytes: &[u8], at: usize) -> i64 {
+    i64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn f64_at(bytes: &[u8], at: usize) -> f64 {
+    f64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HST: &[u8] = include_bytes!("../tests/data/EURUSD60.hst");
+    const MT4_CSV: &str = include_str!("../tests/data/EURUSD60.csv");
+    const MT5_CSV: &str = include_str!("../tests/data/EURUSD_H1.csv");
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn periods() {
+        for short in Bar::available_timeframes() {
+            match mt_period(short) {
+                Some(period) => assert_eq!(mt_timeframe(period).unwrap(), short),
+                None => assert!(["Q1", "Y1"].contains(&short)),
+            }
+        }
+        assert_eq!(mt_period("H4"), Some(240));
+        assert_eq!(mt_timeframe(90).as_deref(), Some("M90"));
+        assert!(<dyn Sampler>::from_short("M90").is_some());
+        assert_eq!(mt_timeframe(0), None);
+        assert_eq!(mt_period("X1"), None);
+
+        // uniform bars of a day or more don't line up with MetaTrader's
+        for period in [2880, 1441, 4320] {
+            assert_eq!(mt_timeframe(period), None);
+        }
+        for short in ["M2880", "H24", "M1440", "H48", "M05"] {
+            assert_eq!(mt_period(short), None, "{}", short);
+        }
+    }
+
+    // an HST file laid out field by field as the MQL4 documentation has it
+    fn hst(period: i32, records: &[(i64, [f64; 4], i64, i32, i64)]) -> Vec<u8> {
+        let mut file = vec![];
+        file.extend(401i32.to_le_bytes());
+        let mut copyright = b"(C)opyright 2003, MetaQuotes Software Corp.".to_vec();
+        copyright.resize(64, 0);
+        file.extend(copyright);
+        let mut symbol = b"GBPJPY".to_vec();
+        symbol.resize(12, 0);
+        file.extend(symbol);
+        file.extend(period.to_le_bytes());
+        file.extend(3i32.to_le_bytes()); // digits
+        file.extend(1420070400i32.to_le_bytes()); // timesign
+        file.extend(1420156800i32.to_le_bytes()); // last_sync
+        file.extend([0; 13 * 4]); // unused
+        for (ctm, prices, volume, spread, real_volume) in records {
+            file.extend(ctm.to_le_bytes());
+            for price in prices {
+                file.extend(price.to_le_bytes());
+            }
+            file.extend(volume.to_le_bytes());
+            file.extend(spread.to_le_bytes());
+            file.extend(real_volume.to_le_bytes());
+        }
+        file
+    }
+
+    #[test]
+    fn hst_layout() {
+        let file = hst(
+            30,
+            &[
+                (1420189200, [187.51, 187.62, 187.3, 187.41], 512, 3, 7),
+                (1420191000, [187.41, 187.5, 187.2, 187.22], 448, 4, 0),
+            ],
+        );
+        let reader = HstReader::new(&file[..]).unwrap();
+        assert_eq!(
+            reader.header(),
+            &HstHeader {
+                symbol: "GBPJPY".to_string(),
+                timeframe: "M30".to_string(),
+                digits: 3,
+            }
+        );
+        let bars: Vec<_> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].bar.bar_start, date("2015-01-02 09:00:00"));
+        assert_eq!(bars[0].bar.next_bar_dt, date("2015-01-02 09:30:00"));
+        assert_eq!(bars[1].bar.close, 187.22);
+        assert_eq!(
+            (bars[0].tick_volume, bars[0].spread, bars[0].real_volume),
+            (512, 3, 7)
+        );
+
+        assert!(matches!(
+            HstReader::new(&hst(2880, &[])[..]),
+            Err(MtError::Period(2880))
+        ));
+    }
+
+    #[test]
+    fn hst_round_trip() {
+        let reader = HstReader::new(HST).unwrap();
+        let header = reader.header().clone();
+        assert_eq!(
+            header,
+            HstHeader {
+                symbol: "EURUSD".to_string(),
+                timeframe: "H1".to_string(),
+                digits: 5,
+            }
+        );
+        let bars: Vec<_> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(bars.len(), 6);
+        assert_eq!(
+            bars[0],
+            MtBar {
+                bar: Bar {
+                    open: 1.20402,
+                    high: 1.20581,
+                    low: 1.20339,
+                    close: 1.20533,
+                    bar_start: date("2015-01-02 09:00:00"),
+                    next_bar_dt: date("2015-01-02 10:00:00"),
+                },
+                tick_volume: 1834,
+                spread: 2,
+                real_volume: 0,
+            }
+        );
+
+        let mut writer = HstWriter::new(vec![], &header).unwrap();
+        for bar in &bars {
+            writer.write_bar(bar).unwrap();
+        }
+        assert_eq!(writer.into_inner().unwrap(), HST);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        for (format, fixture) in [(MtCsvFormat::Mt4, MT4_CSV), (MtCsvFormat::Mt5, MT5_CSV)] {
+            let bars: Vec<_> = MtCsvReader::new(fixture.as_bytes(), format, "H1")
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            let mut writer = MtCsvWriter::new(vec![], format).unwrap().digits(5);
+            for bar in &bars {
+                writer.write_bar(bar).unwrap();
+            }
+            let written = writer.into_inner().unwrap();
+            assert_eq!(String::from_utf8(written).unwrap(), fixture);
+        }
+    }
+
+    #[test]
+    fn formats_agree() {
+        let hst: Vec<_> = HstReader::new(HST).unwrap().map(Result::unwrap).collect();
+        let mt4: Vec<_> = MtCsvReader::new(MT4_CSV.as_bytes(), MtCsvFormat::Mt4, "H1")
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let mt5: Vec<_> = MtCsvReader::new(MT5_CSV.as_bytes(), MtCsvFormat::Mt5, "H1")
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        for ((hst, mt4), mt5) in hst.iter().zip(&mt4).zip(&mt5) {
+            assert_eq!(hst.bar, mt4.bar);
+            assert_eq!(hst, mt5);
+            assert_eq!(hst.tick_volume, mt4.tick_volume);
+        }
+    }
+
+    #[test]
+    fn weekly_and_daily_bars() {
+        // MetaTrader weeks start on Sunday, MT5 leaves out the time of daily bars
+        let csv = "<DATE>\t<OPEN>\t<HIGH>\t<LOW>\t<CLOSE>\t<TICKVOL>\n\
+                   2015.01.04\t1.2\t1.3\t1.1\t1.25\t100\n";
+        let bar = MtCsvReader::new(csv.as_bytes(), MtCsvFormat::Mt5, "W1")
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(bar.bar.bar_start, date("2015-01-04 00:00:00"));
+        assert_eq!(bar.bar.next_bar_dt, date("2015-01-11 00:00:00"));
+        assert_eq!(bar.tick_volume, 100);
+    }
+
+    #[test]
+    fn errors() {
+        let mut hst = HST.to_vec();
+        hst[0] = 144;
+        assert!(matches!(
+            HstReader::new(&hst[..]),
+            Err(MtError::Format("unsupported version"))
+        ));
+        let truncated: Vec<_> = HstReader::new(&HST[..HST.len() - 1]).unwrap().collect();
+        assert!(matches!(
+            truncated.last(),
+            Some(Err(MtError::Format("truncated record")))
+        ));
+
+        let header = HstHeader {
+            symbol: "EURUSD".to_string(),
+            timeframe: "Q1".to_string(),
+            digits: 5,
+        };
+        assert_eq!(
+            HstWriter::new(vec![], &header).err().unwrap().to_string(),
+            "no MetaTrader period for Q1"
+        );
+
+        let csv = "2015.01.02,10:00,1.2,1.3,x,1.25,10\n";
+        let err = MtCsvReader::new(csv.as_bytes(), MtCsvFormat::Mt4, "H1")
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 1: invalid <LOW>: \"x\"");
+    }
+}
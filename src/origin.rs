@@ -0,0 +1,203 @@
+use chrono::prelude::*;
+use chrono::Duration;
+
+/// Where the buckets of a fixed-length period are aligned
+///
+/// Applies to the minute, hour and day samplers and `Uniform`. Weeks and months
+/// always follow the calendar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Origin {
+    /// Buckets restart at midnight, the last bar of a day is truncated
+    /// if the period doesn't divide 24 hours. Periods longer than a day are
+    /// aligned on the epoch instead.
+    #[default]
+    StartOfDay,
+    /// 1970-01-01 00:00:00, bars run across days
+    Epoch,
+    /// The time of the first tick the sampler receives
+    FirstTick,
+    Custom(NaiveDateTime),
+}
+
+impl Origin {
+    pub(crate) fn bar_start(self, period: Duration, dt: NaiveDateTime) -> NaiveDateTime {
+        let origin = self.for_period(period).resolve(dt);
+        origin + from_nanos(nanos(dt - origin).div_euclid(nanos(period)) * nanos(period))
+    }
+
+    pub(crate) fn next_bar_dt(self, period: Duration, dt: NaiveDateTime) -> NaiveDateTime {
+        let next_bar_dt = self.bar_start(period, dt) + period;
+        match self.for_period(period) {
+            Origin::StartOfDay => {
+                next_bar_dt.min(dt.date().and_hms_opt(0, 0, 0).unwrap() + Duration::days(1))
+            }
+            _ => next_bar_dt,
+        }
+    }
+
+    // a bar can't restart at midnight and last longer than a day
+    fn for_period(self, period: Duration) -> Self {
+        match self {
+            Origin::StartOfDay if period > Duration::days(1) => Origin::Epoch,
+            origin => origin,
+        }
+    }
+
+    // samplers replace `FirstTick` with `Custom` once they see the first tick
+    fn resolve(self, dt: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Origin::StartOfDay => dt.date().and_hms_opt(0, 0, 0).unwrap(),
+            Origin::Epoch => NaiveDate::from_ymd_opt(1970, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            Origin::FirstTick => dt,
+            Origin::Custom(origin) => origin,
+        }
+    }
+}
+
+fn nanos(duration: Duration) -> i128 {
+    let seconds = duration.num_seconds();
+    let subsec = (duration - Duration::seconds(seconds))
+        .num_nanoseconds()
+        .unwrap_or(0);
+    seconds as i128 * 1_000_000_000 + subsec as i128
+}
+
+fn from_nanos(nanos: i128) -> Duration {
+    Duration::seconds(nanos.div_euclid(1_000_000_000) as i64)
+        + Duration::nanoseconds(nanos.rem_euclid(1_000_000_000) as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use chrono::prelude::*;
+    use chrono::Duration;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn bar(value: f64, start: &str, end: &str) -> Bar {
+        Bar {
+            open: value,
+            high: value,
+            low: value,
+            close: value,
+            bar_start: date(start),
+            next_bar_dt: date(end),
+        }
+    }
+
+    #[test]
+    fn start_of_day_truncates_last_bar() {
+        let mut sampler = Uniform::new(Duration::hours(5));
+        sampler.next_bar(date("2015-01-01 21:00:00"), 1.);
+        assert_eq!(
+            sampler.next_bar(date("2015-01-02 06:00:00"), 2.),
+            Some(Bars::WithEmpty(
+                bar(1., "2015-01-01 20:00:00", "2015-01-02 00:00:00"),
+                vec![bar(1., "2015-01-02 00:00:00", "2015-01-02 05:00:00")]
+            ))
+        );
+    }
+
+    #[test]
+    fn epoch_runs_across_days() {
+        let mut sampler = <dyn Sampler>::from_short("H5").unwrap();
+        sampler.set_origin(Origin::Epoch);
+        // 2015-01-01 is 16436 days = 78892.8 periods of 5 hours after the epoch
+        sampler.next_bar(date("2015-01-01 21:00:00"), 1.);
+        assert_eq!(
+            sampler.next_bar(date("2015-01-02 08:00:00"), 2.),
+            Some(Bars::WithEmpty(
+                bar(1., "2015-01-01 21:00:00", "2015-01-02 02:00:00"),
+                vec![bar(1., "2015-01-02 02:00:00", "2015-01-02 07:00:00")]
+            ))
+        );
+        assert_eq!(
+            sampler.current_incomplete(),
+            Some(bar(2., "2015-01-02 07:00:00", "2015-01-02 12:00:00"))
+        );
+    }
+
+    #[test]
+    fn longer_than_a_day() {
+        let mut sampler = <dyn Sampler>::from_short("H48").unwrap();
+        // 2015-01-01 is an even number of days after the epoch
+        sampler.next_bar(date("2015-01-02 21:00:00"), 1.);
+        assert_eq!(
+            sampler.next_bar(date("2015-01-03 08:00:00"), 2.),
+            Some(Bars::Single(bar(
+                1.,
+                "2015-01-01 00:00:00",
+                "2015-01-03 00:00:00"
+            )))
+        );
+        assert_eq!(
+            sampler.current_incomplete(),
+            Some(bar(2., "2015-01-03 00:00:00", "2015-01-05 00:00:00"))
+        );
+    }
+
+    #[test]
+    fn first_tick() {
+        let mut sampler = M15::default();
+        sampler.set_origin(Origin::FirstTick);
+        sampler.next_bar(date("2015-01-01 10:07:00"), 1.);
+        assert_eq!(
+            sampler.next_bar(date("2015-01-01 10:22:00"), 2.),
+            Some(Bars::Single(bar(
+                1.,
+                "2015-01-01 10:07:00",
+                "2015-01-01 10:22:00"
+            )))
+        );
+        assert_eq!(
+            sampler.bar_start(date("2015-01-01 09:59:00")),
+            date("2015-01-01 09:52:00")
+        );
+    }
+
+    #[test]
+    fn custom_origin() {
+        // trading days starting at 17:00
+        let mut sampler = D1::default();
+        sampler.set_origin(Origin::Custom(date("2015-01-01 17:00:00")));
+        sampler.next_bar(date("2015-01-05 16:59:59"), 1.);
+        assert_eq!(
+            sampler.next_bar(date("2015-01-05 17:00:00"), 2.),
+            Some(Bars::Single(bar(
+                1.,
+                "2015-01-04 17:00:00",
+                "2015-01-05 17:00:00"
+            )))
+        );
+
+        let mut sampler = Uniform::new(Duration::minutes(7));
+        sampler.set_origin(Origin::Custom(date("2015-01-01 10:00:30")));
+        sampler.next_bar(date("2015-01-01 09:55:00"), 1.);
+        assert_eq!(
+            sampler.current_incomplete(),
+            Some(bar(1., "2015-01-01 09:53:30", "2015-01-01 10:00:30"))
+        );
+    }
+
+    #[test]
+    fn default_alignment_is_unchanged() {
+        for short in Bar::available_timeframes() {
+            let sampler = <dyn Sampler>::from_short(short).unwrap();
+            let mut epoch = <dyn Sampler>::from_short(short).unwrap();
+            epoch.set_origin(Origin::Epoch);
+
+            let mut dt = date("2015-01-01 00:00:00");
+            while dt < date("2015-01-03 00:00:00") {
+                assert_eq!(sampler.bar_start(dt), epoch.bar_start(dt));
+                assert_eq!(sampler.next_bar_dt(dt), epoch.next_bar_dt(dt));
+                dt += Duration::seconds(59);
+            }
+        }
+    }
+}
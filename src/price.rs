@@ -0,0 +1,222 @@
+use std::fmt::Debug;
+
+/// A value samplers can aggregate into bars.
+///
+/// Implemented for `f64`, `f32`, `i64` (prices as integer ticks) and, with the `decimal`
+/// feature, `rust_decimal::Decimal`.
+pub trait Price: Copy + PartialOrd + Debug + Send + Sync + 'static {
+    /// `false` for NaN and infinities
+    fn is_finite(self) -> bool;
+
+    fn is_nan(self) -> bool;
+
+    /// Used for derived statistics like averages and spreads
+    fn to_f64(self) -> f64;
+}
+
+impl Price for f64 {
+    fn is_finite(self) -> bool {
+        f64::is_finite(self)
+    }
+
+    fn is_nan(self) -> bool {
+        f64::is_nan(self)
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+impl Price for f32 {
+    fn is_finite(self) -> bool {
+        f32::is_finite(self)
+    }
+
+    fn is_nan(self) -> bool {
+        f32::is_nan(self)
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Price for i64 {
+    fn is_finite(self) -> bool {
+        true
+    }
+
+    fn is_nan(self) -> bool {
+        false
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+#[cfg(feature = "decimal")]
+impl Price for rust_decimal::Decimal {
+    fn is_finite(self) -> bool {
+        true
+    }
+
+    fn is_nan(self) -> bool {
+        false
+    }
+
+    fn to_f64(self) -> f64 {
+        rust_decimal::prelude::ToPrimitive::to_f64(&self).unwrap_or(f64::NAN)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use chrono::prelude::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn bar<P>(open: P, high: P, low: P, close: P, start: &str, end: &str) -> Bar<P> {
+        Bar {
+            open,
+            high,
+            low,
+            close,
+            bar_start: date(start),
+            next_bar_dt: date(end),
+        }
+    }
+
+    #[test]
+    fn integer_ticks() {
+        let mut sampler = M15::default();
+        assert_eq!(
+            sampler.next_bar(date("2015-01-01 10:03:00"), 10_250i64),
+            None
+        );
+        assert_eq!(sampler.next_bar(date("2015-01-01 10:04:00"), 10_275), None);
+        assert_eq!(sampler.next_bar(date("2015-01-01 10:05:00"), 10_200), None);
+
+        let res = sampler.next_bar(date("2015-01-01 10:31:00"), 10_300);
+        assert_eq!(
+            res,
+            Some(Bars::WithEmpty(
+                bar(
+                    10_250,
+                    10_275,
+                    10_200,
+                    10_200,
+                    "2015-01-01 10:00:00",
+                    "2015-01-01 10:15:00"
+                ),
+                vec![bar(
+                    10_200,
+                    10_200,
+                    10_200,
+                    10_200,
+                    "2015-01-01 10:15:00",
+                    "2015-01-01 10:30:00"
+                )]
+            ))
+        );
+    }
+
+    #[test]
+    fn single_precision() {
+        let mut sampler = <dyn Sampler<f32>>::from_short("H1").unwrap();
+        assert_eq!(sampler.next_bar(date("2015-01-01 10:03:00"), 1.5f32), None);
+        assert!(matches!(
+            sampler.try_next_bar(date("2015-01-01 10:04:00"), f32::NAN),
+            Err(PriceError::NonFinite(value)) if value.is_nan()
+        ));
+        assert_eq!(
+            sampler.next_bar(date("2015-01-01 11:00:00"), 2.5),
+            Some(Bars::Single(bar(
+                1.5,
+                1.5,
+                1.5,
+                1.5,
+                "2015-01-01 10:00:00",
+                "2015-01-01 11:00:00"
+            )))
+        );
+    }
+
+    #[test]
+    fn calendar_samplers_are_generic() {
+        let mut days = D1::default();
+        let mut weeks = W1::default();
+        let mut months = Mn1::default();
+        for (dt, value) in &[
+            ("2021-01-04 10:00:00", 3i64),
+            ("2021-01-05 10:00:00", 1),
+            ("2021-02-01 10:00:00", 2),
+        ] {
+            days.next_bar(date(dt), *value);
+            weeks.next_bar(date(dt), *value);
+            months.next_bar(date(dt), *value);
+        }
+        assert_eq!(
+            months.current_incomplete(),
+            Some(bar(
+                2,
+                2,
+                2,
+                2,
+                "2021-02-01 00:00:00",
+                "2021-03-01 00:00:00"
+            ))
+        );
+        assert_eq!(
+            weeks.current_incomplete(),
+            Some(bar(
+                2,
+                2,
+                2,
+                2,
+                "2021-02-01 00:00:00",
+                "2021-02-08 00:00:00"
+            ))
+        );
+        assert_eq!(
+            days.current_incomplete(),
+            Some(bar(
+                2,
+                2,
+                2,
+                2,
+                "2021-02-01 00:00:00",
+                "2021-02-02 00:00:00"
+            ))
+        );
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn decimal() {
+        use rust_decimal::Decimal;
+
+        let price = |s: &str| s.parse::<Decimal>().unwrap();
+        let mut sampler = M5::default();
+        sampler.next_bar(date("2015-01-01 10:00:00"), price("0.1"));
+        sampler.next_bar(date("2015-01-01 10:01:00"), price("0.3"));
+        sampler.next_bar(date("2015-01-01 10:02:00"), price("0.2"));
+
+        let res = sampler.next_bar(date("2015-01-01 10:05:00"), price("0.4"));
+        assert_eq!(
+            res,
+            Some(Bars::Single(bar(
+                price("0.1"),
+                price("0.3"),
+                price("0.1"),
+                price("0.2"),
+                "2015-01-01 10:00:00",
+                "2015-01-01 10:05:00"
+            )))
+        );
+    }
+}
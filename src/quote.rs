@@ -0,0 +1,399 @@
+use crate::average::TimeWeighted;
+use crate::validate::{propagating_max, propagating_min};
+use crate::{Bar, Price, PriceError, PricePolicy, Sampler};
+use chrono::prelude::*;
+use std::marker::PhantomData;
+
+/// Top-of-book bar: bid, ask and mid prices plus spread statistics
+#[derive(Debug, PartialEq)]
+pub struct QuoteBar<P = f64> {
+    pub bid: Bar<P>,
+    pub ask: Bar<P>,
+    pub mid: Bar<f64>,
+    /// Mean of `ask - bid` over the quotes of the bar
+    pub avg_spread: f64,
+    pub max_spread: f64,
+    /// `ask - bid` weighted by how long each quote was in effect within the bar.
+    /// The first bar is weighted from its first quote, no quote is in effect before.
+    pub twa_spread: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum QuoteBars<P = f64> {
+    // closing value
+    Single(QuoteBar<P>),
+    // closing value and empty bars
+    WithEmpty(QuoteBar<P>, Vec<QuoteBar<P>>),
+}
+
+/// Resamples `(dt, bid, ask)` quotes using the timeframe of the wrapped sampler
+#[derive(Debug, Clone)]
+pub struct QuoteSampler<T, P = f64> {
+    bid: T,
+    ask: T,
+    spread: Option<Spread>,
+    policy: PricePolicy,
+    price: PhantomData<P>,
+}
+
+impl<P: Price, T: Sampler<P> + Clone> QuoteSampler<T, P> {
+    /// # Panics
+    ///
+    /// If the sampler already has an incomplete bar, its quotes would be missing
+    /// from the spread statistics
+    pub fn new(sampler: T) -> Self {
+        Self::from_samplers(sampler.clone(), sampler)
+    }
+}
+
+impl<P: Price> QuoteSampler<Box<dyn Sampler<P>>, P> {
+    pub fn from_short(short: &str) -> Option<Self> {
+        Some(Self::from_samplers(
+            <dyn Sampler<P>>::from_short(short)?,
+            <dyn Sampler<P>>::from_short(short)?,
+        ))
+    }
+}
+
+impl<P: Price, T: Sampler<P>> QuoteSampler<T, P> {
+    fn from_samplers(mut bid: T, mut ask: T) -> Self {
+        assert!(
+            bid.current_incomplete().is_none() && ask.current_incomplete().is_none(),
+            "sampler already has an incomplete bar"
+        );
+        // prices are validated once for both sides before they reach the samplers
+        bid.set_policy(PricePolicy::Propagate);
+        ask.set_policy(PricePolicy::Propagate);
+        Self {
+            bid,
+            ask,
+            spread: None,
+            policy: PricePolicy::default(),
+            price: PhantomData,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: PricePolicy) {
+        self.policy = policy;
+    }
+
+    /// Same as `try_next_quote`, but a rejected quote is dropped
+    pub fn next_quote(&mut self, dt: NaiveDateTime, bid: P, ask: P) -> Option<QuoteBars<P>> {
+        self.try_next_quote(dt, bid, ask).unwrap_or(None)
+    }
+
+    /// Returns Some(bars) if period has been passed, None otherwise
+    pub fn try_next_quote(
+        &mut self,
+        dt: NaiveDateTime,
+        bid: P,
+        ask: P,
+    ) -> Result<Option<QuoteBars<P>>, PriceError<P>> {
+        for value in &[bid, ask] {
+            if !value.is_finite() {
+                match self.policy {
+                    PricePolicy::Reject => return Err(PriceError::NonFinite(*value)),
+                    PricePolicy::Skip => return Ok(None),
+                    PricePolicy::Propagate => {}
+                }
+            }
+        }
+
+        let closed_bid = self.bid.try_next_bar(dt, bid)?;
+        let closed_ask = self.ask.try_next_bar(dt, ask)?;
+        let mid = (bid.to_f64() + ask.to_f64()) / 2.;
+        let spread = ask.to_f64() - bid.to_f64();
+
+        let bars = match (closed_bid, closed_ask, self.spread.take()) {
+            (Some(bid_bars), Some(ask_bars), Some(state)) => {
+                let (bid_bar, bid_empty) = bid_bars.into_parts();
+                let (ask_bar, ask_empty) = ask_bars.into_parts();
+                let end = bid_bar.next_bar_dt;
+                let bar = state.bar(bid_bar, ask_bar, end);
+                let empty_bars: Vec<_> = bid_empty
+                    .into_iter()
+                    .zip(ask_empty)
+                    .map(|(bid, ask)| state.empty_bar(bid, ask))
+                    .collect();
+
+                // the last quote stays in effect from the start of the new bar
+                let bar_start = self
+                    .bid
+                    .current_incomplete()
+                    .map_or(dt, |bar| bar.bar_start);
+                self.spread = Some(Spread::new(
+                    state.weighted.carry(bar_start, dt, spread),
+                    mid,
+                    spread,
+                ));
+
+                if empty_bars.is_empty() {
+                    Some(QuoteBars::Single(bar))
+                } else {
+                    Some(QuoteBars::WithEmpty(bar, empty_bars))
+                }
+            }
+            (None, None, Some(mut state)) => {
+                state.push(dt, mid, spread);
+                self.spread = Some(state);
+                None
+            }
+            (None, None, None) => {
+                self.spread = Some(Spread::new(TimeWeighted::new(dt, spread), mid, spread));
+                None
+            }
+            // both samplers start without a bar and take the same timestamps
+            _ => unreachable!("bid and ask samplers share the timeframe"),
+        };
+        Ok(bars)
+    }
+
+    /// Statistics of the incomplete bar are computed up to its last quote
+    pub fn current_incomplete(&self) -> Option<QuoteBar<P>> {
+        let state = self.spread.as_ref()?;
+        Some(state.bar(
+            self.bid.current_incomplete()?,
+            self.ask.current_incomplete()?,
+            state.weighted.last_dt(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Spread {
+    mid_open: f64,
+    mid_high: f64,
+    mid_low: f64,
+    mid_close: f64,
+    sum: f64,
+    count: u32,
+    max: f64,
+    weighted: TimeWeighted,
+}
+
+impl Spread {
+    fn new(weighted: TimeWeighted, mid: f64, spread: f64) -> Self {
+        Self {
+            mid_open: mid,
+            mid_high: mid,
+            mid_low: mid,
+            mid_close: mid,
+            sum: spread,
+            count: 1,
+            max: spread,
+            weighted,
+        }
+    }
+
+    fn push(&mut self, dt: NaiveDateTime, mid: f64, spread: f64) {
+        self.mid_high = propagating_max(mid, self.mid_high);
+        self.mid_low = propagating_min(mid, self.mid_low);
+        self.mid_close = mid;
+        self.sum += spread;
+        self.count += 1;
+        self.max = propagating_max(spread, self.max);
+        self.weighted.push(dt, spread);
+    }
+
+    fn bar<P>(&self, bid: Bar<P>, ask: Bar<P>, end: NaiveDateTime) -> QuoteBar<P> {
+        QuoteBar {
+            mid: Bar {
+                open: self.mid_open,
+                high: self.mid_high,
+                low: self.mid_low,
+                close: self.mid_close,
+                bar_start: bid.bar_start,
+                next_bar_dt: bid.next_bar_dt,
+            },
+            avg_spread: self.sum / self.count as f64,
+            max_spread: self.max,
+            twa_spread: self.weighted.average(end),
+            bid,
+            ask,
+        }
+    }
+
+    fn empty_bar<P>(&self, bid: Bar<P>, ask: Bar<P>) -> QuoteBar<P> {
+        QuoteBar {
+            mid: Bar {
+                open: self.mid_close,
+                high: self.mid_close,
+                low: self.mid_close,
+                close: self.mid_close,
+                bar_start: bid.bar_start,
+                next_bar_dt: bid.next_bar_dt,
+            },
+            avg_spread: self.weighted.last,
+            max_spread: self.weighted.last,
+            twa_spread: self.weighted.last,
+            bid,
+            ask,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn bar<P: Copy>(open: P, high: P, low: P, close: P, start: &str, end: &str) -> Bar<P> {
+        Bar {
+            open,
+            high,
+            low,
+            close,
+            bar_start: date(start),
+            next_bar_dt: date(end),
+        }
+    }
+
+    fn flat<P: Copy>(value: P, start: &str, end: &str) -> Bar<P> {
+        bar(value, value, value, value, start, end)
+    }
+
+    #[test]
+    fn quote_bars() {
+        let mut sampler = QuoteSampler::new(M5::default());
+        assert_eq!(
+            sampler.next_quote(date("2015-01-01 10:00:00"), 1., 1.5),
+            None
+        );
+        assert_eq!(
+            sampler.next_quote(date("2015-01-01 10:01:00"), 1.25, 1.5),
+            None
+        );
+        assert_eq!(
+            sampler.next_quote(date("2015-01-01 10:04:00"), 0.75, 1.75),
+            None
+        );
+
+        let res = sampler.next_quote(date("2015-01-01 10:12:00"), 1., 1.25);
+        assert_eq!(
+            res,
+            Some(QuoteBars::WithEmpty(
+                QuoteBar {
+                    bid: bar(
+                        1.,
+                        1.25,
+                        0.75,
+                        0.75,
+                        "2015-01-01 10:00:00",
+                        "2015-01-01 10:05:00"
+                    ),
+                    ask: bar(
+                        1.5,
+                        1.75,
+                        1.5,
+                        1.75,
+                        "2015-01-01 10:00:00",
+                        "2015-01-01 10:05:00"
+                    ),
+                    mid: bar(
+                        1.25,
+                        1.375,
+                        1.25,
+                        1.25,
+                        "2015-01-01 10:00:00",
+                        "2015-01-01 10:05:00"
+                    ),
+                    avg_spread: 1.75 / 3.,
+                    max_spread: 1.,
+                    // 0.5 for 60s, 0.25 for 180s, 1.0 for 60s
+                    twa_spread: 135. / 300.,
+                },
+                vec![QuoteBar {
+                    bid: flat(0.75, "2015-01-01 10:05:00", "2015-01-01 10:10:00"),
+                    ask: flat(1.75, "2015-01-01 10:05:00", "2015-01-01 10:10:00"),
+                    mid: flat(1.25, "2015-01-01 10:05:00", "2015-01-01 10:10:00"),
+                    avg_spread: 1.,
+                    max_spread: 1.,
+                    twa_spread: 1.,
+                }]
+            ))
+        );
+
+        // 1.0 spread is still in effect from 10:10 until the 10:12 quote
+        let res = sampler.next_quote(date("2015-01-01 10:15:00"), 1., 1.5);
+        assert_eq!(
+            res,
+            Some(QuoteBars::Single(QuoteBar {
+                bid: flat(1., "2015-01-01 10:10:00", "2015-01-01 10:15:00"),
+                ask: flat(1.25, "2015-01-01 10:10:00", "2015-01-01 10:15:00"),
+                mid: flat(1.125, "2015-01-01 10:10:00", "2015-01-01 10:15:00"),
+                avg_spread: 0.25,
+                max_spread: 0.25,
+                twa_spread: 165. / 300.,
+            }))
+        );
+    }
+
+    #[test]
+    fn incomplete_quote_bar() {
+        let mut sampler = QuoteSampler::from_short("M15").unwrap();
+        sampler.next_quote(date("2015-01-01 10:00:00"), 1., 2.);
+        sampler.next_quote(date("2015-01-01 10:01:00"), 1., 1.5);
+        sampler.next_quote(date("2015-01-01 10:02:00"), 1., 1.25);
+
+        let bar = sampler.current_incomplete().unwrap();
+        assert_eq!(
+            bar.bid,
+            flat(1., "2015-01-01 10:00:00", "2015-01-01 10:15:00")
+        );
+        assert_eq!(bar.max_spread, 1.);
+        assert_eq!(bar.avg_spread, 1.75 / 3.);
+        assert_eq!(bar.twa_spread, 0.75);
+    }
+
+    #[test]
+    fn integer_quotes() {
+        let mut sampler = QuoteSampler::new(H1::default());
+        sampler.next_quote(date("2015-01-01 10:00:00"), 100i64, 102);
+        sampler.next_quote(date("2015-01-01 10:30:00"), 101, 104);
+
+        let bar = match sampler.next_quote(date("2015-01-01 11:00:00"), 100, 101) {
+            Some(QuoteBars::Single(bar)) => bar,
+            res => panic!("unexpected {:?}", res),
+        };
+        assert_eq!(bar.ask.high, 104);
+        assert_eq!(bar.mid.close, 102.5);
+        assert_eq!(bar.twa_spread, 2.5);
+    }
+
+    #[test]
+    fn invalid_quote() {
+        let mut sampler = QuoteSampler::new(M1::default());
+        sampler.next_quote(date("2015-01-01 10:00:00"), 1., 2.);
+        assert_eq!(
+            sampler.try_next_quote(date("2015-01-01 10:00:01"), 1., f64::INFINITY),
+            Err(PriceError::NonFinite(f64::INFINITY))
+        );
+
+        sampler.set_policy(PricePolicy::Skip);
+        assert_eq!(
+            sampler.try_next_quote(date("2015-01-01 10:00:02"), f64::NAN, 2.),
+            Ok(None)
+        );
+        let bar = sampler.current_incomplete().unwrap();
+        assert_eq!(
+            bar.bid,
+            flat(1., "2015-01-01 10:00:00", "2015-01-01 10:01:00")
+        );
+        assert_eq!(
+            bar.ask,
+            flat(2., "2015-01-01 10:00:00", "2015-01-01 10:01:00")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "sampler already has an incomplete bar")]
+    fn pre_fed_sampler() {
+        let mut sampler = M1::default();
+        sampler.next_bar(date("2015-01-01 10:00:00"), 1.);
+        QuoteSampler::new(sampler);
+    }
+}
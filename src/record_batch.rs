@@ -0,0 +1,466 @@
+use crate::{BarColumns, BatchError, BatchSampler};
+use arrow::array::{
+    Array, ArrayRef, AsArray, BooleanArray, Float64Array, RecordBatch, TimestampNanosecondArray,
+};
+use arrow::compute::cast;
+use arrow::datatypes::{
+    DataType, Field, Float64Type, Schema, SchemaRef, TimeUnit, TimestampNanosecondType,
+};
+use arrow::error::ArrowError;
+use chrono::prelude::*;
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum RecordBatchError {
+    Arrow(ArrowError),
+    Parquet(ParquetError),
+    /// A tick column that isn't in the batch, or has a type that can't be cast
+    Column(String),
+    /// A tick without a timestamp, or one out of the nanosecond range
+    NullTime {
+        row: usize,
+    },
+    /// A tick refused by the price policy, with the bars closed by the ticks before it
+    Price {
+        error: BatchError,
+        bars: RecordBatch,
+    },
+}
+
+impl fmt::Display for RecordBatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordBatchError::Arrow(err) => write!(f, "{}", err),
+            RecordBatchError::Parquet(err) => write!(f, "{}", err),
+            RecordBatchError::Column(name) => write!(f, "invalid column: {}", name),
+            RecordBatchError::NullTime { row } => write!(f, "row {}: invalid time", row),
+            RecordBatchError::Price { error, .. } => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for RecordBatchError {}
+
+impl From<ArrowError> for RecordBatchError {
+    fn from(err: ArrowError) -> Self {
+        RecordBatchError::Arrow(err)
+    }
+}
+
+impl From<ParquetError> for RecordBatchError {
+    fn from(err: ParquetError) -> Self {
+        RecordBatchError::Parquet(err)
+    }
+}
+
+/// Resamples tick `RecordBatch`es into bar `RecordBatch`es
+///
+/// Ticks have a timestamp column of any unit, read as UTC, a price column cast to
+/// `Float64` and an optional size column. A null price counts as NaN, a null size as zero.
+/// Bars have the columns of `bar_schema`.
+pub struct RecordBatchSampler {
+    sampler: Box<dyn BatchSampler>,
+    time: String,
+    price: String,
+    volume: String,
+    // reused between batches
+    dt: Vec<NaiveDateTime>,
+}
+
+impl RecordBatchSampler {
+    /// Reads the `time`, `price` and `volume` columns
+    pub fn new(sampler: Box<dyn BatchSampler>) -> Self {
+        Self {
+            sampler,
+            time: "time".to_string(),
+            price: "price".to_string(),
+            volume: "volume".to_string(),
+            dt: vec![],
+        }
+    }
+
+    pub fn from_short(short: &str) -> Option<Self> {
+        Some(Self::new(<dyn BatchSampler>::from_short(short)?))
+    }
+
+    /// Names of the tick columns, the volume column may be missing from the batches
+    pub fn columns(mut self, time: &str, price: &str, volume: &str) -> Self {
+        self.time = time.to_string();
+        self.price = price.to_string();
+        self.volume = volume.to_string();
+        self
+    }
+
+    /// For the policy, closure and origin
+    pub fn sampler_mut(&mut self) -> &mut dyn BatchSampler {
+        self.sampler.as_mut()
+    }
+
+    /// Bars closed by the ticks of `batch`, may be empty
+    ///
+    /// A tick refused by the price policy stops the batch, the error carries the
+    /// bars closed by the ticks before it. The sampler has taken those ticks, so
+    /// the rest of the batch goes on from the refused row.
+    pub fn resample(&mut self, batch: &RecordBatch) -> Result<RecordBatch, RecordBatchError> {
+        let column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .ok_or_else(|| RecordBatchError::Column(name.to_string()))
+        };
+
+        let time = column(&self.time)?;
+        let time = match time.data_type() {
+            DataType::Timestamp(_, tz) => {
+                cast(time, &DataType::Timestamp(TimeUnit::Nanosecond, tz.clone()))?
+            }
+            _ => return Err(RecordBatchError::Column(self.time.clone())),
+        };
+        let time = time.as_primitive::<TimestampNanosecondType>();
+        self.dt.clear();
+        for row in 0..time.len() {
+            let nanos = time.is_valid(row).then(|| time.value(row));
+            match nanos.and_then(from_nanos) {
+                Some(dt) => self.dt.push(dt),
+                None => return Err(RecordBatchError::NullTime { row }),
+            }
+        }
+
+        let price = to_f64(column(&self.price)?, &self.price, f64::NAN)?;
+        let volume = match batch.column_by_name(&self.volume) {
+            Some(volume) => Some(to_f64(volume, &self.volume, 0.)?),
+            None => None,
+        };
+
+        let mut bars = BarColumns::default();
+        match self
+            .sampler
+            .resample(&self.dt, &price, volume.as_deref(), &mut bars)
+        {
+            Ok(()) => bars_to_batch(bars),
+            Err(error) => Err(RecordBatchError::Price {
+                error,
+                bars: bars_to_batch(bars)?,
+            }),
+        }
+    }
+
+    /// The incomplete bar as a batch of one row, or an empty batch
+    pub fn current_incomplete(&self) -> Result<RecordBatch, RecordBatchError> {
+        let mut bars = BarColumns::default();
+        if let Some(bar) = self.sampler.current_incomplete() {
+            bars.push(bar);
+        }
+        bars_to_batch(bars)
+    }
+}
+
+impl fmt::Debug for RecordBatchSampler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RecordBatchSampler")
+            .field("time", &self.time)
+            .field("price", &self.price)
+            .field("volume", &self.volume)
+            .finish()
+    }
+}
+
+/// `bar_start` and `next_bar_dt` as nanosecond timestamps without a timezone, then
+/// `open`, `high`, `low`, `close`, `volume`, `vwap` and `twap` as `Float64`, and
+/// `empty` as `Boolean`, true for the flat bars of periods without ticks
+pub fn bar_schema() -> SchemaRef {
+    let time = DataType::Timestamp(TimeUnit::Nanosecond, None);
+    let fields: Vec<_> = ["open", "high", "low", "close", "volume", "vwap", "twap"]
+        .iter()
+        .map(|name| Field::new(*name, DataType::Float64, false))
+        .collect();
+    Arc::new(Schema::new(
+        vec![
+            Field::new("bar_start", time.clone(), false),
+            Field::new("next_bar_dt", time, false),
+        ]
+        .into_iter()
+        .chain(fields)
+        .chain([Field::new("empty", DataType::Boolean, false)])
+        .collect::<Vec<_>>(),
+    ))
+}
+
+/// Resamples every batch and writes the bars to Parquet, the incomplete bar last
+/// if `with_incomplete` is set
+///
+/// Returns the number of bars written.
+pub fn resample_parquet<I, W>(
+    batches: I,
+    sampler: &mut RecordBatchSampler,
+    writer: W,
+    with_incomplete: bool,
+) -> Result<u64, RecordBatchError>
+where
+    I: IntoIterator<Item = Result<RecordBatch, ArrowError>>,
+    W: Write + Send,
+{
+    let mut writer = ArrowWriter::try_new(writer, bar_schema(), None)?;
+    let mut count = 0;
+    for batch in batches {
+        let bars = sampler.resample(&batch?)?;
+        count += bars.num_rows() as u64;
+        writer.write(&bars)?;
+    }
+    if with_incomplete {
+        let bars = sampler.current_incomplete()?;
+        count += bars.num_rows() as u64;
+        writer.write(&bars)?;
+    }
+    writer.close()?;
+    Ok(count)
+}
+
+fn to_f64(array: &ArrayRef, name: &str, null: f64) -> Result<Vec<f64>, RecordBatchError> {
+    let array =
+        cast(array, &DataType::Float64).map_err(|_| RecordBatchError::Column(name.to_string()))?;
+    let array = array.as_primitive::<Float64Type>();
+    Ok((0..array.len())
+        .map(|row| {
+            if array.is_valid(row) {
+                array.value(row)
+            } else {
+                null
+            }
+        })
+        .collect())
+}
+
+fn from_nanos(nanos: i64) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(
+        nanos.div_euclid(1_000_000_000),
+        nanos.rem_euclid(1_000_000_000) as u32,
+    )
+    .map(|dt| dt.naive_utc())
+}
+
+fn to_nanos(dt: &[NaiveDateTime]) -> Result<TimestampNanosecondArray, ArrowError> {
+    dt.iter()
+        .map(|dt| {
+            dt.and_utc().timestamp_nanos_opt().ok_or_else(|| {
+                ArrowError::CastError(format!("{} is out of the nanosecond range", dt))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(TimestampNanosecondArray::from)
+}
+
+fn bars_to_batch(bars: BarColumns) -> Result<RecordBatch, RecordBatchError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(to_nanos(&bars.bar_start)?),
+        Arc::new(to_nanos(&bars.next_bar_dt)?),
+        Arc::new(Float64Array::from(bars.open)),
+        Arc::new(Float64Array::from(bars.high)),
+        Arc::new(Float64Array::from(bars.low)),
+        Arc::new(Float64Array::from(bars.close)),
+        Arc::new(Float64Array::from(bars.volume)),
+        Arc::new(Float64Array::from(bars.vwap)),
+        Arc::new(Float64Array::from(bars.twap)),
+        Arc::new(BooleanArray::from(bars.empty)),
+    ];
+    Ok(RecordBatch::try_new(bar_schema(), columns)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::{Int64Array, TimestampMillisecondArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn millis(date_str: &str) -> i64 {
+        date(date_str).and_utc().timestamp_millis()
+    }
+
+    fn ticks(time: &[&str], price: Vec<Option<i64>>, volume: Option<Vec<f64>>) -> RecordBatch {
+        let time: Vec<_> = time.iter().map(|dt| millis(dt)).collect();
+        let mut columns: Vec<(&str, ArrayRef)> = vec![
+            (
+                "ts",
+                Arc::new(TimestampMillisecondArray::from(time).with_timezone("UTC")),
+            ),
+            ("px", Arc::new(Int64Array::from(price))),
+        ];
+        if let Some(volume) = volume {
+            columns.push(("qty", Arc::new(Float64Array::from(volume))));
+        }
+        RecordBatch::try_from_iter(columns).unwrap()
+    }
+
+    fn column(batch: &RecordBatch, name: &str) -> Vec<f64> {
+        batch
+            .column_by_name(name)
+            .unwrap()
+            .as_primitive::<Float64Type>()
+            .values()
+            .to_vec()
+    }
+
+    fn times(batch: &RecordBatch, name: &str) -> Vec<NaiveDateTime> {
+        batch
+            .column_by_name(name)
+            .unwrap()
+            .as_primitive::<TimestampNanosecondType>()
+            .values()
+            .iter()
+            .map(|nanos| from_nanos(*nanos).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn resample_batches() {
+        let mut sampler = RecordBatchSampler::from_short("M5")
+            .unwrap()
+            .columns("ts", "px", "qty");
+
+        let bars = sampler
+            .resample(&ticks(
+                &["2015-01-01 10:01:00", "2015-01-01 10:02:00"],
+                vec![Some(10), Some(12)],
+                Some(vec![1., 3.]),
+            ))
+            .unwrap();
+        assert_eq!(bars.num_rows(), 0);
+        assert_eq!(bars.schema(), bar_schema());
+
+        let bars = sampler
+            .resample(&ticks(
+                &["2015-01-01 10:04:00", "2015-01-01 10:11:00"],
+                vec![Some(8), Some(9)],
+                Some(vec![4., 2.]),
+            ))
+            .unwrap();
+        assert_eq!(
+            times(&bars, "bar_start"),
+            vec![date("2015-01-01 10:00:00"), date("2015-01-01 10:05:00")]
+        );
+        assert_eq!(
+            times(&bars, "next_bar_dt"),
+            vec![date("2015-01-01 10:05:00"), date("2015-01-01 10:10:00")]
+        );
+        assert_eq!(column(&bars, "open"), vec![10., 8.]);
+        assert_eq!(column(&bars, "high"), vec![12., 8.]);
+        assert_eq!(column(&bars, "low"), vec![8., 8.]);
+        assert_eq!(column(&bars, "close"), vec![8., 8.]);
+        assert_eq!(column(&bars, "volume"), vec![8., 0.]);
+        assert_eq!(column(&bars, "vwap"), vec![9.75, 8.]);
+        let empty = bars.column_by_name("empty").unwrap().as_boolean();
+        assert_eq!(
+            empty.iter().collect::<Vec<_>>(),
+            vec![Some(false), Some(true)]
+        );
+
+        let incomplete = sampler.current_incomplete().unwrap();
+        assert_eq!(column(&incomplete, "close"), vec![9.]);
+    }
+
+    #[test]
+    fn missing_columns_and_nulls() {
+        let mut sampler = RecordBatchSampler::from_short("H1").unwrap();
+        assert!(matches!(
+            sampler.resample(&ticks(&["2015-01-01 10:00:00"], vec![Some(1)], None)),
+            Err(RecordBatchError::Column(name)) if name == "time"
+        ));
+
+        let mut sampler = sampler.columns("ts", "px", "qty");
+        let err = sampler
+            .resample(&ticks(
+                &["2015-01-01 10:00:00", "2015-01-01 10:30:00"],
+                vec![Some(1), None],
+                None,
+            ))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "row 1: non-finite price: NaN");
+
+        sampler.sampler_mut().set_policy(crate::PricePolicy::Skip);
+        sampler
+            .resample(&ticks(&["2015-01-01 10:40:00"], vec![None], None))
+            .unwrap();
+        let bars = sampler
+            .resample(&ticks(&["2015-01-01 11:00:00"], vec![Some(2)], None))
+            .unwrap();
+        assert_eq!(column(&bars, "close"), vec![1.]);
+        assert_eq!(column(&bars, "volume"), vec![0.]);
+    }
+
+    #[test]
+    fn rejected_tick_keeps_earlier_bars() {
+        let mut sampler = RecordBatchSampler::from_short("M5")
+            .unwrap()
+            .columns("ts", "px", "qty");
+        let batch = ticks(
+            &[
+                "2015-01-01 10:01:00",
+                "2015-01-01 10:06:00",
+                "2015-01-01 10:07:00",
+                "2015-01-01 10:12:00",
+            ],
+            vec![Some(1), Some(2), None, Some(3)],
+            None,
+        );
+        let (row, bars) = match sampler.resample(&batch) {
+            Err(RecordBatchError::Price { error, bars }) => (error.row, bars),
+            result => panic!("unexpected {:?}", result),
+        };
+        assert_eq!(row, 2);
+        assert_eq!(times(&bars, "bar_start"), vec![date("2015-01-01 10:00:00")]);
+        assert_eq!(column(&bars, "close"), vec![1.]);
+
+        // the rest of the batch picks up after the refused row
+        let bars = sampler.resample(&batch.slice(row + 1, 1)).unwrap();
+        assert_eq!(times(&bars, "bar_start"), vec![date("2015-01-01 10:05:00")]);
+        assert_eq!(column(&bars, "close"), vec![2.]);
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let path = std::env::temp_dir().join(format!("metabars-{}.parquet", std::process::id()));
+        let batches = vec![
+            Ok(ticks(
+                &["2015-01-01 10:01:00", "2015-01-01 10:07:00"],
+                vec![Some(1), Some(2)],
+                Some(vec![1., 1.]),
+            )),
+            Ok(ticks(
+                &["2015-01-01 10:20:00"],
+                vec![Some(3)],
+                Some(vec![1.]),
+            )),
+        ];
+        let mut sampler = RecordBatchSampler::from_short("M5")
+            .unwrap()
+            .columns("ts", "px", "qty");
+        let count = resample_parquet(
+            batches,
+            &mut sampler,
+            std::fs::File::create(&path).unwrap(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(count, 5);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let bars: Vec<_> = reader.collect::<Result<_, _>>().unwrap();
+        let bars = arrow::compute::concat_batches(&bar_schema(), &bars).unwrap();
+        assert_eq!(column(&bars, "close"), vec![1., 2., 2., 2., 3.]);
+        assert_eq!(
+            times(&bars, "bar_start").last(),
+            Some(&date("2015-01-01 10:20:00"))
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
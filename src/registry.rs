@@ -0,0 +1,234 @@
+use crate::{Bar, Bars, Price, PriceError, Sampler};
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Closed bars of a symbol
+#[derive(Debug, PartialEq)]
+pub struct SymbolBars<P = f64> {
+    pub symbol: String,
+    pub bars: Bars<P>,
+}
+
+/// A bar of a symbol
+#[derive(Debug, PartialEq)]
+pub struct SymbolBar<P = f64> {
+    pub symbol: String,
+    pub bar: Bar<P>,
+}
+
+type Factory<P> = Box<dyn Fn() -> Box<dyn Sampler<P>> + Send>;
+
+/// Samplers of the same timeframe keyed by symbol, created on the first tick of a symbol
+pub struct Registry<P = f64> {
+    factory: Factory<P>,
+    samplers: HashMap<String, Box<dyn Sampler<P>>>,
+}
+
+impl<P: Price> Registry<P> {
+    /// Returns None if the timeframe is unknown to `Sampler::from_short`
+    pub fn new(short: &str) -> Option<Self> {
+        <dyn Sampler<P>>::from_short(short)?;
+        let short = short.to_string();
+        Some(Self::from_fn(move || {
+            <dyn Sampler<P>>::from_short(&short).unwrap()
+        }))
+    }
+
+    /// Uses `factory` to create samplers, e.g. to configure them
+    pub fn from_fn<F>(factory: F) -> Self
+    where
+        F: Fn() -> Box<dyn Sampler<P>> + Send + 'static,
+    {
+        Self {
+            factory: Box::new(factory),
+            samplers: HashMap::new(),
+        }
+    }
+
+    /// Same as `try_next_bar`, but a rejected price is dropped
+    pub fn next_bar(&mut self, symbol: &str, dt: NaiveDateTime, value: P) -> Option<SymbolBars<P>> {
+        self.try_next_bar(symbol, dt, value).unwrap_or(None)
+    }
+
+    /// Routes the tick to the sampler of `symbol`
+    pub fn try_next_bar(
+        &mut self,
+        symbol: &str,
+        dt: NaiveDateTime,
+        value: P,
+    ) -> Result<Option<SymbolBars<P>>, PriceError<P>> {
+        let bars = self.sampler_mut(symbol).try_next_bar(dt, value)?;
+        Ok(bars.map(|bars| SymbolBars {
+            symbol: symbol.to_string(),
+            bars,
+        }))
+    }
+
+    /// Creates the sampler of `symbol` if it doesn't exist yet
+    pub fn sampler_mut(&mut self, symbol: &str) -> &mut dyn Sampler<P> {
+        if !self.samplers.contains_key(symbol) {
+            self.samplers.insert(symbol.to_string(), (self.factory)());
+        }
+        self.samplers.get_mut(symbol).unwrap().as_mut()
+    }
+
+    pub fn sampler(&self, symbol: &str) -> Option<&dyn Sampler<P>> {
+        self.samplers.get(symbol).map(|sampler| sampler.as_ref())
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        self.samplers.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+
+    /// Incomplete bars of all symbols, sorted by symbol
+    pub fn snapshot(&self) -> Vec<SymbolBar<P>> {
+        let mut bars: Vec<_> = self
+            .samplers
+            .iter()
+            .filter_map(|(symbol, sampler)| {
+                Some(SymbolBar {
+                    symbol: symbol.clone(),
+                    bar: sampler.current_incomplete()?,
+                })
+            })
+            .collect();
+        bars.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        bars
+    }
+
+    /// Returns the incomplete bars of all symbols, sorted by symbol, and drops the samplers
+    pub fn flush(&mut self) -> Vec<SymbolBar<P>> {
+        let bars = self.snapshot();
+        self.samplers.clear();
+        bars
+    }
+}
+
+impl<P> fmt::Debug for Registry<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("symbols", &self.samplers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn bar(open: f64, high: f64, low: f64, close: f64, start: &str, end: &str) -> Bar {
+        Bar {
+            open,
+            high,
+            low,
+            close,
+            bar_start: date(start),
+            next_bar_dt: date(end),
+        }
+    }
+
+    #[test]
+    fn routes_ticks_by_symbol() {
+        let mut registry = Registry::new("M5").unwrap();
+        assert!(registry.is_empty());
+        assert_eq!(
+            registry.next_bar("EURUSD", date("2015-01-01 10:00:00"), 1.1),
+            None
+        );
+        assert_eq!(
+            registry.next_bar("GBPUSD", date("2015-01-01 10:01:00"), 1.5),
+            None
+        );
+        assert_eq!(
+            registry.next_bar("EURUSD", date("2015-01-01 10:02:00"), 1.2),
+            None
+        );
+        assert_eq!(registry.len(), 2);
+
+        assert_eq!(
+            registry.next_bar("EURUSD", date("2015-01-01 10:05:00"), 1.3),
+            Some(SymbolBars {
+                symbol: "EURUSD".to_string(),
+                bars: Bars::Single(bar(
+                    1.1,
+                    1.2,
+                    1.1,
+                    1.2,
+                    "2015-01-01 10:00:00",
+                    "2015-01-01 10:05:00"
+                )),
+            })
+        );
+        assert_eq!(
+            registry
+                .sampler("GBPUSD")
+                .and_then(|sampler| sampler.current_incomplete()),
+            Some(bar(
+                1.5,
+                1.5,
+                1.5,
+                1.5,
+                "2015-01-01 10:00:00",
+                "2015-01-01 10:05:00"
+            ))
+        );
+    }
+
+    #[test]
+    fn snapshot_and_flush() {
+        let mut registry = Registry::new("H1").unwrap();
+        registry.next_bar("B", date("2015-01-01 10:00:00"), 2.);
+        registry.next_bar("A", date("2015-01-01 10:30:00"), 1.);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(
+            snapshot
+                .iter()
+                .map(|bar| bar.symbol.as_str())
+                .collect::<Vec<_>>(),
+            vec!["A", "B"]
+        );
+        assert_eq!(registry.len(), 2);
+
+        assert_eq!(registry.flush(), snapshot);
+        assert!(registry.is_empty());
+        assert!(registry.snapshot().is_empty());
+    }
+
+    #[test]
+    fn configured_samplers() {
+        assert!(Registry::<f64>::new("X1").is_none());
+
+        let mut registry = Registry::from_fn(|| {
+            let mut sampler: Box<dyn Sampler> = Box::new(M15::default());
+            sampler.set_closed(Closed::Right);
+            sampler.set_policy(PricePolicy::Reject);
+            sampler
+        });
+        assert!(registry
+            .try_next_bar("A", date("2015-01-01 10:15:00"), f64::NAN)
+            .is_err());
+        assert_eq!(
+            registry.next_bar("A", date("2015-01-01 10:15:00"), 1.),
+            None
+        );
+        assert_eq!(
+            registry.snapshot()[0].bar.bar_start,
+            date("2015-01-01 10:00:00")
+        );
+    }
+}
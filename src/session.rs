@@ -0,0 +1,189 @@
+use chrono::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// Trading hours: a daily time window on some days of the week
+///
+/// Parsed from `09:30-16:00` or `09:30-16:00/mon-fri`. A window that ends at or before
+/// its start runs overnight and belongs to the day it starts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    // indexed by `Weekday::num_days_from_monday`
+    days: [bool; 7],
+}
+
+impl Session {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        Self {
+            start,
+            end,
+            days: [true; 7],
+        }
+    }
+
+    /// Limits the session to the days from `first` to `last`, wrapping around Sunday
+    pub fn days(mut self, first: Weekday, last: Weekday) -> Self {
+        self.days = [false; 7];
+        let mut day = first;
+        loop {
+            self.days[day.num_days_from_monday() as usize] = true;
+            if day == last {
+                break;
+            }
+            day = day.succ();
+        }
+        self
+    }
+
+    pub fn contains(&self, dt: NaiveDateTime) -> bool {
+        let time = dt.time();
+        if self.start < self.end {
+            self.is_open_on(dt.weekday()) && self.start <= time && time < self.end
+        } else if time >= self.start {
+            self.is_open_on(dt.weekday())
+        } else {
+            time < self.end && self.is_open_on(dt.weekday().pred())
+        }
+    }
+
+    /// Whether the session is open at any time of `start..end`
+    pub fn overlaps(&self, start: NaiveDateTime, end: NaiveDateTime) -> bool {
+        if start >= end {
+            return false;
+        }
+        if self.contains(start) {
+            return true;
+        }
+        // otherwise a window has to open within the period
+        let mut day = start.date();
+        while day <= end.date() {
+            let open = day.and_time(self.start);
+            if self.is_open_on(day.weekday()) && start <= open && open < end {
+                return true;
+            }
+            day = match day.succ_opt() {
+                Some(day) => day,
+                None => break,
+            };
+        }
+        false
+    }
+
+    fn is_open_on(&self, day: Weekday) -> bool {
+        self.days[day.num_days_from_monday() as usize]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSessionError(String);
+
+impl fmt::Display for ParseSessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid session: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseSessionError {}
+
+impl FromStr for Session {
+    type Err = ParseSessionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSessionError(s.to_string());
+        let (hours, days) = match s.find('/') {
+            Some(slash) => (&s[..slash], Some(&s[slash + 1..])),
+            None => (s, None),
+        };
+
+        let mut times = hours
+            .splitn(2, '-')
+            .map(|time| NaiveTime::parse_from_str(time.trim(), "%H:%M"));
+        let session = match (times.next(), times.next()) {
+            (Some(Ok(start)), Some(Ok(end))) => Session::new(start, end),
+            _ => return Err(err()),
+        };
+
+        match days {
+            Some(days) => {
+                let mut days = days.splitn(2, '-').map(|day| day.trim().parse::<Weekday>());
+                match (days.next(), days.next()) {
+                    (Some(Ok(first)), Some(Ok(last))) => Ok(session.days(first, last)),
+                    (Some(Ok(day)), None) => Ok(session.days(day, day)),
+                    _ => Err(err()),
+                }
+            }
+            None => Ok(session),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn day_session() {
+        let session: Session = "09:30-16:00/mon-fri".parse().unwrap();
+        // 2021-01-08 is a friday
+        assert!(!session.contains(date("2021-01-08 09:29:59")));
+        assert!(session.contains(date("2021-01-08 09:30:00")));
+        assert!(session.contains(date("2021-01-08 15:59:59")));
+        assert!(!session.contains(date("2021-01-08 16:00:00")));
+        assert!(!session.contains(date("2021-01-09 10:00:00")));
+    }
+
+    #[test]
+    fn overnight_session() {
+        // FX week from sunday 17:00 to friday 17:00
+        let session: Session = "17:00-17:00/sun-thu".parse().unwrap();
+        assert!(!session.contains(date("2021-01-03 16:59:59")));
+        assert!(session.contains(date("2021-01-03 17:00:00")));
+        assert!(session.contains(date("2021-01-06 03:00:00")));
+        assert!(session.contains(date("2021-01-08 16:59:59")));
+        assert!(!session.contains(date("2021-01-08 17:00:00")));
+        assert!(!session.contains(date("2021-01-09 10:00:00")));
+    }
+
+    #[test]
+    fn overlapping_periods() {
+        let session: Session = "09:30-16:00/mon-fri".parse().unwrap();
+        assert!(session.overlaps(date("2021-01-08 09:00:00"), date("2021-01-08 10:00:00")));
+        assert!(session.overlaps(date("2021-01-08 15:00:00"), date("2021-01-08 16:00:00")));
+        assert!(!session.overlaps(date("2021-01-08 16:00:00"), date("2021-01-08 17:00:00")));
+        assert!(!session.overlaps(date("2021-01-08 08:00:00"), date("2021-01-08 09:30:00")));
+        // saturday, sunday and the week containing them
+        assert!(!session.overlaps(date("2021-01-09 00:00:00"), date("2021-01-11 00:00:00")));
+        assert!(session.overlaps(date("2021-01-04 00:00:00"), date("2021-01-11 00:00:00")));
+
+        let session: Session = "17:00-17:00/sun-thu".parse().unwrap();
+        assert!(session.overlaps(date("2021-01-03 16:00:00"), date("2021-01-03 18:00:00")));
+        assert!(!session.overlaps(date("2021-01-08 17:00:00"), date("2021-01-10 17:00:00")));
+    }
+
+    #[test]
+    fn invalid_session() {
+        assert!("09:30".parse::<Session>().is_err());
+        assert!("09:30-25:00".parse::<Session>().is_err());
+        assert_eq!(
+            "09:30-16:00/someday"
+                .parse::<Session>()
+                .unwrap_err()
+                .to_string(),
+            "invalid session: \"09:30-16:00/someday\""
+        );
+        assert_eq!(
+            "00:00-12:00/sat".parse::<Session>(),
+            Ok(Session::new(
+                NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(12, 0, 0).unwrap()
+            )
+            .days(Weekday::Sat, Weekday::Sat))
+        );
+    }
+}
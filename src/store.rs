@@ -0,0 +1,543 @@
+//! Embedded bar store, one append-only file per symbol and timeframe
+//!
+//! Files are a 16 byte header, `"MBSTORE1"` and the record size as u64, followed by
+//! fixed-size records: `bar_start` and `next_bar_dt` as i64 nanoseconds since the
+//! epoch, open, high, low and close as f64 and an FNV-1a checksum of the preceding
+//! 48 bytes, all little-endian. Records are found by binary search on `bar_start`.
+//!
+//! A file has one writer, holding an exclusive lock, and any number of readers,
+//! in the same process or not. A record is appended with a single write, so a crash
+//! can only leave an incomplete or torn record at the end: readers never see records
+//! whose checksum doesn't match, and the writer overwrites the tail in place with
+//! the next bars. Files never shrink, so a mapped range stays valid.
+
+use crate::{Bar, Bars};
+use chrono::prelude::*;
+use fs2::FileExt;
+use memmap2::Mmap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"MBSTORE1";
+const HEADER_LEN: u64 = 16;
+const RECORD_LEN: u64 = 56;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    /// Not a store file, or one with a damaged header
+    Format,
+    /// Another writer has the file open
+    Locked,
+    /// `bar_start` isn't after the one of the last bar in the file
+    OutOfOrder(NaiveDateTime),
+    /// A time that doesn't fit in nanoseconds since the epoch
+    TimeRange(NaiveDateTime),
+    /// A symbol or timeframe that can't be a file name
+    InvalidName(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "{}", err),
+            StoreError::Format => write!(f, "not a bar store file"),
+            StoreError::Locked => write!(f, "file is locked by another writer"),
+            StoreError::OutOfOrder(dt) => write!(f, "bar at {} is out of order", dt),
+            StoreError::TimeRange(dt) => write!(f, "{} is out of range", dt),
+            StoreError::InvalidName(name) => write!(f, "invalid name: {:?}", name),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+/// A directory of bar files, `<root>/<symbol>/<timeframe>.bars`
+#[derive(Debug, Clone)]
+pub struct BarStore {
+    root: PathBuf,
+}
+
+impl BarStore {
+    /// Creates the directory if it doesn't exist
+    pub fn open<Q: AsRef<Path>>(root: Q) -> Result<Self, StoreError> {
+        fs::create_dir_all(&root)?;
+        Ok(Self {
+            root: root.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn path(&self, symbol: &str, timeframe: &str) -> Result<PathBuf, StoreError> {
+        for name in &[symbol, timeframe] {
+            let valid =
+                !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\', '\0']);
+            if !valid {
+                return Err(StoreError::InvalidName(name.to_string()));
+            }
+        }
+        Ok(self.root.join(symbol).join(format!("{}.bars", timeframe)))
+    }
+
+    /// Opens the file for writing, creating it if needed
+    pub fn appender(&self, symbol: &str, timeframe: &str) -> Result<BarAppender, StoreError> {
+        let path = self.path(symbol, timeframe)?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        BarAppender::open(path)
+    }
+
+    pub fn reader(&self, symbol: &str, timeframe: &str) -> Result<StoreReader, StoreError> {
+        StoreReader::open(self.path(symbol, timeframe)?)
+    }
+
+    /// Symbols with at least one file
+    pub fn symbols(&self) -> Result<Vec<String>, StoreError> {
+        let mut symbols = vec![];
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                symbols.extend(entry.file_name().to_str().map(String::from));
+            }
+        }
+        symbols.sort();
+        Ok(symbols)
+    }
+}
+
+/// Appends bars to a store file
+///
+/// Bars are written as they come, call `sync` to make them durable.
+#[derive(Debug)]
+pub struct BarAppender {
+    file: File,
+    len: u64,
+    last: Option<NaiveDateTime>,
+    recovered: u64,
+}
+
+impl BarAppender {
+    /// Locks the file and finds the end of its valid records, past which an
+    /// incomplete or torn tail left by a crash is overwritten by the next bars
+    pub fn open<Q: AsRef<Path>>(path: Q) -> Result<Self, StoreError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        file.try_lock_exclusive().map_err(|err| {
+            if err.kind() == fs2::lock_contended_error().kind() {
+                StoreError::Locked
+            } else {
+                StoreError::Io(err)
+            }
+        })?;
+
+        let size = file.metadata()?.len();
+        if size < HEADER_LEN {
+            // a new file, or one that crashed while being created
+            file.set_len(0)?;
+            file.write_all(MAGIC)?;
+            file.write_all(&RECORD_LEN.to_le_bytes())?;
+        } else {
+            let mut header = [0; HEADER_LEN as usize];
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut header)?;
+            check_header(&header)?;
+        }
+
+        let mut len = (size.max(HEADER_LEN) - HEADER_LEN) / RECORD_LEN;
+        let mut last = None;
+        let mut record = [0; RECORD_LEN as usize];
+        while len > 0 {
+            file.seek(SeekFrom::Start(HEADER_LEN + (len - 1) * RECORD_LEN))?;
+            file.read_exact(&mut record)?;
+            if let Some(bar) = decode(&record) {
+                last = Some(bar.bar_start);
+                break;
+            }
+            len -= 1;
+        }
+        // readers may have mapped the tail, truncating it would pull the pages
+        // from under them
+        let end = HEADER_LEN + len * RECORD_LEN;
+        let recovered = size.saturating_sub(end);
+        file.seek(SeekFrom::Start(end))?;
+
+        Ok(Self {
+            file,
+            len,
+            last,
+            recovered,
+        })
+    }
+
+    /// Bytes of an incomplete tail found when the file was opened, to be overwritten
+    pub fn recovered(&self) -> u64 {
+        self.recovered
+    }
+
+    /// Number of bars in the file
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn append(&mut self, bar: &Bar) -> Result<(), StoreError> {
+        if self.last.is_some_and(|last| bar.bar_start <= last) {
+            return Err(StoreError::OutOfOrder(bar.bar_start));
+        }
+        let record = encode(bar)?;
+        self.file.write_all(&record)?;
+        self.last = Some(bar.bar_start);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Appends a sampler's output, the closed bar and the empty ones after it
+    pub fn append_bars(&mut self, bars: &Bars) -> Result<(), StoreError> {
+        match bars {
+            Bars::Single(bar) => self.append(bar),
+            Bars::WithEmpty(bar, empty) => {
+                self.append(bar)?;
+                empty.iter().try_for_each(|bar| self.append(bar))
+            }
+        }
+    }
+
+    /// Waits until the bars appended so far are on disk
+    pub fn sync(&self) -> Result<(), StoreError> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Memory-mapped view of a store file
+///
+/// Sees the bars present when it was opened or last refreshed, `refresh` picks up
+/// the ones a live writer appended since.
+#[derive(Debug)]
+pub struct StoreReader {
+    file: File,
+    map: Option<Mmap>,
+    len: usize,
+}
+
+impl StoreReader {
+    pub fn open<Q: AsRef<Path>>(path: Q) -> Result<Self, StoreError> {
+        let mut reader = Self {
+            file: File::open(path)?,
+            map: None,
+            len: 0,
+        };
+        reader.refresh()?;
+        Ok(reader)
+    }
+
+    /// Maps the bars appended since the last refresh, returns how many there are
+    pub fn refresh(&mut self) -> Result<usize, StoreError> {
+        let size = self.file.metadata()?.len();
+        if size < HEADER_LEN {
+            return Ok(0);
+        }
+        if self.map.as_ref().map_or(0, |map| map.len() as u64) != size {
+            // SAFETY: files never shrink, writers append or overwrite records past
+            // the last valid one in place, so the mapped range stays backed by the
+            // file. A record being written fails its checksum and is never decoded.
+            let map = unsafe { Mmap::map(&self.file)? };
+            check_header(&map[..HEADER_LEN as usize])?;
+            self.map = Some(map);
+        }
+        // bars written over a torn tail land within the mapped range
+        let map = self.map.as_ref().unwrap();
+        let mut len = (map.len() as u64 - HEADER_LEN) / RECORD_LEN;
+        while len > self.len as u64 && decode(record(map, len as usize - 1)).is_none() {
+            len -= 1;
+        }
+        let added = len as usize - self.len;
+        self.len = len as usize;
+        Ok(added)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The `index`-th bar, None if out of range or damaged
+    pub fn get(&self, index: usize) -> Option<Bar> {
+        if index >= self.len {
+            return None;
+        }
+        decode(record(self.map.as_ref()?, index))
+    }
+
+    pub fn last(&self) -> Option<Bar> {
+        self.get(self.len.checked_sub(1)?)
+    }
+
+    /// Index of the first bar with `bar_start >= dt`
+    pub fn position(&self, dt: NaiveDateTime) -> usize {
+        let map = match &self.map {
+            Some(map) => map,
+            None => return 0,
+        };
+        let nanos = match dt.and_utc().timestamp_nanos_opt() {
+            Some(nanos) => nanos,
+            None if dt.year() < 1970 => return 0,
+            None => return self.len,
+        };
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if i64_at(record(map, middle), 0) < nanos {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    /// Bars with `from <= bar_start < to`, in order
+    pub fn range(&self, from: NaiveDateTime, to: NaiveDateTime) -> impl Iterator<Item = Bar> + '_ {
+        let start = self.position(from);
+        let end = self.position(to).max(start);
+        (start..end).filter_map(move |index| self.get(index))
+    }
+}
+
+fn check_header(header: &[u8]) -> Result<(), StoreError> {
+    if &header[..8] != MAGIC || i64_at(header, 8) as u64 != RECORD_LEN {
+        return Err(StoreError::Format);
+    }
+    Ok(())
+}
+
+fn record(map: &Mmap, index: usize) -> &[u8] {
+    let start = HEADER_LEN as usize + index * RECORD_LEN as usize;
+    &map[start..start + RECORD_LEN as usize]
+}
+
+fn i64_at(bytes: &[u8], at: usize) -> i64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    i64::from_le_bytes(buf)
+}
+
+fn encode(bar: &Bar) -> Result<[u8; RECORD_LEN as usize], StoreError> {
+    let nanos = |dt: NaiveDateTime| {
+        dt.and_utc()
+            .timestamp_nanos_opt()
+            .ok_or(StoreError::TimeRange(dt))
+    };
+    let mut record = [0; RECORD_LEN as usize];
+    let fields = [
+        nanos(bar.bar_start)?.to_le_bytes(),
+        nanos(bar.next_bar_dt)?.to_le_bytes(),
+        bar.open.to_le_bytes(),
+        bar.high.to_le_bytes(),
+        bar.low.to_le_bytes(),
+        bar.close.to_le_bytes(),
+    ];
+    for (i, field) in fields.iter().enumerate() {
+        record[i * 8..i * 8 + 8].copy_from_slice(field);
+    }
+    let checksum = fnv1a(&record[..48]);
+    record[48..].copy_from_slice(&checksum.to_le_bytes());
+    Ok(record)
+}
+
+// None if the checksum doesn't match
+fn decode(record: &[u8]) -> Option<Bar> {
+    if fnv1a(&record[..48]) != i64_at(record, 48) as u64 {
+        return None;
+    }
+    let time = |at| {
+        let nanos = i64_at(record, at);
+        DateTime::from_timestamp(
+            nanos.div_euclid(1_000_000_000),
+            nanos.rem_euclid(1_000_000_000) as u32,
+        )
+        .map(|dt| dt.naive_utc())
+    };
+    let price = |at| f64::from_bits(i64_at(record, at) as u64);
+    Some(Bar {
+        bar_start: time(0)?,
+        next_bar_dt: time(8)?,
+        open: price(16),
+        high: price(24),
+        low: price(32),
+        close: price(40),
+    })
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use chrono::Duration;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn temp_store(name: &str) -> BarStore {
+        let root = std::env::temp_dir().join(format!("metabars-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        BarStore::open(root).unwrap()
+    }
+
+    fn bar(minute: i64, price: f64) -> Bar {
+        let bar_start = date("2015-01-01 10:00:00") + Duration::minutes(minute);
+        Bar {
+            open: price,
+            high: price + 1.,
+            low: price - 1.,
+            close: price,
+            bar_start,
+            next_bar_dt: bar_start + Duration::minutes(1),
+        }
+    }
+
+    #[test]
+    fn append_from_sampler_and_query() {
+        let store = temp_store("query");
+        let mut appender = store.appender("EURUSD", "M1").unwrap();
+        let mut sampler = M1::default();
+        let mut expected = vec![];
+        for i in 0..500i64 {
+            let dt = date("2015-01-01 10:00:00") + Duration::seconds(i * i % 97 + i * 23);
+            if let Some(bars) = sampler.next_bar(dt, i as f64) {
+                appender.append_bars(&bars).unwrap();
+                let (bar, empty) = bars.into_parts();
+                expected.push(bar);
+                expected.extend(empty);
+            }
+        }
+        appender.sync().unwrap();
+        assert_eq!(appender.len(), expected.len() as u64);
+
+        let reader = store.reader("EURUSD", "M1").unwrap();
+        assert_eq!(reader.len(), expected.len());
+        assert_eq!(
+            reader
+                .range(NaiveDateTime::MIN, NaiveDateTime::MAX)
+                .collect::<Vec<_>>(),
+            expected
+        );
+
+        let from = expected[10].bar_start;
+        let to = expected[20].bar_start + Duration::seconds(1);
+        assert_eq!(
+            reader.range(from, to).collect::<Vec<_>>(),
+            expected[10..=20].to_vec()
+        );
+        assert_eq!(reader.range(to, from).count(), 0);
+        assert_eq!(reader.last(), expected.last().cloned());
+        assert_eq!(store.symbols().unwrap(), vec!["EURUSD"]);
+        fs::remove_dir_all(&store.root).unwrap();
+    }
+
+    #[test]
+    fn live_reader() {
+        let store = temp_store("live");
+        let mut appender = store.appender("A", "M1").unwrap();
+        appender.append(&bar(0, 0.)).unwrap();
+
+        let mut reader = store.reader("A", "M1").unwrap();
+        assert_eq!(reader.len(), 1);
+
+        let writer = std::thread::spawn(move || {
+            for minute in 1..1_000 {
+                appender.append(&bar(minute, minute as f64)).unwrap();
+            }
+            appender
+        });
+        let mut seen = 1;
+        while seen < 1_000 {
+            seen += reader.refresh().unwrap();
+            // every mapped bar is complete
+            if let Some(last) = reader.last() {
+                assert_eq!(last, bar(seen as i64 - 1, (seen - 1) as f64));
+            }
+        }
+        let mut appender = writer.join().unwrap();
+
+        assert!(matches!(store.appender("A", "M1"), Err(StoreError::Locked)));
+        assert!(matches!(
+            appender.append(&bar(5, 0.)),
+            Err(StoreError::OutOfOrder(_))
+        ));
+        fs::remove_dir_all(&store.root).unwrap();
+    }
+
+    #[test]
+    fn tail_recovery() {
+        let store = temp_store("recovery");
+        let path = store.path("A", "M1").unwrap();
+        let mut appender = store.appender("A", "M1").unwrap();
+        for minute in 0..10 {
+            appender.append(&bar(minute, 1.)).unwrap();
+        }
+        drop(appender);
+
+        // a torn last record and half of one more
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 10;
+        bytes[last] ^= 0xff;
+        bytes.extend_from_slice(&encode(&bar(10, 1.)).unwrap()[..20]);
+        fs::write(&path, &bytes).unwrap();
+
+        let mut reader = store.reader("A", "M1").unwrap();
+        assert_eq!(reader.len(), 9);
+
+        // the tail the reader has mapped is overwritten, not truncated
+        let mut appender = store.appender("A", "M1").unwrap();
+        assert_eq!(appender.recovered(), RECORD_LEN + 20);
+        assert_eq!(appender.len(), 9);
+        appender.append(&bar(9, 2.)).unwrap();
+        drop(appender);
+        assert_eq!(fs::metadata(&path).unwrap().len(), bytes.len() as u64);
+        assert_eq!(reader.refresh().unwrap(), 1);
+        assert_eq!(reader.last(), Some(bar(9, 2.)));
+        let reader = store.reader("A", "M1").unwrap();
+        assert_eq!(reader.last(), Some(bar(9, 2.)));
+        assert_eq!(reader.len(), 10);
+
+        // a crash before the header was complete
+        fs::write(&path, b"MBST").unwrap();
+        let appender = store.appender("A", "M1").unwrap();
+        assert!(appender.is_empty());
+        drop(appender);
+        assert!(store.reader("A", "M1").unwrap().is_empty());
+
+        fs::write(&path, [0; 100]).unwrap();
+        assert!(matches!(store.appender("A", "M1"), Err(StoreError::Format)));
+        assert!(matches!(store.reader("A", "M1"), Err(StoreError::Format)));
+        assert!(matches!(
+            store.path("../A", "M1"),
+            Err(StoreError::InvalidName(_))
+        ));
+        fs::remove_dir_all(&store.root).unwrap();
+    }
+}
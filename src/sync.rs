@@ -0,0 +1,251 @@
+use crate::{Bar, Price, PricePolicy, Registry, Sampler, SymbolBar};
+use chrono::prelude::*;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Bars of every symbol of a universe for the same period
+#[derive(Debug, PartialEq)]
+pub struct CrossSection<P = f64> {
+    pub bar_start: NaiveDateTime,
+    pub next_bar_dt: NaiveDateTime,
+    /// In universe order, symbols without any tick so far are left out
+    pub bars: Vec<SymbolBar<P>>,
+}
+
+/// Samplers of a fixed set of symbols closing their bars together
+///
+/// A tick of any symbol, or `advance`, closes the period for all of them.
+/// Symbols without ticks in a period get a flat bar at their last close.
+pub struct Universe<P = f64> {
+    symbols: Vec<String>,
+    // the same symbols, to look ticks up
+    members: HashSet<String>,
+    registry: Registry<P>,
+    // sees every tick and tells when periods pass
+    clock: Box<dyn Sampler<P>>,
+    last: Option<P>,
+}
+
+impl<P: Price> Universe<P> {
+    /// Returns None if the timeframe is unknown to `Sampler::from_short`
+    pub fn new<S: ToString>(short: &str, symbols: &[S]) -> Option<Self> {
+        <dyn Sampler<P>>::from_short(short)?;
+        let short = short.to_string();
+        Some(Self::from_fn(
+            move || <dyn Sampler<P>>::from_short(&short).unwrap(),
+            symbols,
+        ))
+    }
+
+    /// Uses `factory` to create samplers, e.g. to configure them
+    pub fn from_fn<F, S>(factory: F, symbols: &[S]) -> Self
+    where
+        F: Fn() -> Box<dyn Sampler<P>> + Send + 'static,
+        S: ToString,
+    {
+        let mut clock = factory();
+        clock.set_policy(PricePolicy::Propagate);
+        let symbols: Vec<String> = symbols.iter().map(ToString::to_string).collect();
+        Self {
+            members: symbols.iter().cloned().collect(),
+            symbols,
+            registry: Registry::from_fn(factory),
+            clock,
+            last: None,
+        }
+    }
+
+    pub fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    /// Returns a cross-section for every period passed, ticks of symbols
+    /// outside of the universe are ignored
+    pub fn next_bar(&mut self, symbol: &str, dt: NaiveDateTime, value: P) -> Vec<CrossSection<P>> {
+        if !self.members.contains(symbol) {
+            return vec![];
+        }
+        let sections = self.tick(dt, value);
+        // bars it returns have been emitted with the cross-sections
+        self.registry.next_bar(symbol, dt, value);
+        sections
+    }
+
+    /// Closes the periods that passed by `dt` without a tick
+    pub fn advance(&mut self, dt: NaiveDateTime) -> Vec<CrossSection<P>> {
+        match self.last {
+            Some(value) => self.tick(dt, value),
+            None => vec![],
+        }
+    }
+
+    /// The incomplete cross-section
+    pub fn current_incomplete(&self) -> Option<CrossSection<P>> {
+        let bar = self.clock.current_incomplete()?;
+        Some(self.section(bar.bar_start, bar.next_bar_dt))
+    }
+
+    fn tick(&mut self, dt: NaiveDateTime, value: P) -> Vec<CrossSection<P>> {
+        self.last = Some(value);
+        let (bar, empty) = match self.clock.next_bar(dt, value) {
+            Some(bars) => bars.into_parts(),
+            None => return vec![],
+        };
+        Some(bar)
+            .into_iter()
+            .chain(empty)
+            .map(|period| self.section(period.bar_start, period.next_bar_dt))
+            .collect()
+    }
+
+    fn section(&self, bar_start: NaiveDateTime, next_bar_dt: NaiveDateTime) -> CrossSection<P> {
+        let bars = self
+            .symbols
+            .iter()
+            .filter_map(|symbol| {
+                let bar = self.registry.sampler(symbol)?.current_incomplete()?;
+                let bar = if bar.bar_start == bar_start {
+                    bar
+                } else {
+                    Bar {
+                        open: bar.close,
+                        high: bar.close,
+                        low: bar.close,
+                        close: bar.close,
+                        bar_start,
+                        next_bar_dt,
+                    }
+                };
+                Some(SymbolBar {
+                    symbol: symbol.clone(),
+                    bar,
+                })
+            })
+            .collect();
+        CrossSection {
+            bar_start,
+            next_bar_dt,
+            bars,
+        }
+    }
+}
+
+impl<P> fmt::Debug for Universe<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Universe")
+            .field("symbols", &self.symbols)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn bar(symbol: &str, open: f64, high: f64, low: f64, close: f64, start: &str) -> SymbolBar {
+        let bar_start = date(start);
+        SymbolBar {
+            symbol: symbol.to_string(),
+            bar: Bar {
+                open,
+                high,
+                low,
+                close,
+                bar_start,
+                next_bar_dt: bar_start + chrono::Duration::minutes(5),
+            },
+        }
+    }
+
+    fn section(start: &str, bars: Vec<SymbolBar>) -> CrossSection {
+        let bar_start = date(start);
+        CrossSection {
+            bar_start,
+            next_bar_dt: bar_start + chrono::Duration::minutes(5),
+            bars,
+        }
+    }
+
+    #[test]
+    fn synchronized_close() {
+        let mut universe = Universe::new("M5", &["A", "B", "C"]).unwrap();
+        assert!(universe.advance(date("2015-01-01 10:00:00")).is_empty());
+
+        assert!(universe
+            .next_bar("A", date("2015-01-01 10:01:00"), 1.)
+            .is_empty());
+        assert!(universe
+            .next_bar("B", date("2015-01-01 10:02:00"), 10.)
+            .is_empty());
+        assert!(universe
+            .next_bar("A", date("2015-01-01 10:03:00"), 2.)
+            .is_empty());
+        assert!(universe
+            .next_bar("X", date("2015-01-01 10:30:00"), 0.)
+            .is_empty());
+
+        // C has never traded
+        assert_eq!(
+            universe.next_bar("A", date("2015-01-01 10:06:00"), 3.),
+            vec![section(
+                "2015-01-01 10:00:00",
+                vec![
+                    bar("A", 1., 2., 1., 2., "2015-01-01 10:00:00"),
+                    bar("B", 10., 10., 10., 10., "2015-01-01 10:00:00"),
+                ]
+            )]
+        );
+
+        assert_eq!(
+            universe.next_bar("C", date("2015-01-01 10:16:00"), 100.),
+            vec![
+                section(
+                    "2015-01-01 10:05:00",
+                    vec![
+                        bar("A", 3., 3., 3., 3., "2015-01-01 10:05:00"),
+                        bar("B", 10., 10., 10., 10., "2015-01-01 10:05:00"),
+                    ]
+                ),
+                section(
+                    "2015-01-01 10:10:00",
+                    vec![
+                        bar("A", 3., 3., 3., 3., "2015-01-01 10:10:00"),
+                        bar("B", 10., 10., 10., 10., "2015-01-01 10:10:00"),
+                    ]
+                ),
+            ]
+        );
+
+        // B's first tick after the gap doesn't repeat the bars already emitted
+        assert!(universe
+            .next_bar("B", date("2015-01-01 10:17:00"), 11.)
+            .is_empty());
+
+        assert_eq!(
+            universe.advance(date("2015-01-01 10:20:00")),
+            vec![section(
+                "2015-01-01 10:15:00",
+                vec![
+                    bar("A", 3., 3., 3., 3., "2015-01-01 10:15:00"),
+                    bar("B", 11., 11., 11., 11., "2015-01-01 10:15:00"),
+                    bar("C", 100., 100., 100., 100., "2015-01-01 10:15:00"),
+                ]
+            )]
+        );
+        assert_eq!(
+            universe.current_incomplete(),
+            Some(section(
+                "2015-01-01 10:20:00",
+                vec![
+                    bar("A", 3., 3., 3., 3., "2015-01-01 10:20:00"),
+                    bar("B", 11., 11., 11., 11., "2015-01-01 10:20:00"),
+                    bar("C", 100., 100., 100., 100., "2015-01-01 10:20:00"),
+                ]
+            ))
+        );
+    }
+}
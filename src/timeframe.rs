@@ -1,17 +1,20 @@
+use crate::origin::Origin;
+use crate::price::Price;
+use crate::validate::{propagating_max, propagating_min, PriceError, PricePolicy};
 use chrono::prelude::*;
 
-#[derive(Debug, PartialEq)]
-pub struct Bar {
-    pub open: f64,
-    pub high: f64,
-    pub low: f64,
-    pub close: f64,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bar<P = f64> {
+    pub open: P,
+    pub high: P,
+    pub low: P,
+    pub close: P,
     pub bar_start: NaiveDateTime,
     pub next_bar_dt: NaiveDateTime,
 }
 
-impl From<&State> for Bar {
-    fn from(state: &State) -> Self {
+impl<P: Price> From<&State<P>> for Bar<P> {
+    fn from(state: &State<P>) -> Self {
         Self {
             open: state.open,
             high: state.high,
@@ -23,68 +26,216 @@ impl From<&State> for Bar {
     }
 }
 
+impl<P> Bar<P> {
+    /// The time a bar is labeled with downstream
+    pub fn label(&self, label: Label) -> NaiveDateTime {
+        match label {
+            Label::Open => self.bar_start,
+            Label::Close => self.next_bar_dt,
+        }
+    }
+}
+
 impl Bar {
     pub fn available_timeframes() -> Vec<&'static str> {
         let minutes = vec![
             "M1", "M2", "M3", "M4", "M5", "M6", "M10", "M12", "M15", "M20", "M30",
         ];
         let hours = vec!["H1", "H2", "H3", "H4", "H6", "H8", "H12"];
+        let calendar = vec!["D1", "W1", "Mn1", "Q1", "Y1"];
 
-        [minutes, hours].concat()
+        [minutes, hours, calendar].concat()
     }
 }
 
 #[derive(Debug, PartialEq)]
-pub enum Bars {
+pub enum Bars<P = f64> {
     // closing value
-    Single(Bar),
+    Single(Bar<P>),
     // closing value and count of empty bars
-    WithEmpty(Bar, Vec<Bar>),
+    WithEmpty(Bar<P>, Vec<Bar<P>>),
+}
+
+impl<P> Bars<P> {
+    /// Splits into the closed bar and the empty bars following it
+    pub fn into_parts(self) -> (Bar<P>, Vec<Bar<P>>) {
+        match self {
+            Bars::Single(bar) => (bar, vec![]),
+            Bars::WithEmpty(bar, empty) => (bar, empty),
+        }
+    }
+}
+
+/// Which end of `bar_start..next_bar_dt` belongs to the bar
+///
+/// With `Closed::Right` a tick exactly at 10:15:00 belongs to the 10:00 M15 bar
+/// and closes it with the next tick. Only the samplers apply it, `bar_start` and
+/// `next_bar_dt` always return left-closed boundaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Closed {
+    #[default]
+    Left,
+    Right,
+}
+
+impl Closed {
+    fn is_past(self, dt: NaiveDateTime, boundary: NaiveDateTime) -> bool {
+        match self {
+            Closed::Left => dt >= boundary,
+            Closed::Right => dt > boundary,
+        }
+    }
+
+    // a point inside the bar `dt` belongs to, boundaries are left-closed
+    fn inside(self, dt: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Closed::Left => dt,
+            Closed::Right => dt - chrono::Duration::nanoseconds(1),
+        }
+    }
 }
-pub trait Sampler: Send {
+
+/// Which time of a bar is its label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Label {
+    #[default]
+    Open,
+    Close,
+}
+
+pub trait Sampler<P: Price = f64>: Send {
     /// Returns Some(price) if period has been passed, None otherwise
     fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime;
 
-    fn next_bar(&mut self, dt: NaiveDateTime, value: f64) -> Option<Bars>;
+    /// Returns the bars closed by the tick
+    ///
+    /// A price refused by `PricePolicy::Reject` is dropped here just as with
+    /// `PricePolicy::Skip`, only `try_next_bar` reports it.
+    fn next_bar(&mut self, dt: NaiveDateTime, value: P) -> Option<Bars<P>>;
+
+    /// Same as `next_bar`, but returns an error if the price is NaN or infinite and
+    /// the policy is `PricePolicy::Reject`
+    ///
+    /// Samplers without a policy take every price through `next_bar` by default.
+    fn try_next_bar(
+        &mut self,
+        dt: NaiveDateTime,
+        value: P,
+    ) -> Result<Option<Bars<P>>, PriceError<P>> {
+        Ok(self.next_bar(dt, value))
+    }
+
+    /// Does nothing by default, for samplers that take every price
+    fn set_policy(&mut self, _policy: PricePolicy) {}
+
+    /// Does nothing by default, for samplers with a fixed closure
+    fn set_closed(&mut self, _closed: Closed) {}
+
+    /// Does nothing by default, for samplers with a fixed alignment
+    fn set_origin(&mut self, _origin: Origin) {}
 
     fn next_bar_dt(&self, dt: NaiveDateTime) -> chrono::NaiveDateTime;
 
-    fn current_incomplete(&self) -> Option<Bar>;
+    fn current_incomplete(&self) -> Option<Bar<P>>;
+}
+
+impl<P: Price, S: Sampler<P> + ?Sized> Sampler<P> for Box<S> {
+    fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime {
+        (**self).bar_start(dt)
+    }
+
+    fn next_bar(&mut self, dt: NaiveDateTime, value: P) -> Option<Bars<P>> {
+        (**self).next_bar(dt, value)
+    }
+
+    fn try_next_bar(
+        &mut self,
+        dt: NaiveDateTime,
+        value: P,
+    ) -> Result<Option<Bars<P>>, PriceError<P>> {
+        (**self).try_next_bar(dt, value)
+    }
+
+    fn set_policy(&mut self, policy: PricePolicy) {
+        (**self).set_policy(policy)
+    }
+
+    fn set_closed(&mut self, closed: Closed) {
+        (**self).set_closed(closed)
+    }
+
+    fn set_origin(&mut self, origin: Origin) {
+        (**self).set_origin(origin)
+    }
+
+    fn next_bar_dt(&self, dt: NaiveDateTime) -> NaiveDateTime {
+        (**self).next_bar_dt(dt)
+    }
+
+    fn current_incomplete(&self) -> Option<Bar<P>> {
+        (**self).current_incomplete()
+    }
 }
 
 macro_rules! sampler {
-    ($name:tt) => {
-        #[derive(Debug)]
-        pub struct $name {
-            state: Option<State>,
+    ($name:tt $(, $field:ident: $ty:ty = $default:expr)*) => {
+        #[derive(Debug, Clone)]
+        pub struct $name<P = f64> {
+            state: Option<State<P>>,
+            policy: PricePolicy,
+            closed: Closed,
+            origin: Origin,
+            $($field: $ty,)*
         }
 
-        impl Default for $name {
+        impl<P> Default for $name<P> {
             fn default() -> Self {
-                Self { state: None }
+                Self {
+                    state: None,
+                    policy: PricePolicy::default(),
+                    closed: Closed::default(),
+                    origin: Origin::default(),
+                    $($field: $default,)*
+                }
+            }
+        }
+
+        impl<P> $name<P> {
+            pub fn with_policy(policy: PricePolicy) -> Self {
+                Self {
+                    policy,
+                    ..Self::default()
+                }
+            }
+
+            pub fn with_closed(closed: Closed) -> Self {
+                Self {
+                    closed,
+                    ..Self::default()
+                }
             }
         }
     };
 }
 
 #[derive(Debug, Clone)]
-struct State {
+struct State<P> {
     bar_start: NaiveDateTime,
     next_bar_dt: NaiveDateTime,
-    open: f64,
-    high: f64,
-    low: f64,
-    close: f64,
+    open: P,
+    high: P,
+    low: P,
+    close: P,
 }
 
-impl State {
+impl<P> State<P> {
     fn new(
         bar_start: NaiveDateTime,
         next_bar_dt: NaiveDateTime,
-        open: f64,
-        high: f64,
-        low: f64,
-        close: f64,
+        open: P,
+        high: P,
+        low: P,
+        close: P,
     ) -> Self {
         Self {
             bar_start,
@@ -99,12 +250,40 @@ impl State {
 
 macro_rules! next {
     () => {
-        fn current_incomplete(&self) -> Option<Bar> {
+        fn current_incomplete(&self) -> Option<Bar<P>> {
             self.state.as_ref().map(Bar::from)
         }
 
-        fn next_bar(&mut self, dt: NaiveDateTime, value: f64) -> Option<Bars> {
-            match self.state {
+        fn set_policy(&mut self, policy: PricePolicy) {
+            self.policy = policy;
+        }
+
+        fn set_closed(&mut self, closed: Closed) {
+            self.closed = closed;
+        }
+
+        fn set_origin(&mut self, origin: Origin) {
+            self.origin = origin;
+        }
+
+        fn next_bar(&mut self, dt: NaiveDateTime, value: P) -> Option<Bars<P>> {
+            self.try_next_bar(dt, value).unwrap_or(None)
+        }
+
+        fn try_next_bar(
+            &mut self,
+            dt: NaiveDateTime,
+            value: P,
+        ) -> Result<Option<Bars<P>>, PriceError<P>> {
+            if !value.is_finite() {
+                match self.policy {
+                    PricePolicy::Reject => return Err(PriceError::NonFinite(value)),
+                    PricePolicy::Skip => return Ok(None),
+                    PricePolicy::Propagate => {}
+                }
+            }
+
+            let bars = match self.state {
                 Some(State {
                     bar_start,
                     next_bar_dt,
@@ -113,7 +292,7 @@ macro_rules! next {
                     low,
                     close,
                 }) => {
-                    if dt >= next_bar_dt {
+                    if self.closed.is_past(dt, next_bar_dt) {
                         let full_bar = Bar {
                             open,
                             high,
@@ -127,7 +306,7 @@ macro_rules! next {
                         let mut empty_bar_end = self.next_bar_dt(next_bar_dt);
 
                         let mut empty_bars = vec![];
-                        while dt >= empty_bar_end {
+                        while self.closed.is_past(dt, empty_bar_end) {
                             empty_bars.push(Bar {
                                 open: close,
                                 high: close,
@@ -155,8 +334,8 @@ macro_rules! next {
                             Some(Bars::Single(full_bar))
                         }
                     } else {
-                        let high = f64::max(value, high);
-                        let low = f64::min(value, low);
+                        let high = propagating_max(value, high);
+                        let low = propagating_min(value, low);
                         let close = value;
 
                         self.state =
@@ -165,9 +344,13 @@ macro_rules! next {
                     }
                 }
                 None => {
-                    let next_bar_dt = self.next_bar_dt(dt);
+                    if self.origin == Origin::FirstTick {
+                        self.origin = Origin::Custom(dt);
+                    }
+                    let inside = self.closed.inside(dt);
+                    let next_bar_dt = self.next_bar_dt(inside);
                     self.state = Some(State::new(
-                        self.bar_start(dt),
+                        self.bar_start(inside),
                         next_bar_dt,
                         value,
                         value,
@@ -176,7 +359,8 @@ macro_rules! next {
                     ));
                     None
                 }
-            }
+            };
+            Ok(bars)
         }
     };
 }
@@ -185,25 +369,17 @@ macro_rules! Minute {
     ($name: ident, $period: expr) => {
         sampler!($name);
 
-        impl Sampler for $name {
+        impl<P: Price> Sampler<P> for $name<P> {
             next!();
 
-            #[allow(clippy::modulo_one)]
             fn next_bar_dt(&self, dt: NaiveDateTime) -> NaiveDateTime {
-                dt.date()
-                    .and_hms(dt.hour(), 0, 0)
-                    .checked_add_signed(chrono::Duration::minutes(
-                        (dt.minute() + ($period - dt.minute() % $period)) as i64,
-                    ))
-                    .unwrap()
+                self.origin
+                    .next_bar_dt(chrono::Duration::minutes($period), dt)
             }
 
             fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime {
-                NaiveDate::from_ymd(dt.year(), dt.month(), dt.day()).and_hms(
-                    dt.hour(),
-                    (dt.minute() / $period) * $period,
-                    0,
-                )
+                self.origin
+                    .bar_start(chrono::Duration::minutes($period), dt)
             }
         }
     };
@@ -213,25 +389,16 @@ macro_rules! Hour {
     ($name: ident, $period: expr) => {
         sampler!($name);
 
-        impl Sampler for $name {
+        impl<P: Price> Sampler<P> for $name<P> {
             next!();
 
-            #[allow(clippy::modulo_one)]
             fn next_bar_dt(&self, dt: NaiveDateTime) -> NaiveDateTime {
-                dt.date()
-                    .and_hms(0, 0, 0)
-                    .checked_add_signed(chrono::Duration::hours(
-                        (dt.hour() + ($period - dt.hour() % $period)) as i64,
-                    ))
-                    .unwrap()
+                self.origin
+                    .next_bar_dt(chrono::Duration::hours($period), dt)
             }
 
             fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime {
-                NaiveDate::from_ymd(dt.year(), dt.month(), dt.day()).and_hms(
-                    (dt.hour() / $period) * $period,
-                    0,
-                    0,
-                )
+                self.origin.bar_start(chrono::Duration::hours($period), dt)
             }
         }
     };
@@ -258,23 +425,20 @@ Hour!(H8, 8);
 Hour!(H12, 12);
 
 sampler!(D1);
-impl Sampler for D1 {
+impl<P: Price> Sampler<P> for D1<P> {
     next!();
 
     fn next_bar_dt(&self, dt: NaiveDateTime) -> NaiveDateTime {
-        dt.date()
-            .and_hms(0, 0, 0)
-            .checked_add_signed(chrono::Duration::days(1))
-            .unwrap()
+        self.origin.next_bar_dt(chrono::Duration::days(1), dt)
     }
 
     fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime {
-        NaiveDate::from_ymd(dt.year(), dt.month(), dt.day()).and_hms(0, 0, 0)
+        self.origin.bar_start(chrono::Duration::days(1), dt)
     }
 }
 
 sampler!(W1);
-impl Sampler for W1 {
+impl<P: Price> Sampler<P> for W1<P> {
     next!();
 
     fn next_bar_dt(&self, dt: NaiveDateTime) -> chrono::NaiveDateTime {
@@ -284,12 +448,15 @@ impl Sampler for W1 {
         dt.date()
             .checked_add_signed(chrono::Duration::days(add))
             .unwrap()
-            .and_hms(0, 0, 0)
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
     }
 
     fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime {
-        NaiveDate::from_ymd(dt.year(), dt.month(), dt.day())
-            .and_hms(0, 0, 0)
+        NaiveDate::from_ymd_opt(dt.year(), dt.month(), dt.day())
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
             .checked_sub_signed(chrono::Duration::days(
                 dt.weekday().number_from_monday() as i64 - 1,
             ))
@@ -297,53 +464,155 @@ impl Sampler for W1 {
     }
 }
 
-sampler!(Mn1);
-impl Sampler for Mn1 {
+macro_rules! Month {
+    ($name: ident, $months: expr) => {
+        sampler!($name, year_start: u32 = 1);
+
+        impl<P> $name<P> {
+            /// Periods aligned to a fiscal year starting on the first day of `month`
+            pub fn with_year_start(month: u32) -> Self {
+                assert!((1..=12).contains(&month), "month must be in 1..=12");
+                Self {
+                    year_start: month,
+                    ..Self::default()
+                }
+            }
+        }
+
+        impl<P: Price> Sampler<P> for $name<P> {
+            next!();
+
+            fn next_bar_dt(&self, dt: NaiveDateTime) -> NaiveDateTime {
+                month_start(dt, $months, self.year_start, 1)
+            }
+
+            fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime {
+                month_start(dt, $months, self.year_start, 0)
+            }
+        }
+    };
+}
+
+// start of the `shift`-th period after the one `dt` is in,
+// periods are `months` long and aligned to `year_start`
+fn month_start(dt: NaiveDateTime, months: i64, year_start: u32, shift: i64) -> NaiveDateTime {
+    let index = dt.year() as i64 * 12 + dt.month0() as i64 - (year_start as i64 - 1);
+    let start = index - index.rem_euclid(months) + months * shift + (year_start as i64 - 1);
+    NaiveDate::from_ymd_opt(
+        start.div_euclid(12) as i32,
+        start.rem_euclid(12) as u32 + 1,
+        1,
+    )
+    .unwrap()
+    .and_hms_opt(0, 0, 0)
+    .unwrap()
+}
+
+Month!(Mn1, 1);
+Month!(Q1, 3);
+Month!(Y1, 12);
+
+/// Sampler for an arbitrary period, e.g. H5 or M7
+#[derive(Debug, Clone)]
+pub struct Uniform<P = f64> {
+    period: chrono::Duration,
+    state: Option<State<P>>,
+    policy: PricePolicy,
+    closed: Closed,
+    origin: Origin,
+}
+
+impl<P> Uniform<P> {
+    pub fn new(period: chrono::Duration) -> Self {
+        assert!(period > chrono::Duration::zero(), "period must be positive");
+        Self {
+            period,
+            state: None,
+            policy: PricePolicy::default(),
+            closed: Closed::default(),
+            origin: Origin::default(),
+        }
+    }
+}
+
+impl<P: Price> Sampler<P> for Uniform<P> {
     next!();
 
-    fn next_bar_dt(&self, dt: NaiveDateTime) -> chrono::NaiveDateTime {
-        let date = dt.date();
-        let date = if date.month() == 12 {
-            // FIXME: bug with B.C.?
-            NaiveDate::from_ymd(date.year() + 1, 1, 1)
-        } else {
-            NaiveDate::from_ymd(date.year(), date.month() + 1, 1)
-        };
-        date.and_hms(0, 0, 0)
+    fn next_bar_dt(&self, dt: NaiveDateTime) -> NaiveDateTime {
+        self.origin.next_bar_dt(self.period, dt)
     }
 
-    // FIXME: fails on 0 year but who cares?
     fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime {
-        NaiveDate::from_ymd(dt.year(), dt.month(), 1).and_hms(0, 0, 0)
+        self.origin.bar_start(self.period, dt)
     }
 }
 
-impl dyn Sampler {
-    pub fn from_short(short: &str) -> Option<Box<dyn Sampler>> {
-        match short {
-            "M1" => Some(Box::new(M1::default())),
-            "M2" => Some(Box::new(M2::default())),
-            "M3" => Some(Box::new(M3::default())),
-            "M4" => Some(Box::new(M4::default())),
-            "M5" => Some(Box::new(M5::default())),
-            "M6" => Some(Box::new(M6::default())),
-            "M10" => Some(Box::new(M10::default())),
-            "M12" => Some(Box::new(M12::default())),
-            "M15" => Some(Box::new(M15::default())),
-            "M20" => Some(Box::new(M20::default())),
-            "M30" => Some(Box::new(M30::default())),
-            "H1" => Some(Box::new(H1::default())),
-            "H2" => Some(Box::new(H2::default())),
-            "H3" => Some(Box::new(H3::default())),
-            "H4" => Some(Box::new(H4::default())),
-            "H6" => Some(Box::new(H6::default())),
-            "H8" => Some(Box::new(H8::default())),
-            "H12" => Some(Box::new(H12::default())),
-            _ => None,
+impl<P: Price> dyn Sampler<P> {
+    pub fn from_short(short: &str) -> Option<Box<dyn Sampler<P>>> {
+        struct Boxed;
+
+        impl<P: Price> FromSampler<P> for Boxed {
+            type Output = Box<dyn Sampler<P>>;
+
+            fn build<S: Sampler<P> + 'static>(self, sampler: S) -> Self::Output {
+                Box::new(sampler)
+            }
         }
+
+        parse_short(short, Boxed)
     }
 }
 
+// what `parse_short` hands the concrete sampler to
+pub(crate) trait FromSampler<P: Price> {
+    type Output;
+
+    fn build<S: Sampler<P> + 'static>(self, sampler: S) -> Self::Output;
+}
+
+// the one table of short names, `M7` and `H5` style names beyond it are `Uniform`
+pub(crate) fn parse_short<P: Price, F: FromSampler<P>>(short: &str, with: F) -> Option<F::Output> {
+    let output = match short {
+        "M1" => with.build(M1::<P>::default()),
+        "M2" => with.build(M2::<P>::default()),
+        "M3" => with.build(M3::<P>::default()),
+        "M4" => with.build(M4::<P>::default()),
+        "M5" => with.build(M5::<P>::default()),
+        "M6" => with.build(M6::<P>::default()),
+        "M10" => with.build(M10::<P>::default()),
+        "M12" => with.build(M12::<P>::default()),
+        "M15" => with.build(M15::<P>::default()),
+        "M20" => with.build(M20::<P>::default()),
+        "M30" => with.build(M30::<P>::default()),
+        "H1" => with.build(H1::<P>::default()),
+        "H2" => with.build(H2::<P>::default()),
+        "H3" => with.build(H3::<P>::default()),
+        "H4" => with.build(H4::<P>::default()),
+        "H6" => with.build(H6::<P>::default()),
+        "H8" => with.build(H8::<P>::default()),
+        "H12" => with.build(H12::<P>::default()),
+        "D1" => with.build(D1::<P>::default()),
+        "W1" => with.build(W1::<P>::default()),
+        "Mn1" => with.build(Mn1::<P>::default()),
+        "Q1" => with.build(Q1::<P>::default()),
+        "Y1" => with.build(Y1::<P>::default()),
+        _ => {
+            // one spelling per period, no sign or leading zeros
+            let digits = short.get(1..)?;
+            if !digits.bytes().all(|b| b.is_ascii_digit()) || digits.starts_with('0') {
+                return None;
+            }
+            let period = match (short.get(..1)?, digits.parse::<i64>()) {
+                ("M", Ok(minutes)) if minutes > 0 => chrono::Duration::try_minutes(minutes)?,
+                ("H", Ok(hours)) if hours > 0 => chrono::Duration::try_hours(hours)?,
+                _ => return None,
+            };
+            with.build(Uniform::<P>::new(period))
+        }
+    };
+    Some(output)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -753,6 +1022,301 @@ mod test {
         assert_eq!(res, None);
     }
 
+    #[test]
+    fn minimal_sampler() {
+        // a sampler written against the original trait
+        struct Daily(Option<Bar>);
+
+        impl Sampler for Daily {
+            fn bar_start(&self, dt: NaiveDateTime) -> NaiveDateTime {
+                dt.date().and_time(NaiveTime::MIN)
+            }
+
+            fn next_bar(&mut self, dt: NaiveDateTime, value: f64) -> Option<Bars> {
+                let bar_start = self.bar_start(dt);
+                let closed = self.0.take().filter(|bar| bar.bar_start != bar_start);
+                self.0 = Some(Bar {
+                    open: value,
+                    high: value,
+                    low: value,
+                    close: value,
+                    bar_start,
+                    next_bar_dt: self.next_bar_dt(dt),
+                });
+                closed.map(Bars::Single)
+            }
+
+            fn next_bar_dt(&self, dt: NaiveDateTime) -> NaiveDateTime {
+                self.bar_start(dt) + chrono::Duration::days(1)
+            }
+
+            fn current_incomplete(&self) -> Option<Bar> {
+                self.0.clone()
+            }
+        }
+
+        let mut sampler = Daily(None);
+        sampler.set_policy(PricePolicy::Skip);
+        assert_eq!(
+            sampler.try_next_bar(date("2015-01-01 10:00:00"), 1.),
+            Ok(None)
+        );
+        assert!(matches!(
+            sampler.try_next_bar(date("2015-01-02 10:00:00"), 2.),
+            Ok(Some(Bars::Single(_)))
+        ));
+    }
+
+    #[test]
+    fn unknown_short_names() {
+        for short in [
+            "",
+            "M",
+            "é5",
+            "5M",
+            "M0",
+            "H-1",
+            "M9223372036854775807",
+            "H9999999999999",
+            "M+5",
+            "M05",
+            "H012",
+        ] {
+            assert!(<dyn Sampler>::from_short(short).is_none(), "{}", short);
+        }
+        assert!(<dyn Sampler>::from_short("M7").is_some());
+    }
+
+    #[test]
+    fn test_right_closed() {
+        let mut sampler = M15::with_closed(Closed::Right);
+        let res = sampler.next_bar(date("2015-01-01 10:15:00"), 0.);
+        assert_eq!(res, None);
+
+        let res = sampler.next_bar(date("2015-01-01 10:15:01"), 1.);
+        assert_eq!(
+            res,
+            Some(Bars::Single(Bar {
+                open: 0.,
+                high: 0.,
+                low: 0.,
+                close: 0.,
+                bar_start: date("2015-01-01 10:00:00"),
+                next_bar_dt: date("2015-01-01 10:15:00")
+            }))
+        );
+
+        // the end of the period still belongs to it
+        let res = sampler.next_bar(date("2015-01-01 10:30:00"), 2.);
+        assert_eq!(res, None);
+
+        let res = sampler.next_bar(date("2015-01-01 11:00:00"), 3.);
+        assert_eq!(
+            res,
+            Some(Bars::WithEmpty(
+                Bar {
+                    open: 1.,
+                    high: 2.,
+                    low: 1.,
+                    close: 2.,
+                    bar_start: date("2015-01-01 10:15:00"),
+                    next_bar_dt: date("2015-01-01 10:30:00")
+                },
+                vec![Bar {
+                    open: 2.,
+                    high: 2.,
+                    low: 2.,
+                    close: 2.,
+                    bar_start: date("2015-01-01 10:30:00"),
+                    next_bar_dt: date("2015-01-01 10:45:00")
+                }]
+            ))
+        );
+        let bar = sampler.current_incomplete().unwrap();
+        assert_eq!(bar.bar_start, date("2015-01-01 10:45:00"));
+        assert_eq!(bar.label(Label::Close), date("2015-01-01 11:00:00"));
+    }
+
+    #[test]
+    fn test_right_closed_first_tick() {
+        let mut sampler = <dyn Sampler>::from_short("H4").unwrap();
+        sampler.set_closed(Closed::Right);
+        sampler.next_bar(date("2015-01-01 00:00:00"), 1.);
+        assert_eq!(
+            sampler
+                .current_incomplete()
+                .map(|bar| bar.label(Label::Open)),
+            Some(date("2014-12-31 20:00:00"))
+        );
+
+        let mut sampler = D1::with_closed(Closed::Right);
+        sampler.next_bar(date("2015-01-03 10:45:02"), 0.);
+        assert_eq!(sampler.next_bar(date("2015-01-04 00:00:00"), 1.), None);
+        assert_eq!(
+            sampler.next_bar(date("2015-01-04 00:00:01"), 2.),
+            Some(Bars::Single(Bar {
+                open: 0.,
+                high: 1.,
+                low: 0.,
+                close: 1.,
+                bar_start: date("2015-01-03 00:00:00"),
+                next_bar_dt: date("2015-01-04 00:00:00")
+            }))
+        );
+    }
+
+    #[test]
+    fn test_mn1_year_rollover() {
+        let mut sampler = Mn1::default();
+        sampler.next_bar(date("2020-12-31 23:59:59"), 1.);
+        assert_eq!(
+            sampler.next_bar(date("2021-01-01 00:00:00"), 2.),
+            Some(Bars::Single(Bar {
+                open: 1.,
+                high: 1.,
+                low: 1.,
+                close: 1.,
+                bar_start: date("2020-12-01 00:00:00"),
+                next_bar_dt: date("2021-01-01 00:00:00")
+            }))
+        );
+
+        // around year 0 and B.C.
+        assert_eq!(
+            sampler.next_bar_dt(
+                NaiveDate::from_ymd_opt(-1, 12, 5)
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap()
+            ),
+            NaiveDate::from_ymd_opt(0, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        );
+        assert_eq!(
+            sampler.bar_start(
+                NaiveDate::from_ymd_opt(0, 2, 5)
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap()
+            ),
+            NaiveDate::from_ymd_opt(0, 2, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_q1() {
+        let mut sampler = Q1::default();
+        sampler.next_bar(date("2020-11-15 10:00:00"), 1.);
+        sampler.next_bar(date("2020-12-31 10:00:00"), 2.);
+
+        let res = sampler.next_bar(date("2021-07-01 00:00:00"), 3.);
+        assert_eq!(
+            res,
+            Some(Bars::WithEmpty(
+                Bar {
+                    open: 1.,
+                    high: 2.,
+                    low: 1.,
+                    close: 2.,
+                    bar_start: date("2020-10-01 00:00:00"),
+                    next_bar_dt: date("2021-01-01 00:00:00")
+                },
+                vec![
+                    Bar {
+                        open: 2.,
+                        high: 2.,
+                        low: 2.,
+                        close: 2.,
+                        bar_start: date("2021-01-01 00:00:00"),
+                        next_bar_dt: date("2021-04-01 00:00:00")
+                    },
+                    Bar {
+                        open: 2.,
+                        high: 2.,
+                        low: 2.,
+                        close: 2.,
+                        bar_start: date("2021-04-01 00:00:00"),
+                        next_bar_dt: date("2021-07-01 00:00:00")
+                    },
+                ]
+            ))
+        );
+
+        // fiscal quarters starting in February
+        let sampler = Q1::<f64>::with_year_start(2);
+        assert_eq!(
+            sampler.bar_start(date("2021-01-31 10:00:00")),
+            date("2020-11-01 00:00:00")
+        );
+        assert_eq!(
+            sampler.next_bar_dt(date("2021-01-31 10:00:00")),
+            date("2021-02-01 00:00:00")
+        );
+    }
+
+    #[test]
+    fn test_y1() {
+        let mut sampler = <dyn Sampler>::from_short("Y1").unwrap();
+        sampler.next_bar(date("2017-06-01 00:00:00"), 1.);
+
+        let res = sampler.next_bar(date("2020-01-01 00:00:00"), 2.);
+        let empty_bar = |year: i32| Bar {
+            open: 1.,
+            high: 1.,
+            low: 1.,
+            close: 1.,
+            bar_start: NaiveDate::from_ymd_opt(year, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            next_bar_dt: NaiveDate::from_ymd_opt(year + 1, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+        };
+        assert_eq!(
+            res,
+            Some(Bars::WithEmpty(
+                empty_bar(2017),
+                vec![empty_bar(2018), empty_bar(2019)]
+            ))
+        );
+    }
+
+    #[test]
+    fn test_fiscal_y1() {
+        // fiscal year from April to March
+        let mut sampler = Y1::with_year_start(4);
+        sampler.next_bar(date("2020-03-31 23:59:59"), 1.);
+        sampler.next_bar(date("2020-04-01 00:00:00"), 2.);
+        assert_eq!(
+            sampler.next_bar(date("2022-04-01 00:00:00"), 3.),
+            Some(Bars::WithEmpty(
+                Bar {
+                    open: 2.,
+                    high: 2.,
+                    low: 2.,
+                    close: 2.,
+                    bar_start: date("2020-04-01 00:00:00"),
+                    next_bar_dt: date("2021-04-01 00:00:00")
+                },
+                vec![Bar {
+                    open: 2.,
+                    high: 2.,
+                    low: 2.,
+                    close: 2.,
+                    bar_start: date("2021-04-01 00:00:00"),
+                    next_bar_dt: date("2022-04-01 00:00:00")
+                }]
+            ))
+        );
+    }
+
     fn date(date_str: &str) -> NaiveDateTime {
         NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
     }
@@ -0,0 +1,344 @@
+use crate::average::TimeWeighted;
+use crate::{Bar, Price, PriceError, PricePolicy, Sampler};
+use chrono::prelude::*;
+use std::marker::PhantomData;
+
+/// Bar of trades with volume and average prices
+#[derive(Debug, PartialEq)]
+pub struct TradeBar<P = f64> {
+    pub bar: Bar<P>,
+    pub volume: f64,
+    /// Volume-weighted average price, the close if the bar has no volume
+    pub vwap: f64,
+    /// Price weighted by how long it was in effect within `bar_start..next_bar_dt`.
+    /// The first bar is weighted from its first trade, no price is in effect before.
+    pub twap: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TradeBars<P = f64> {
+    // closing value
+    Single(TradeBar<P>),
+    // closing value and empty bars
+    WithEmpty(TradeBar<P>, Vec<TradeBar<P>>),
+}
+
+/// Resamples `(dt, price, volume)` trades using the timeframe of the wrapped sampler
+#[derive(Debug, Clone)]
+pub struct TradeSampler<T, P = f64> {
+    sampler: T,
+    averages: Option<Averages>,
+    policy: PricePolicy,
+    price: PhantomData<P>,
+}
+
+impl<P: Price> TradeSampler<Box<dyn Sampler<P>>, P> {
+    pub fn from_short(short: &str) -> Option<Self> {
+        Some(Self::new(<dyn Sampler<P>>::from_short(short)?))
+    }
+}
+
+impl<P: Price, T: Sampler<P>> TradeSampler<T, P> {
+    /// # Panics
+    ///
+    /// If the sampler already has an incomplete bar, its trades would be missing
+    /// from volume and averages
+    pub fn new(mut sampler: T) -> Self {
+        assert!(
+            sampler.current_incomplete().is_none(),
+            "sampler already has an incomplete bar"
+        );
+        // prices are validated before they reach the sampler
+        sampler.set_policy(PricePolicy::Propagate);
+        Self {
+            sampler,
+            averages: None,
+            policy: PricePolicy::default(),
+            price: PhantomData,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: PricePolicy) {
+        self.policy = policy;
+    }
+
+    // for settings other than the policy
+    pub(crate) fn sampler_mut(&mut self) -> &mut T {
+        &mut self.sampler
+    }
+
+    /// Same as `try_next_trade`, but a rejected trade is dropped
+    pub fn next_trade(&mut self, dt: NaiveDateTime, price: P, volume: f64) -> Option<TradeBars<P>> {
+        self.try_next_trade(dt, price, volume).unwrap_or(None)
+    }
+
+    /// Returns Some(bars) if period has been passed, None otherwise.
+    ///
+    /// A negative, NaN or infinite volume is handled by the price policy like a
+    /// non-finite price.
+    pub fn try_next_trade(
+        &mut self,
+        dt: NaiveDateTime,
+        price: P,
+        volume: f64,
+    ) -> Result<Option<TradeBars<P>>, PriceError<P>> {
+        if !price.is_finite() {
+            match self.policy {
+                PricePolicy::Reject => return Err(PriceError::NonFinite(price)),
+                PricePolicy::Skip => return Ok(None),
+                PricePolicy::Propagate => {}
+            }
+        }
+        if !(volume >= 0. && volume.is_finite()) {
+            match self.policy {
+                PricePolicy::Reject => return Err(PriceError::Volume(volume)),
+                PricePolicy::Skip => return Ok(None),
+                PricePolicy::Propagate => {}
+            }
+        }
+
+        let closed = self.sampler.try_next_bar(dt, price)?;
+        let value = price.to_f64();
+
+        let bars = match (closed, self.averages.take()) {
+            (Some(bars), Some(state)) => {
+                let (bar, empty) = bars.into_parts();
+                let bar = state.bar(bar);
+                let empty_bars: Vec<_> =
+                    empty.into_iter().map(|bar| state.empty_bar(bar)).collect();
+
+                // the last price stays in effect from the start of the new bar
+                let bar_start = self
+                    .sampler
+                    .current_incomplete()
+                    .map_or(dt, |bar| bar.bar_start);
+                self.averages = Some(Averages::new(
+                    state.weighted.carry(bar_start, dt, value),
+                    value,
+                    volume,
+                ));
+
+                if empty_bars.is_empty() {
+                    Some(TradeBars::Single(bar))
+                } else {
+                    Some(TradeBars::WithEmpty(bar, empty_bars))
+                }
+            }
+            (None, Some(mut state)) => {
+                state.push(dt, value, volume);
+                self.averages = Some(state);
+                None
+            }
+            (None, None) => {
+                self.averages = Some(Averages::new(TimeWeighted::new(dt, value), value, volume));
+                None
+            }
+            // the sampler starts without a bar, see `new`
+            (Some(_), None) => unreachable!("sampler can't close a bar without trades"),
+        };
+        Ok(bars)
+    }
+
+    /// TWAP of the incomplete bar is computed up to its last trade
+    pub fn current_incomplete(&self) -> Option<TradeBar<P>> {
+        let state = self.averages.as_ref()?;
+        let mut bar = state.bar(self.sampler.current_incomplete()?);
+        bar.twap = state.weighted.average(state.weighted.last_dt());
+        Some(bar)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Averages {
+    volume: f64,
+    // sum of price * volume
+    turnover: f64,
+    weighted: TimeWeighted,
+}
+
+impl Averages {
+    fn new(weighted: TimeWeighted, price: f64, volume: f64) -> Self {
+        Self {
+            volume,
+            turnover: price * volume,
+            weighted,
+        }
+    }
+
+    fn push(&mut self, dt: NaiveDateTime, price: f64, volume: f64) {
+        self.volume += volume;
+        self.turnover += price * volume;
+        self.weighted.push(dt, price);
+    }
+
+    fn bar<P>(&self, bar: Bar<P>) -> TradeBar<P> {
+        TradeBar {
+            volume: self.volume,
+            vwap: if self.volume != 0. {
+                self.turnover / self.volume
+            } else {
+                self.weighted.last
+            },
+            twap: self.weighted.average(bar.next_bar_dt),
+            bar,
+        }
+    }
+
+    fn empty_bar<P>(&self, bar: Bar<P>) -> TradeBar<P> {
+        TradeBar {
+            bar,
+            volume: 0.,
+            vwap: self.weighted.last,
+            twap: self.weighted.last,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn bar<P: Copy>(open: P, high: P, low: P, close: P, start: &str, end: &str) -> Bar<P> {
+        Bar {
+            open,
+            high,
+            low,
+            close,
+            bar_start: date(start),
+            next_bar_dt: date(end),
+        }
+    }
+
+    #[test]
+    fn vwap_and_twap() {
+        let mut sampler = TradeSampler::new(M5::default());
+        assert_eq!(
+            sampler.next_trade(date("2015-01-01 10:01:00"), 10., 1.),
+            None
+        );
+        assert_eq!(
+            sampler.next_trade(date("2015-01-01 10:02:00"), 12., 3.),
+            None
+        );
+        assert_eq!(
+            sampler.next_trade(date("2015-01-01 10:04:00"), 8., 4.),
+            None
+        );
+
+        let res = sampler.next_trade(date("2015-01-01 10:11:00"), 9., 2.);
+        assert_eq!(
+            res,
+            Some(TradeBars::WithEmpty(
+                TradeBar {
+                    bar: bar(
+                        10.,
+                        12.,
+                        8.,
+                        8.,
+                        "2015-01-01 10:00:00",
+                        "2015-01-01 10:05:00"
+                    ),
+                    volume: 8.,
+                    // (10 * 1 + 12 * 3 + 8 * 4) / 8
+                    vwap: 9.75,
+                    // the first bar starts with the first trade:
+                    // 10 for 60s, 12 for 120s, 8 for 60s
+                    twap: 10.5,
+                },
+                vec![TradeBar {
+                    bar: bar(8., 8., 8., 8., "2015-01-01 10:05:00", "2015-01-01 10:10:00"),
+                    volume: 0.,
+                    vwap: 8.,
+                    twap: 8.,
+                }]
+            ))
+        );
+        assert_eq!(
+            sampler.current_incomplete(),
+            Some(TradeBar {
+                bar: bar(9., 9., 9., 9., "2015-01-01 10:10:00", "2015-01-01 10:15:00"),
+                volume: 2.,
+                vwap: 9.,
+                // 8 from 10:10 until the trade at 10:11
+                twap: 8.,
+            })
+        );
+
+        let res = sampler.next_trade(date("2015-01-01 10:15:00"), 7., 1.);
+        assert_eq!(
+            res,
+            Some(TradeBars::Single(TradeBar {
+                bar: bar(9., 9., 9., 9., "2015-01-01 10:10:00", "2015-01-01 10:15:00"),
+                volume: 2.,
+                vwap: 9.,
+                // 8 for 60s, 9 for 240s
+                twap: 8.8,
+            }))
+        );
+    }
+
+    #[test]
+    fn zero_volume_vwap_falls_back_to_close() {
+        let mut sampler = TradeSampler::from_short("H1").unwrap();
+        sampler.next_trade(date("2015-01-01 10:00:00"), 1., 0.);
+        sampler.next_trade(date("2015-01-01 10:30:00"), 3., 0.);
+
+        let bar = sampler.current_incomplete().unwrap();
+        assert_eq!(bar.volume, 0.);
+        assert_eq!(bar.vwap, 3.);
+        assert_eq!(bar.twap, 1.);
+    }
+
+    #[test]
+    fn integer_prices() {
+        let mut sampler = TradeSampler::new(H1::default());
+        sampler.next_trade(date("2015-01-01 10:00:00"), 101i64, 1.);
+        sampler.next_trade(date("2015-01-01 10:45:00"), 105, 3.);
+
+        match sampler.next_trade(date("2015-01-01 11:00:00"), 100, 1.) {
+            Some(TradeBars::Single(bar)) => {
+                assert_eq!(bar.bar.high, 105);
+                assert_eq!(bar.vwap, 104.);
+                assert_eq!(bar.twap, 102.);
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn rejected_trade_is_not_counted() {
+        let mut sampler = TradeSampler::new(M1::default());
+        sampler.next_trade(date("2015-01-01 10:00:00"), 1., 1.);
+        assert!(sampler
+            .try_next_trade(date("2015-01-01 10:00:30"), f64::NAN, 5.)
+            .is_err());
+        assert_eq!(sampler.current_incomplete().unwrap().volume, 1.);
+
+        for volume in [-1., f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                sampler.try_next_trade(date("2015-01-01 10:00:40"), 2., volume),
+                Err(PriceError::Volume(_))
+            ));
+        }
+        sampler.set_policy(PricePolicy::Skip);
+        assert_eq!(
+            sampler.try_next_trade(date("2015-01-01 10:00:50"), 2., -1.),
+            Ok(None)
+        );
+        let bar = sampler.current_incomplete().unwrap();
+        assert_eq!((bar.volume, bar.vwap, bar.bar.close), (1., 1., 1.));
+    }
+
+    #[test]
+    #[should_panic(expected = "sampler already has an incomplete bar")]
+    fn pre_fed_sampler() {
+        let mut sampler = M1::default();
+        sampler.next_bar(date("2015-01-01 10:00:00"), 1.);
+        TradeSampler::new(sampler);
+    }
+}
@@ -0,0 +1,521 @@
+use crate::{Sampler, Tick, TradeBar, TradeBars, TradeSampler};
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// how long the request loop waits before checking whether to stop
+const POLL: Duration = Duration::from_millis(10);
+
+/// Ticks by symbol that `UdfServer` resamples on request
+pub trait TickStore: Send + Sync {
+    fn symbols(&self) -> Vec<String>;
+
+    /// Ticks of `symbol` with `from <= dt < to`, in order
+    fn ticks(&self, symbol: &str, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Tick>;
+}
+
+/// Ticks sorted by time
+impl TickStore for HashMap<String, Vec<Tick>> {
+    fn symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<_> = self.keys().cloned().collect();
+        symbols.sort();
+        symbols
+    }
+
+    fn ticks(&self, symbol: &str, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Tick> {
+        let ticks = match self.get(symbol) {
+            Some(ticks) => ticks,
+            None => return vec![],
+        };
+        let start = ticks.partition_point(|tick| tick.dt < from);
+        let end = ticks.partition_point(|tick| tick.dt < to);
+        ticks[start..end.max(start)].to_vec()
+    }
+}
+
+/// The timeframe of a TradingView resolution, as `Sampler::from_short` takes it
+///
+/// Resolutions are minutes like `1`, `60` or `240`, or days, weeks and months like
+/// `1D`, `W` or `3M`. Minutes stop below a day, `1440` is `D1` and longer
+/// periods go by days.
+pub fn udf_timeframe(resolution: &str) -> Option<String> {
+    let timeframe = match resolution {
+        "D" | "1D" => "D1",
+        "W" | "1W" => "W1",
+        "M" | "1M" => "Mn1",
+        "3M" => "Q1",
+        "12M" => "Y1",
+        minutes => {
+            return match minutes.parse::<u32>().ok()? {
+                0 => None,
+                1440 => Some("D1".to_string()),
+                minutes if minutes > 1440 => None,
+                minutes if minutes % 60 == 0 => Some(format!("H{}", minutes / 60)),
+                minutes => Some(format!("M{}", minutes)),
+            }
+        }
+    };
+    Some(timeframe.to_string())
+}
+
+/// The TradingView resolution of a timeframe, None for `Uniform` ones TradingView
+/// can't express
+pub fn udf_resolution(short: &str) -> Option<String> {
+    let resolution = match short {
+        "D1" => "1D",
+        "W1" => "1W",
+        "Mn1" => "1M",
+        "Q1" => "3M",
+        "Y1" => "12M",
+        _ => {
+            <dyn Sampler>::from_short(short)?;
+            let minutes = match (short.get(..1)?, short[1..].parse::<u32>().ok()?) {
+                ("M", minutes) => minutes,
+                ("H", hours) => hours.checked_mul(60)?,
+                _ => return None,
+            };
+            return Some(minutes)
+                .filter(|minutes| *minutes < 1440)
+                .map(|minutes| minutes.to_string());
+        }
+    };
+    Some(resolution.to_string())
+}
+
+/// Serves a TickStore to TradingView's charting library over its UDF protocol
+///
+/// Answers `/config`, `/symbols?symbol=`, `/time` and
+/// `/history?symbol=&resolution=&from=&to=`, resampling the ticks of each history
+/// request. Periods without ticks are left out, like TradingView expects.
+pub struct UdfServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Udf<S> {
+    store: S,
+    resolutions: Vec<String>,
+    pricescale: u32,
+}
+
+impl UdfServer {
+    /// Serves the `timeframes` of the symbols in `store`, at `pricescale` steps
+    /// per unit of price
+    pub fn bind<A, S>(addr: A, store: S, timeframes: &[&str], pricescale: u32) -> io::Result<Self>
+    where
+        A: ToSocketAddrs,
+        S: TickStore + 'static,
+    {
+        let resolutions = timeframes
+            .iter()
+            .map(|short| {
+                udf_resolution(short)
+                    .filter(|resolution| udf_timeframe(resolution).as_deref() == Some(short))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("no resolution for {}", short),
+                        )
+                    })
+            })
+            .collect::<io::Result<_>>()?;
+        let server = tiny_http::Server::http(addr).map_err(io::Error::other)?;
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| io::Error::other("not an IP address"))?;
+        let udf = Udf {
+            store,
+            resolutions,
+            pricescale,
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if let Ok(Some(request)) = server.recv_timeout(POLL) {
+                        let (status, body) = udf.respond(request.url());
+                        let response = tiny_http::Response::from_string(body)
+                            .with_status_code(status)
+                            .with_header(header("Content-Type", "application/json"))
+                            .with_header(header("Access-Control-Allow-Origin", "*"));
+                        let _ = request.respond(response);
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for UdfServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<S: TickStore> Udf<S> {
+    fn respond(&self, url: &str) -> (u16, String) {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let query: HashMap<_, _> = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (key, percent_decode(value)))
+            .collect();
+        match path {
+            "/config" => (200, self.config()),
+            "/time" => (200, Utc::now().timestamp().to_string()),
+            "/symbols" => match query.get("symbol") {
+                Some(symbol) => self.symbol(symbol),
+                None => (400, error("missing symbol")),
+            },
+            "/history" => self.history(&query),
+            _ => (404, error("not found")),
+        }
+    }
+
+    fn config(&self) -> String {
+        format!(
+            "{{\"supported_resolutions\":{},\"supports_group_request\":false,\
+             \"supports_marks\":false,\"supports_search\":false,\
+             \"supports_timescale_marks\":false,\"supports_time\":true}}",
+            self.resolutions_json()
+        )
+    }
+
+    fn symbol(&self, symbol: &str) -> (u16, String) {
+        if !self.store.symbols().iter().any(|known| known == symbol) {
+            return (404, error("unknown_symbol"));
+        }
+        let mut json = String::from("{\"name\":");
+        push_str(&mut json, symbol);
+        json.push_str(",\"ticker\":");
+        push_str(&mut json, symbol);
+        json.push_str(",\"description\":");
+        push_str(&mut json, symbol);
+        let _ = write!(
+            json,
+            ",\"type\":\"\",\"session\":\"24x7\",\"timezone\":\"Etc/UTC\",\"exchange\":\"\",\
+             \"minmov\":1,\"pricescale\":{},\"has_intraday\":true,\"has_daily\":true,\
+             \"has_weekly_and_monthly\":true,\"supported_resolutions\":{}}}",
+            self.pricescale,
+            self.resolutions_json()
+        );
+        (200, json)
+    }
+
+    fn history(&self, query: &HashMap<&str, String>) -> (u16, String) {
+        let param = |name: &str| query.get(name).ok_or_else(|| format!("missing {}", name));
+        let time = |name: &str| -> Result<NaiveDateTime, String> {
+            param(name)?
+                .parse()
+                .ok()
+                .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+                .map(|dt| dt.naive_utc())
+                .ok_or_else(|| format!("invalid {}", name))
+        };
+        let request = (|| {
+            let symbol = param("symbol")?;
+            let resolution = param("resolution")?;
+            if !self.resolutions.contains(resolution) {
+                return Err(format!("unsupported resolution: {}", resolution));
+            }
+            Ok((
+                symbol,
+                udf_timeframe(resolution).unwrap(),
+                time("from")?,
+                time("to")?,
+            ))
+        })();
+        let (symbol, timeframe, from, to) = match request {
+            Ok(request) => request,
+            Err(err) => return (400, error(&err)),
+        };
+
+        let mut sampler = TradeSampler::from_short(&timeframe).unwrap();
+        let ticks = self.store.ticks(
+            symbol,
+            sampler.sampler_mut().bar_start(from),
+            sampler.sampler_mut().next_bar_dt(to),
+        );
+        let mut bars = vec![];
+        for tick in ticks {
+            match sampler.next_trade(tick.dt, tick.price, tick.volume) {
+                Some(TradeBars::Single(bar)) | Some(TradeBars::WithEmpty(bar, _)) => bars.push(bar),
+                None => {}
+            }
+        }
+        bars.extend(sampler.current_incomplete());
+        // bars overlapping `from..=to`
+        bars.retain(|bar| bar.bar.next_bar_dt > from && bar.bar.bar_start <= to);
+        if bars.is_empty() {
+            return (200, "{\"s\":\"no_data\"}".to_string());
+        }
+
+        let column =
+            |value: fn(&TradeBar) -> String| bars.iter().map(value).collect::<Vec<_>>().join(",");
+        let json = format!(
+            "{{\"s\":\"ok\",\"t\":[{}],\"o\":[{}],\"h\":[{}],\"l\":[{}],\"c\":[{}],\"v\":[{}]}}",
+            column(|bar| bar.bar.bar_start.and_utc().timestamp().to_string()),
+            column(|bar| number(bar.bar.open)),
+            column(|bar| number(bar.bar.high)),
+            column(|bar| number(bar.bar.low)),
+            column(|bar| number(bar.bar.close)),
+            column(|bar| number(bar.volume)),
+        );
+        (200, json)
+    }
+
+    fn resolutions_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, resolution) in self.resolutions.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            push_str(&mut json, resolution);
+        }
+        json.push(']');
+        json
+    }
+}
+
+fn header(name: &str, value: &str) -> tiny_http::Header {
+    tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn error(message: &str) -> String {
+    let mut json = String::from("{\"s\":\"error\",\"errmsg\":");
+    push_str(&mut json, message);
+    json.push('}');
+    json
+}
+
+// JSON has no NaN or infinities
+fn number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+fn push_str(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn get(server: &UdfServer, path: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    fn store() -> HashMap<String, Vec<Tick>> {
+        let tick = |dt: &str, price: f64, volume: f64| Tick {
+            dt: date(dt),
+            price,
+            volume,
+        };
+        let mut store = HashMap::new();
+        store.insert(
+            "EUR/USD".to_string(),
+            vec![
+                tick("2015-01-01 09:59:00", 1.1, 1.),
+                tick("2015-01-01 10:00:10", 1.2, 2.),
+                tick("2015-01-01 10:03:00", 1.4, 1.),
+                tick("2015-01-01 10:04:59", 1.3, 3.),
+                tick("2015-01-01 10:20:00", 1.25, 1.),
+                tick("2015-01-01 10:31:00", 1.5, 1.),
+            ],
+        );
+        store
+    }
+
+    #[test]
+    fn resolutions() {
+        for short in crate::Bar::available_timeframes() {
+            let resolution = udf_resolution(short).unwrap();
+            assert_eq!(udf_timeframe(&resolution).as_deref(), Some(short));
+        }
+        assert_eq!(udf_timeframe("D").as_deref(), Some("D1"));
+        assert_eq!(udf_timeframe("90").as_deref(), Some("M90"));
+        assert_eq!(udf_timeframe("1440").as_deref(), Some("D1"));
+        assert_eq!(udf_timeframe("0"), None);
+        assert_eq!(udf_timeframe("1S"), None);
+        assert_eq!(udf_resolution("H4").as_deref(), Some("240"));
+
+        // no uniform bars of a day or more
+        for resolution in ["2880", "1441", "10080"] {
+            assert_eq!(udf_timeframe(resolution), None, "{}", resolution);
+        }
+        for short in ["M2880", "H24", "H48", "M05"] {
+            assert_eq!(udf_resolution(short), None, "{}", short);
+        }
+    }
+
+    #[test]
+    fn endpoints() {
+        assert!(UdfServer::bind("127.0.0.1:0", store(), &["X1"], 10_000).is_err());
+        assert!(UdfServer::bind("127.0.0.1:0", store(), &["M2880"], 10_000).is_err());
+        let server = UdfServer::bind("127.0.0.1:0", store(), &["M5", "H1", "D1"], 10_000).unwrap();
+
+        let (status, config) = get(&server, "/config");
+        assert_eq!(status, 200);
+        assert!(config.contains("\"supported_resolutions\":[\"5\",\"60\",\"1D\"]"));
+
+        let (status, symbol) = get(&server, "/symbols?symbol=EUR%2FUSD");
+        assert_eq!(status, 200);
+        assert!(symbol.starts_with("{\"name\":\"EUR/USD\""));
+        assert!(symbol.contains("\"pricescale\":10000"));
+        assert_eq!(
+            get(&server, "/symbols?symbol=GBPUSD"),
+            (
+                404,
+                "{\"s\":\"error\",\"errmsg\":\"unknown_symbol\"}".to_string()
+            )
+        );
+
+        let from = date("2015-01-01 10:00:00").and_utc().timestamp();
+        let to = date("2015-01-01 10:25:00").and_utc().timestamp();
+        let (status, history) = get(
+            &server,
+            &format!(
+                "/history?symbol=EUR%2FUSD&resolution=5&from={}&to={}",
+                from, to
+            ),
+        );
+        assert_eq!(status, 200);
+        // 09:55 is before `from`, 10:05 to 10:15 have no ticks and 10:30 is after `to`
+        assert_eq!(
+            history,
+            format!(
+                "{{\"s\":\"ok\",\"t\":[{},{}],\"o\":[1.2,1.25],\"h\":[1.4,1.25],\
+                 \"l\":[1.2,1.25],\"c\":[1.3,1.25],\"v\":[6,1]}}",
+                from,
+                from + 20 * 60
+            )
+        );
+
+        let (_, history) = get(
+            &server,
+            &format!(
+                "/history?symbol=EUR%2FUSD&resolution=1D&from={}&to={}",
+                from, to
+            ),
+        );
+        assert!(history.contains("\"o\":[1.1],\"h\":[1.5]"));
+        let (_, history) = get(
+            &server,
+            &format!(
+                "/history?symbol=EUR%2FUSD&resolution=5&from={}&to={}",
+                to + 3600,
+                to + 7200
+            ),
+        );
+        assert_eq!(history, "{\"s\":\"no_data\"}");
+    }
+
+    #[test]
+    fn bad_requests() {
+        let server = UdfServer::bind("127.0.0.1:0", store(), &["M5"], 100).unwrap();
+        assert_eq!(
+            get(
+                &server,
+                "/history?symbol=EUR%2FUSD&resolution=60&from=0&to=1"
+            ),
+            (
+                400,
+                "{\"s\":\"error\",\"errmsg\":\"unsupported resolution: 60\"}".to_string()
+            )
+        );
+        assert_eq!(
+            get(
+                &server,
+                "/history?symbol=EUR%2FUSD&resolution=5&from=x&to=1"
+            )
+            .1,
+            "{\"s\":\"error\",\"errmsg\":\"invalid from\"}"
+        );
+        assert_eq!(get(&server, "/symbols").0, 400);
+        assert_eq!(get(&server, "/marks").0, 404);
+        let (status, time) = get(&server, "/time");
+        assert_eq!(status, 200);
+        assert!(time.parse::<i64>().unwrap() > 1_500_000_000);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{Bar, Bars, Price};
+use std::fmt;
+
+/// What a sampler does with a price that is NaN or infinite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PricePolicy {
+    /// Refuse the tick: `try_next_bar` returns an error and the state is untouched,
+    /// `next_bar` drops the tick as with `Skip`
+    #[default]
+    Reject,
+    /// Silently drop the tick
+    Skip,
+    /// Accept the tick: a NaN makes the high and low of its bar NaN, and the open
+    /// or close when it is the first or last price of the bar
+    Propagate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceError<P = f64> {
+    NonFinite(P),
+    /// A trade volume that is negative, NaN or infinite
+    Volume(f64),
+}
+
+impl<P: Price> fmt::Display for PriceError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PriceError::NonFinite(value) => write!(f, "non-finite price: {:?}", value),
+            PriceError::Volume(volume) => write!(f, "invalid volume: {}", volume),
+        }
+    }
+}
+
+impl<P: Price> std::error::Error for PriceError<P> {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantError {
+    /// `low <= open <= high` does not hold
+    Open,
+    /// `low <= close <= high` does not hold
+    Close,
+    /// `bar_start < next_bar_dt` does not hold
+    Period,
+    /// An empty bar doesn't start where the previous one ends
+    Gap,
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantError::Open => write!(f, "open is outside of low..=high"),
+            InvariantError::Close => write!(f, "close is outside of low..=high"),
+            InvariantError::Period => write!(f, "bar_start is not before next_bar_dt"),
+            InvariantError::Gap => write!(f, "bars are not contiguous"),
+        }
+    }
+}
+
+impl std::error::Error for InvariantError {}
+
+impl<P: Price> Bar<P> {
+    /// Verifies that `low <= open, close <= high` and that the bar spans a non-empty period.
+    /// A bar with a NaN price never passes.
+    pub fn check(&self) -> Result<(), InvariantError> {
+        if !(self.low <= self.open && self.open <= self.high) {
+            return Err(InvariantError::Open);
+        }
+        if !(self.low <= self.close && self.close <= self.high) {
+            return Err(InvariantError::Close);
+        }
+        if self.bar_start >= self.next_bar_dt {
+            return Err(InvariantError::Period);
+        }
+        Ok(())
+    }
+}
+
+impl<P: Price> Bars<P> {
+    /// Checks every bar and that the empty bars follow the closed one without gaps
+    pub fn check(&self) -> Result<(), InvariantError> {
+        match self {
+            Bars::Single(bar) => bar.check(),
+            Bars::WithEmpty(bar, empty) => {
+                bar.check()?;
+                let mut prev_end = bar.next_bar_dt;
+                for empty_bar in empty {
+                    empty_bar.check()?;
+                    if empty_bar.bar_start != prev_end {
+                        return Err(InvariantError::Gap);
+                    }
+                    prev_end = empty_bar.next_bar_dt;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub(crate) fn propagating_max<P: Price>(a: P, b: P) -> P {
+    if b.is_nan() || b > a {
+        b
+    } else {
+        a
+    }
+}
+
+pub(crate) fn propagating_min<P: Price>(a: P, b: P) -> P {
+    if b.is_nan() || b < a {
+        b
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use chrono::prelude::*;
+    use proptest::prelude::*;
+
+    fn all_samplers() -> Vec<Box<dyn Sampler>> {
+        Bar::available_timeframes()
+            .into_iter()
+            .map(|short| <dyn Sampler>::from_short(short).unwrap())
+            .collect()
+    }
+
+    fn price() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            8 => -1e6..1e6f64,
+            1 => Just(f64::NAN),
+            1 => Just(f64::INFINITY),
+            1 => Just(f64::NEG_INFINITY),
+        ]
+    }
+
+    // ticks as (seconds since previous tick, price)
+    fn ticks() -> impl Strategy<Value = Vec<(i64, f64)>> {
+        prop::collection::vec((0..20_000i64, price()), 1..50)
+    }
+
+    fn feed(
+        sampler: &mut dyn Sampler,
+        ticks: &[(i64, f64)],
+    ) -> Vec<Result<Option<Bars>, PriceError>> {
+        let mut dt = NaiveDate::from_ymd_opt(2020, 12, 30)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        ticks
+            .iter()
+            .map(|(step, value)| {
+                dt += chrono::Duration::seconds(*step);
+                sampler.try_next_bar(dt, *value)
+            })
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn reject_keeps_bars_consistent(ticks in ticks()) {
+            for mut sampler in all_samplers() {
+                sampler.set_policy(PricePolicy::Reject);
+                for (res, (_, value)) in feed(sampler.as_mut(), &ticks).into_iter().zip(&ticks) {
+                    match res {
+                        Ok(Some(bars)) => prop_assert_eq!(bars.check(), Ok(())),
+                        Ok(None) => prop_assert!(value.is_finite()),
+                        Err(err) => {
+                            prop_assert!(!value.is_finite());
+                            prop_assert!(matches!(err, PriceError::NonFinite(_)));
+                        }
+                    }
+                    if let Some(bar) = sampler.current_incomplete() {
+                        prop_assert_eq!(bar.check(), Ok(()));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn skip_keeps_bars_consistent(ticks in ticks()) {
+            for mut sampler in all_samplers() {
+                sampler.set_policy(PricePolicy::Skip);
+                for res in feed(sampler.as_mut(), &ticks) {
+                    prop_assert!(res.is_ok());
+                    if let Ok(Some(bars)) = res {
+                        prop_assert_eq!(bars.check(), Ok(()));
+                    }
+                    if let Some(bar) = sampler.current_incomplete() {
+                        prop_assert_eq!(bar.check(), Ok(()));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn propagate_poisons_high_and_low(ticks in ticks()) {
+            for mut sampler in all_samplers() {
+                sampler.set_policy(PricePolicy::Propagate);
+                for res in feed(sampler.as_mut(), &ticks) {
+                    prop_assert!(res.is_ok());
+                    if let Ok(Some(Bars::Single(bar))) | Ok(Some(Bars::WithEmpty(bar, _))) = res {
+                        if bar.check().is_err() {
+                            prop_assert!(bar.high.is_nan() && bar.low.is_nan());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nan_is_rejected_by_default() {
+        let mut sampler = M5::default();
+        let dt = NaiveDate::from_ymd_opt(2015, 1, 1)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        assert_eq!(sampler.next_bar(dt, 1.), None);
+        assert_eq!(
+            sampler.try_next_bar(dt, f64::NAN).unwrap_err().to_string(),
+            "non-finite price: NaN"
+        );
+        assert_eq!(sampler.next_bar(dt, f64::INFINITY), None);
+        assert_eq!(sampler.current_incomplete().unwrap().close, 1.);
+    }
+
+    #[test]
+    fn nan_propagates_to_high_and_low() {
+        let mut sampler = M5::with_policy(PricePolicy::Propagate);
+        let dt = NaiveDate::from_ymd_opt(2015, 1, 1)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        sampler.next_bar(dt, 1.);
+        sampler.next_bar(dt, f64::NAN);
+        sampler.next_bar(dt, 2.);
+
+        let bar = sampler.current_incomplete().unwrap();
+        assert_eq!(bar.open, 1.);
+        assert!(bar.high.is_nan());
+        assert!(bar.low.is_nan());
+        assert_eq!(bar.close, 2.);
+        assert_eq!(bar.check(), Err(InvariantError::Open));
+    }
+
+    #[test]
+    fn check_detects_gaps() {
+        let dt = |h| {
+            NaiveDate::from_ymd_opt(2015, 1, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        let bar = |start, end| Bar {
+            open: 1.,
+            high: 1.,
+            low: 1.,
+            close: 1.,
+            bar_start: dt(start),
+            next_bar_dt: dt(end),
+        };
+        assert_eq!(
+            Bars::WithEmpty(bar(1, 2), vec![bar(2, 3), bar(3, 4)]).check(),
+            Ok(())
+        );
+        assert_eq!(
+            Bars::WithEmpty(bar(1, 2), vec![bar(3, 4)]).check(),
+            Err(InvariantError::Gap)
+        );
+        assert_eq!(Bars::Single(bar(2, 2)).check(), Err(InvariantError::Period));
+    }
+}
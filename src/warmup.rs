@@ -0,0 +1,253 @@
+use crate::{Bar, Bars, Price, PriceError, Sampler};
+use chrono::prelude::*;
+use std::marker::PhantomData;
+
+/// Primes a sampler with history, then feeds it live ticks without counting the
+/// overlap twice
+///
+/// Live ticks are matched against the newest history by sequence number when both
+/// have one, by timestamp otherwise: a live tick older than the newest historical
+/// one is a duplicate, and so are as many live ticks at the newest historical
+/// timestamp as history had there.
+#[derive(Debug, Clone)]
+pub struct WarmUp<S, P = f64> {
+    sampler: S,
+    sequence: Option<u64>,
+    // live ticks before `until` are duplicates, and the first `at_until` at it
+    until: Option<NaiveDateTime>,
+    at_until: usize,
+    skipped_at_until: usize,
+    duplicates: u64,
+    price: PhantomData<P>,
+}
+
+impl<P: Price, S: Sampler<P>> WarmUp<S, P> {
+    pub fn new(sampler: S) -> Self {
+        Self {
+            sampler,
+            sequence: None,
+            until: None,
+            at_until: 0,
+            skipped_at_until: 0,
+            duplicates: 0,
+            price: PhantomData,
+        }
+    }
+
+    /// Feeds a historical tick, ticks must come in order and before the live ones.
+    /// Returns the bars it closes, which callers usually have already.
+    pub fn history(
+        &mut self,
+        dt: NaiveDateTime,
+        value: P,
+        sequence: Option<u64>,
+    ) -> Result<Option<Bars<P>>, PriceError<P>> {
+        let bars = self.sampler.try_next_bar(dt, value)?;
+        if sequence.is_some() {
+            self.sequence = sequence.max(self.sequence);
+        }
+        if self.until == Some(dt) {
+            self.at_until += 1;
+        } else {
+            self.until = Some(dt);
+            self.at_until = 1;
+        }
+        Ok(bars)
+    }
+
+    /// Feeds historical bars of the sampler's timeframe or a finer one, built from
+    /// the ticks before `until`, the last one possibly incomplete
+    ///
+    /// Each bar counts as its open, high, low and close ticked in the middle of
+    /// its period, so finer bars merge into the sampler's whatever its closure,
+    /// unless a tick fell exactly on one of the sampler's bar boundaries and the
+    /// finer bars were closed on the other side.
+    pub fn history_bars<I>(
+        &mut self,
+        bars: I,
+        until: NaiveDateTime,
+    ) -> Result<Vec<Bars<P>>, PriceError<P>>
+    where
+        I: IntoIterator<Item = Bar<P>>,
+    {
+        let mut closed = vec![];
+        for bar in bars {
+            let dt = bar.bar_start + (bar.next_bar_dt - bar.bar_start) / 2;
+            for value in [bar.open, bar.high, bar.low, bar.close] {
+                closed.extend(self.sampler.try_next_bar(dt, value)?);
+            }
+        }
+        self.until = Some(until);
+        self.at_until = 0;
+        Ok(closed)
+    }
+
+    /// Feeds a live tick, or drops it if history already had it
+    pub fn live(
+        &mut self,
+        dt: NaiveDateTime,
+        value: P,
+        sequence: Option<u64>,
+    ) -> Result<Option<Bars<P>>, PriceError<P>> {
+        if self.is_duplicate(dt, sequence) {
+            self.duplicates += 1;
+            return Ok(None);
+        }
+        self.sampler.try_next_bar(dt, value)
+    }
+
+    /// Live ticks dropped so far
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    pub fn current_incomplete(&self) -> Option<Bar<P>> {
+        self.sampler.current_incomplete()
+    }
+
+    pub fn sampler(&self) -> &S {
+        &self.sampler
+    }
+
+    /// The primed sampler, for when the overlap is over
+    pub fn into_inner(self) -> S {
+        self.sampler
+    }
+
+    fn is_duplicate(&mut self, dt: NaiveDateTime, sequence: Option<u64>) -> bool {
+        if let (Some(sequence), Some(last)) = (sequence, self.sequence) {
+            return sequence <= last;
+        }
+        match self.until {
+            Some(until) if dt < until => true,
+            Some(until) if dt == until && self.skipped_at_until < self.at_until => {
+                self.skipped_at_until += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use chrono::Duration;
+
+    fn date(date_str: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    // ticks with repeated timestamps
+    fn ticks() -> Vec<(NaiveDateTime, f64)> {
+        (0..3_000i64)
+            .map(|i| {
+                let dt = date("2015-01-01 09:00:00") + Duration::seconds(i / 3 * 7);
+                (dt, ((i * 37) % 101) as f64)
+            })
+            .collect()
+    }
+
+    fn run<S: Sampler>(mut sampler: S, ticks: &[(NaiveDateTime, f64)]) -> (Vec<Bars>, S) {
+        let bars = ticks
+            .iter()
+            .filter_map(|(dt, value)| sampler.next_bar(*dt, *value))
+            .collect();
+        (bars, sampler)
+    }
+
+    #[test]
+    fn overlap_by_timestamp() {
+        let ticks = ticks();
+        let (all, expected) = run(M15::default(), &ticks);
+
+        // history ends in the middle of a group of ticks sharing a timestamp,
+        // live starts well before it
+        let mut warm_up = WarmUp::new(M15::default());
+        let mut bars = vec![];
+        for (dt, value) in &ticks[..2_000] {
+            bars.extend(warm_up.history(*dt, *value, None).unwrap());
+        }
+        for (dt, value) in &ticks[1_500..] {
+            bars.extend(warm_up.live(*dt, *value, None).unwrap());
+        }
+        assert_eq!(warm_up.duplicates(), 500);
+        assert_eq!(bars, all);
+        assert_eq!(warm_up.current_incomplete(), expected.current_incomplete());
+    }
+
+    #[test]
+    fn overlap_by_sequence() {
+        let ticks = ticks();
+        let (all, expected) = run(H1::with_closed(Closed::Right), &ticks);
+
+        let mut warm_up = WarmUp::new(H1::with_closed(Closed::Right));
+        let mut bars = vec![];
+        for (i, (dt, value)) in ticks[..1_000].iter().enumerate() {
+            bars.extend(warm_up.history(*dt, *value, Some(i as u64)).unwrap());
+        }
+        for (i, (dt, value)) in ticks.iter().enumerate().skip(990) {
+            bars.extend(warm_up.live(*dt, *value, Some(i as u64)).unwrap());
+        }
+        assert_eq!(warm_up.duplicates(), 10);
+        assert_eq!(bars, all);
+        assert_eq!(
+            warm_up.into_inner().current_incomplete(),
+            expected.current_incomplete()
+        );
+    }
+
+    #[test]
+    fn primed_with_finer_bars() {
+        let ticks = ticks();
+        let split = 2_001;
+        let until = ticks[split].0;
+
+        // M1 bars of the history, the last one incomplete
+        let (m1, m1_sampler) = run(M1::default(), &ticks[..split]);
+        let mut history: Vec<Bar> = m1
+            .into_iter()
+            .flat_map(|bars| {
+                let (bar, empty) = bars.into_parts();
+                Some(bar).into_iter().chain(empty)
+            })
+            .collect();
+        history.extend(m1_sampler.current_incomplete());
+
+        for closed in [Closed::Left, Closed::Right] {
+            let mut warm_up = WarmUp::new(H1::with_closed(closed));
+            let closed_bars = warm_up.history_bars(history.clone(), until).unwrap();
+            assert!(!closed_bars.is_empty());
+            for (dt, value) in &ticks[1_990..] {
+                warm_up.live(*dt, *value, None).unwrap();
+            }
+            assert_eq!(warm_up.duplicates(), 11);
+            let (_, expected) = run(H1::with_closed(closed), &ticks);
+            assert_eq!(warm_up.current_incomplete(), expected.current_incomplete());
+        }
+    }
+
+    #[test]
+    fn same_timeframe_bars() {
+        let ticks = ticks();
+        let (all, expected) = run(M5::default(), &ticks);
+        let mut history: Vec<Bar> = all.into_iter().map(|bars| bars.into_parts().0).collect();
+        history.extend(expected.current_incomplete());
+
+        let mut warm_up = WarmUp::new(M5::default());
+        let mut closed = warm_up
+            .history_bars(history, ticks[ticks.len() - 1].0 + Duration::seconds(1))
+            .unwrap();
+        assert_eq!(
+            closed.pop().map(|bars| bars.into_parts().0),
+            run(M5::default(), &ticks)
+                .0
+                .pop()
+                .map(|bars| bars.into_parts().0)
+        );
+        assert_eq!(warm_up.current_incomplete(), expected.current_incomplete());
+        assert_eq!(warm_up.live(ticks[0].0, 1., None), Ok(None));
+        assert_eq!(warm_up.duplicates(), 1);
+    }
+}
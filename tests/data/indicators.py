@@ -0,0 +1,152 @@
+"""Writes indicators.csv, run with Python 3.11 and only the standard library:
+
+    python3 indicators.py > indicators.csv
+
+Bars are a seeded random walk. The indicator columns follow the same definitions
+as src/indicators.rs, started the way TA-Lib starts them: SMA-seeded EMAs,
+Wilder's RSI and ATR from the mean of the first `n` changes and true ranges,
+Bollinger bands on the population deviation, the MACD signal as the EMA of the
+MACD line. The file guards against regressions, it is not an outside reference.
+"""
+
+import datetime
+import math
+
+PERIODS = 60
+
+
+class Random:
+    """The C library's linear congruential generator"""
+
+    def __init__(self, seed):
+        self.seed = seed
+
+    def next(self):
+        self.seed = (self.seed * 1103515245 + 12345) % 2**31
+        return self.seed / 2**31
+
+
+def random_walk(periods):
+    random = Random(12345)
+    bars = []
+    close = 1.2
+    day = datetime.datetime(2015, 1, 5)
+    for _ in range(periods):
+        open_ = round(close + (random.next() - 0.5) * 0.002, 5)
+        close = round(open_ + (random.next() - 0.5) * 0.02, 5)
+        high = round(max(open_, close) + random.next() * 0.006, 5)
+        low = round(min(open_, close) - random.next() * 0.006, 5)
+        bars.append((day, open_, high, low, close))
+        day += datetime.timedelta(days=1)
+    return bars
+
+
+def sma(values, n):
+    return [
+        sum(values[i - n + 1 : i + 1]) / n if i >= n - 1 else None
+        for i in range(len(values))
+    ]
+
+
+def ema(values, n):
+    out = [None] * len(values)
+    k = 2 / (n + 1)
+    first = next(i for i, value in enumerate(values) if value is not None)
+    seed = first + n - 1
+    if seed >= len(values):
+        return out
+    out[seed] = sum(values[first : seed + 1]) / n
+    for i in range(seed + 1, len(values)):
+        out[i] = out[i - 1] + (values[i] - out[i - 1]) * k
+    return out
+
+
+def rsi_value(gain, loss):
+    if loss:
+        return 100 - 100 / (1 + gain / loss)
+    if gain:
+        return 100.0
+    return 50.0
+
+
+def rsi(closes, n):
+    out = [None] * len(closes)
+    changes = [None] + [closes[i] - closes[i - 1] for i in range(1, len(closes))]
+    gain = sum(max(change, 0) for change in changes[1 : n + 1]) / n
+    loss = sum(max(-change, 0) for change in changes[1 : n + 1]) / n
+    out[n] = rsi_value(gain, loss)
+    for i in range(n + 1, len(closes)):
+        gain = (gain * (n - 1) + max(changes[i], 0)) / n
+        loss = (loss * (n - 1) + max(-changes[i], 0)) / n
+        out[i] = rsi_value(gain, loss)
+    return out
+
+
+def atr(highs, lows, closes, n):
+    true_ranges = [None] + [
+        max(
+            highs[i] - lows[i],
+            abs(highs[i] - closes[i - 1]),
+            abs(lows[i] - closes[i - 1]),
+        )
+        for i in range(1, len(closes))
+    ]
+    out = [None] * len(closes)
+    average = sum(true_ranges[1 : n + 1]) / n
+    out[n] = average
+    for i in range(n + 1, len(closes)):
+        average = (average * (n - 1) + true_ranges[i]) / n
+        out[i] = average
+    return out
+
+
+def bollinger(closes, n, k):
+    middle = sma(closes, n)
+    lower = [None] * len(closes)
+    upper = [None] * len(closes)
+    for i in range(n - 1, len(closes)):
+        window = closes[i - n + 1 : i + 1]
+        deviation = math.sqrt(sum((value - middle[i]) ** 2 for value in window) / n)
+        lower[i] = middle[i] - k * deviation
+        upper[i] = middle[i] + k * deviation
+    return lower, middle, upper
+
+
+def macd(closes, fast, slow, signal):
+    line = [
+        f - s if f is not None and s is not None else None
+        for f, s in zip(ema(closes, fast), ema(closes, slow))
+    ]
+    signal_line = ema(line, signal)
+    histogram = [m - s if s is not None else None for m, s in zip(line, signal_line)]
+    line = [m if s is not None else None for m, s in zip(line, signal_line)]
+    return line, signal_line, histogram
+
+
+def main():
+    bars = random_walk(PERIODS)
+    highs = [bar[2] for bar in bars]
+    lows = [bar[3] for bar in bars]
+    closes = [bar[4] for bar in bars]
+    columns = [
+        sma(closes, 5),
+        ema(closes, 10),
+        rsi(closes, 14),
+        atr(highs, lows, closes, 14),
+        *bollinger(closes, 20, 2),
+        *macd(closes, 12, 26, 9),
+    ]
+
+    print(
+        "date,open,high,low,close,sma5,ema10,rsi14,atr14,"
+        "bb_lower,bb_middle,bb_upper,macd,macd_signal,macd_hist"
+    )
+    for i, bar in enumerate(bars):
+        fields = [bar[0].strftime("%Y-%m-%d")]
+        fields += ["%.5f" % value for value in bar[1:]]
+        fields += ["" if column[i] is None else repr(column[i]) for column in columns]
+        print(",".join(fields))
+
+
+if __name__ == "__main__":
+    main()